@@ -2,5 +2,6 @@
 //#![deny(missing_docs)]
 
 pub mod server;
+pub mod websocket;
 
 pub use crate::server::*;
@@ -1,6 +1,7 @@
 //! Everything related to the actual server implementation
 
 use ::hope::config::Config;
+use ::hope::protocol::model::{hopeK, hopeSP};
 use actix::{prelude::*, SystemRunner};
 use actix_cors::Cors;
 use actix_files::Files;
@@ -21,6 +22,7 @@ use std::{
 use url::Url;
 use hope_library::scheme::hope;
 use hope_library::websocket::HopeWebSocket;
+use crate::websocket::HopeSession;
 use std::time::{Duration, Instant};
 use actix::prelude::*;
 use actix_files as fs;
@@ -40,12 +42,19 @@ async fn p404() -> Result<fs::NamedFile, Error> {
     Ok(fs::NamedFile::open([HTML_FOLDER, FILE_NOTFOUND].concat())?.set_status_code(StatusCode::NOT_FOUND))
 }
 
-/// do websocket handshake and start `MyWebSocket` actor
-async fn ws_index(r: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
+/// Parameters and key shared by all sessions, created once at startup
+#[derive(Clone)]
+pub struct SchemeState {
+    pub sp: hopeSP,
+    pub key: hopeK,
+}
+
+/// do websocket handshake and start `HopeSession` actor, which keeps the
+/// scheme and its channel to the client for the whole connection
+async fn ws_index(r: HttpRequest, stream: web::Payload, state: web::Data<SchemeState>) -> Result<HttpResponse, Error> {
     println!("{:?}", r);
-    let wsh = HopeWebSocket::new();
-    let _hope: hope = hope::new(String::from("local"), &wsh);
-    let res = ws::start(wsh, &r, stream);
+    let (wsh, end) = HopeWebSocket::connect();
+    let res = ws::start(HopeSession::new(end, wsh, state.sp.clone(), state.key.clone()), &r, stream);
     //let res = HopeWebSocket::create(|ctx: &mut Context<HopeWebSocket>| HopeWebSocket { hb: Instant::now() });
     println!("{:?}", res);
     res
@@ -66,6 +75,12 @@ impl Server {
         // Build a new actor system
         let runner = actix::System::new("backend");
 
+        // Generate the parameters and the key before serving, not per handshake
+        let state = web::Data::new(SchemeState {
+            sp: hopeSP::new(String::from("local")),
+            key: hope::keygen_with(&config.keygen).map_err(|e| format_err!("{}", e))?,
+        });
+
         // Create the server
         let server = HttpServer::new(move || {
             App::new()
@@ -77,6 +92,7 @@ impl Server {
                         .finish(),
                 )
 	            .wrap(middleware::Logger::default())
+	            .register_data(state.clone())
 	            //global
 	            .data(web::JsonConfig::default().limit(1024 * 1024)) // <- limit size of the payload (global configuration)
 	            // websocket route
@@ -1,4 +1,4 @@
-use hope::protocol::*;
+use ::hope::protocol::*;
 
 use std::time::{Duration, Instant};
 use actix::prelude::*;
@@ -6,29 +6,46 @@ use actix_files as fs;
 use actix_web::{guard, middleware, web, App, Error, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
 use actix_web::http::{header, Method, StatusCode};
+use ::hope::protocol::model::{hopeK, hopeSP};
+use hope_library::scheme::hope;
+use hope_library::websocket::{HopeClientEnd, HopeWebSocket};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
 /// How often heartbeat pings are sent
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 /// How long before lack of client response causes a timeout
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(15);
+/// How often pending protocol requests are forwarded to the client
+const FORWARD_INTERVAL: Duration = Duration::from_millis(10);
+/// Operation on the scheme of a session
+pub type SchemeJob = Box<dyn FnOnce(&mut hope<'_>) + Send>;
+
 /// websocket connection is long running connection, it easier
-/// to handle with an actor
-pub struct HopeWebSocket {
+/// to handle with an actor. It carries the protocol messages of a
+/// `HopeWebSocket` between the scheme and the browser.
+pub struct HopeSession {
     /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT),
     /// otherwise we drop connection.
     pub hb: Instant,
+    /// client end of the protocol channel
+    pub end: HopeClientEnd,
+    /// queue of the thread that owns the scheme and the server end of the
+    /// channel, it stops once the session is dropped
+    pub jobs: Sender<SchemeJob>,
 }
 
-impl Actor for HopeWebSocket {
+impl Actor for HopeSession {
     type Context = ws::WebsocketContext<Self>;
 
     /// Method is called on actor start. We start the heartbeat process here.
     fn started(&mut self, ctx: &mut Self::Context) {
         self.hb(ctx);
+        self.forward(ctx);
     }
 }
 
 /// Handler for `ws::Message`
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for HopeWebSocket {
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for HopeSession {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         // process websocket messages
         println!("WS: {:?}", msg);
@@ -42,7 +59,8 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for HopeWebSocket {
             }
             Ok(ws::Message::Text(text)) => {
                 println!("WS received Text: {:?}", text);
-                ctx.text(text);
+                // responses to protocol requests
+                let _ = self.end.incoming.send(text.to_string());
             }
             Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
             Ok(ws::Message::Close(_)) => {
@@ -53,9 +71,28 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for HopeWebSocket {
     }
 }
 
-impl HopeWebSocket {
-    pub fn new() -> Self {
-        Self { hb: Instant::now() }
+impl HopeSession {
+    /// Session for the channel ws/end. The scheme over ws lives on its own
+    /// thread as long as the session, because its client-assisted
+    /// operations block until this actor delivers the answer.
+    pub fn new(end: HopeClientEnd, ws: HopeWebSocket, sp: hopeSP, key: hopeK) -> Self {
+        let (jobs, queue) = channel::<SchemeJob>();
+        thread::spawn(move || {
+            let mut _hope: hope = hope::with_key(sp, &ws, key);
+            for job in queue {
+                job(&mut _hope);
+            }
+        });
+        Self {
+            hb: Instant::now(),
+            end: end,
+            jobs: jobs,
+        }
+    }
+
+    /// Queues job on the scheme of this session, false if its thread is gone
+    pub fn run(&self, job: SchemeJob) -> bool {
+        self.jobs.send(job).is_ok()
     }
 
     /// helper method that sends ping to client every second.
@@ -79,10 +116,12 @@ impl HopeWebSocket {
         });
     }
 
-    /// helper method that sends a request to the client
-    ///
-    /// also this method checks heartbeats from client
-    pub fn req(&self, ctx: &mut <Self as Actor>::Context) {
-        ctx.text(String::from("test"));
+    /// helper method that forwards protocol requests to the client
+    pub fn forward(&self, ctx: &mut <Self as Actor>::Context) {
+        ctx.run_interval(FORWARD_INTERVAL, |act, ctx| {
+            while let Ok(req) = act.end.outgoing.try_recv() {
+                ctx.text(req);
+            }
+        });
     }
 }
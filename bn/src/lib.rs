@@ -4,6 +4,7 @@ extern crate serde_derive;
 extern crate serde;
 extern crate byteorder;
 extern crate getrandom;
+extern crate rand_core;
 
 mod arith;
 mod fields;
@@ -15,6 +16,7 @@ use groups::GroupElement;
 use std::ops::{Add, Sub, Mul, Neg};
 use serde::ser::Serialize;
 use serde::de::DeserializeOwned;
+use rand_core::{CryptoRng, OsRng, RngCore};
 
 #[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(C)]
@@ -28,8 +30,12 @@ impl Fr {
         Fr(fields::Fr::one())
     }
     pub fn random() -> Self {
+        Fr::random_with_rng(&mut OsRng)
+    }
+    /// a random element drawn from the given rng
+    pub fn random_with_rng<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
         let mut buf = [0u8; 64];
-        let ret = getrandom::getrandom(&mut buf);
+        rng.fill_bytes(&mut buf);
         Fr(fields::Fr::interpret(&buf))
     }
     pub fn pow(&self, exp: Fr) -> Self {
@@ -99,6 +105,11 @@ pub trait Group
     fn zero() -> Self;
     fn one() -> Self;
     fn random() -> Self;
+    /// a random element drawn from the given rng, a random multiple of
+    /// `one()` unless the group overrides it
+    fn random_with_rng<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        Self::one() * Fr::random_with_rng(rng)
+    }
     fn is_zero(&self) -> bool;
     fn normalize(&mut self);
 }
@@ -115,7 +126,7 @@ impl Group for G1 {
         G1(groups::G1::one())
     }
     fn random() -> Self {
-        G1(groups::G1::random())
+        G1::random_with_rng(&mut OsRng)
     }
     fn is_zero(&self) -> bool {
         self.0.is_zero()
@@ -174,7 +185,7 @@ impl Group for G2 {
         G2(groups::G2::one())
     }
     fn random() -> Self {
-        G2(groups::G2::random())
+        G2::random_with_rng(&mut OsRng)
     }
     fn is_zero(&self) -> bool {
         self.0.is_zero()
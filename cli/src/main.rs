@@ -6,8 +6,9 @@ use std::env::set_var;
 use std::error::Error;
 use std::fmt;
 use std::process;
-use hope::config::Config;
+use hope::config::{Config, KeygenConfig};
 use hope_library::scheme::hope as hopeScheme;
+use hope_library::keyfile;
 use hope_library::websocket::HopeWebSocket;
 use hope_server::server::Server;
 
@@ -65,18 +66,18 @@ fn main() -> Fallible<()> {
     );
     // Create the scheme
     let wsh = HopeWebSocket::new();
-    let _hope: hopeScheme = hopeScheme::new(String::from("demo"), &wsh);
+    let _hope: hopeScheme = hopeScheme::new(String::from("demo"), &wsh)?;
 
 
-    if let Err(e) = run(matches, _hope) {
+    if let Err(e) = run(matches, _hope, &config) {
         println!("Application error: {}", e);
         process::exit(1);
     }
 
-    fn run(matches: ArgMatches, _hope: hopeScheme) -> Result<(), ReclaimPathError> {
+    fn run(matches: ArgMatches, _hope: hopeScheme, config: &Config) -> Result<(), ReclaimPathError> {
         match matches.subcommand() {
             (CMD_SETUP, Some(matches)) => run_setup(matches, _hope),
-            (CMD_KEYGEN, Some(matches)) => run_keygen(matches, _hope),
+            (CMD_KEYGEN, Some(matches)) => run_keygen(matches, _hope, &config.keygen),
             (CMD_ENCRYPT, Some(matches)) => run_encrypt(matches, _hope),
             _ => Ok(()),
         }
@@ -85,16 +86,36 @@ fn main() -> Fallible<()> {
     fn run_setup(arguments: &ArgMatches, _hope: hopeScheme) -> Result<(), ReclaimPathError> {
         println!("Running setup...");
         let sp = _hope.parameters();
-        let jsp = serde_json::to_string(&sp);
-        println!("{:?}", jsp);
+        let armored = keyfile::armor_parameters(&sp);
+        match arguments.value_of("output") {
+            Some(path) => {
+                keyfile::write_keyfile(path, &armored)
+                    .map_err(|e| ReclaimPathError::new(&e.to_string()))?;
+                println!("wrote {} ({})", path, keyfile::parameters_key_id(&sp));
+            }
+            None => print!("{}", armored),
+        }
         Ok(())
     }
 
-    fn run_keygen(arguments: &ArgMatches, _hope: hopeScheme) -> Result<(), ReclaimPathError> {
+    fn run_keygen(arguments: &ArgMatches, _hope: hopeScheme, keygen: &KeygenConfig) -> Result<(), ReclaimPathError> {
         println!("Running keygen...");
-        let sk = hopeScheme::<'_>::keygen().unwrap();
-        let jsk = serde_json::to_string(&sk);
-        println!("{:?}", jsk);
+        let sk = hopeScheme::<'_>::keygen_with(keygen)
+            .map_err(|e| ReclaimPathError::new(&e.to_string()))?;
+        let public = keyfile::armor_hope_public(&sk);
+        let secret = keyfile::armor_hope_secret(&sk)
+            .ok_or_else(|| ReclaimPathError::new("the generated key has no secret part"))?;
+        match arguments.value_of("output") {
+            // the secret key goes next to the public key with a .sec suffix
+            Some(path) => {
+                let secret_path = format!("{}.sec", path);
+                keyfile::write_keyfile(path, &public)
+                    .and_then(|_| keyfile::write_secret_keyfile(&secret_path, &secret))
+                    .map_err(|e| ReclaimPathError::new(&e.to_string()))?;
+                println!("wrote {} and {} ({})", path, secret_path, keyfile::paillier_key_id(&sk._ek));
+            }
+            None => print!("{}{}", public, secret),
+        }
         Ok(())
     }
 
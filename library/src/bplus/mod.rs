@@ -1,112 +1,257 @@
-#![feature(nll)]
-
-use crate::paillier::*;
-use mongodb::oid::ObjectId;
-use crate::scheme::*;
-use std::path::Path;
-use std::fs::File;
-use std::error::Error;
-use std::io::{Write, Read};
-use std::marker::PhantomData;
-const TREE_BEGIN: &'static str = "-----BEGIN TREE-----\n";
-const TREE_END: &'static str = "\n-----END TREE-----";
+use bson::oid::ObjectId;
+use crate::error::HopeError;
+use crate::oracle::ComparisonOracle;
 use serde_derive::{Serialize, Deserialize};
 
 use ::hope::protocol::model::*;
+use num_bigint::BigInt;
+use std::collections::BTreeMap;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Tree {
+pub struct Tree<C = BigInt> {
     pub _id: ObjectId,
     pub _degree: usize,
-    pub _root: Option<hopeNode>,
+    pub _root: Option<hopeNode<C>>,
+    /// codes of the leaves by id as of the last `update_codes`
+    #[serde(skip)]
+    _codes: BTreeMap<ObjectId, u64>,
+    /// region whose codes changed since the last `update_codes`, see
+    /// `mark_dirty`
+    #[serde(skip)]
+    _dirty: Option<(Vec<usize>, usize)>,
 }
 
-impl Tree {
-    pub fn new(degree: usize) -> Tree {
+impl<C: Clone> Tree<C> {
+    /// Tree whose nodes hold at most degree keys, at least three, so both
+    /// halves of a split node are non-empty
+    pub fn new(degree: usize) -> Tree<C> {
         Tree {
             _id: ObjectId::new().unwrap(),
-            _degree: degree,
+            _degree: degree.max(3),
             _root: None,
+            _codes: BTreeMap::new(),
+            _dirty: None,
         }
     }
 
-    pub fn search(&self, key: hopeLeaf) -> Option<&hopeNode> {
-        match self._root {
-            None => None,
-            Some(ref root) => root.search(key),
+    pub fn search(&self, key: hopeLeaf<C>) -> Result<&hopeNode<C>, HopeError> {
+        let _id = key._id.clone();
+        self._root
+            .as_ref()
+            .and_then(|root| root.search(key))
+            .ok_or(HopeError::TreeMiss(_id))
+    }
+
+    /// Number of levels, every leaf node has the same depth
+    pub fn depth(&self) -> usize {
+        let mut _depth = 0;
+        let mut _node = self._root.as_ref();
+        while let Some(x) = _node {
+            _depth += 1;
+            _node = x._children.first();
         }
+        _depth
     }
 
-    pub fn code(&self, _key: ObjectId) -> Option<u64> {
-        match self._root {
-            None => None,
-            Some(ref root) => root.code(0, _key),
+    /// Bits of a digit and number of digits of a code, see `hopeNode::code`
+    fn code_digits(&self) -> (u32, u32) {
+        let _bits = (2 * self._degree as u64 + 1).next_power_of_two().trailing_zeros();
+        (_bits, 64 / _bits)
+    }
+
+    /// Order code of the leaf _key. The codes of all leaves sort like their
+    /// plaintexts, but an insert may change the codes of other leaves.
+    /// Looked up by id after `update_codes`, searched for otherwise.
+    pub fn code(&self, _key: ObjectId) -> Result<u64, HopeError> {
+        let (_bits, _levels) = self.code_digits();
+        if self.depth() > _levels as usize {
+            return Err(HopeError::CodeOverflow {
+                depth: self.depth(),
+                levels: _levels as usize,
+            });
         }
+        if self._dirty.is_none() {
+            if let Some(_o) = self._codes.get(&_key) {
+                return Ok(*_o);
+            }
+        }
+        self._root
+            .as_ref()
+            .and_then(|root| root.code(0, _bits, _levels, &_key))
+            .ok_or(HopeError::TreeMiss(_key))
+    }
+
+    /// Sets `_o` of every leaf to its current code, see `code`. Only the
+    /// leaves whose codes changed since the last call are renumbered: an
+    /// insert without splits renumbers the keys of one leaf node, a split
+    /// the keys and subtrees right of it in the parent, and only a split of
+    /// the root renumbers the whole tree.
+    pub fn update_codes(&mut self) -> Result<(), HopeError> {
+        let (_bits, _levels) = self.code_digits();
+        let _depth = self.depth();
+        if _depth > _levels as usize {
+            return Err(HopeError::CodeOverflow {
+                depth: _depth,
+                levels: _levels as usize,
+            });
+        }
+        let (_path, _from) = match self._dirty.take() {
+            Some(_dirty) => _dirty,
+            None => return Ok(()),
+        };
+        let Tree { _root, _codes, .. } = self;
+        let mut _node = match _root {
+            Some(ref mut root) => root,
+            None => return Ok(()),
+        };
+        let mut _code = 0;
+        let mut _left = _levels;
+        for i in _path {
+            _code = (_code << _bits) + 2 * i as u64;
+            _left -= 1;
+            _node = &mut _node._children[i];
+        }
+        _node.update_codes_from(_code, _bits, _left, _from, &mut |l| {
+            _codes.insert(l._id.clone(), l._o);
+        });
+        Ok(())
+    }
+
+    /// Leaf with id _id, to update its ciphertext in place
+    pub fn leaf_mut(&mut self, _id: &ObjectId) -> Result<&mut hopeLeaf<C>, HopeError> {
+        self._root
+            .as_mut()
+            .and_then(|root| leaf_mut(root, _id))
+            .ok_or_else(|| HopeError::TreeMiss(_id.clone()))
     }
 
-    pub fn update_apl(&self) {
-        match &self._root {
-            None => {}
-            Some(_r) => _r.update_apl(0),
+    /// Sets `_o` of every leaf to its current code, see `update_codes`, and
+    /// writes the codes to the documents of the leaves in _coll
+    #[cfg(feature = "mongodb")]
+    pub fn update_apl(&mut self, _coll: &mongodb::coll::Collection) -> Result<(), HopeError> {
+        let _dirty = self._dirty.clone();
+        self.update_codes()?;
+        let (_path, _from) = match _dirty {
+            Some(_dirty) => _dirty,
+            None => return Ok(()),
+        };
+        let mut _node = match self._root {
+            Some(ref root) => root,
+            None => return Ok(()),
+        };
+        for i in _path {
+            _node = &_node._children[i];
         }
+        _node.update_apl_from(_coll, _from).map_err(|e| HopeError::Storage(e.to_string()))
     }
 
-    pub fn insert(&mut self, _key: hopeLeaf) {
+    /// Inserts _key after every stored key, without comparisons
+    #[deprecated(note = "does not keep the plaintext order, use `insert_with` or `insert_by`")]
+    pub fn insert(&mut self, _key: hopeLeaf<C>) -> Result<(), HopeError> {
+        self.insert_by(_key, &mut |_, _| Ok(true))
+    }
+
+    /// Node containing _key, descending by the comparison _gt(a, b) = a > b
+    pub fn search_by<F>(&self, _key: &hopeLeaf<C>, _gt: &mut F) -> Result<&hopeNode<C>, HopeError>
+    where
+        F: FnMut(&hopeLeaf<C>, &hopeLeaf<C>) -> Result<bool, HopeError>,
+    {
         match self._root {
+            None => None,
+            Some(ref root) => root.search_by(_key, _gt)?,
+        }
+        .ok_or_else(|| HopeError::TreeMiss(_key._id.clone()))
+    }
+
+    /// Node containing _key, asking _oracle for the comparisons
+    pub fn search_with<O>(&self, _key: &hopeLeaf<C>, _oracle: &O) -> Result<&hopeNode<C>, HopeError>
+    where
+        O: ComparisonOracle<C> + ?Sized,
+    {
+        self.search_by(_key, &mut |a, b| _oracle.less_than(&b._c, &a._c))
+    }
+
+    /// Inserts _key in order, using the comparison _gt(a, b) = a > b on the
+    /// encrypted leaves, e.g. `HopeWebSocket::greater_than`. Returns the
+    /// error of the first failed comparison, the tree is left unchanged in
+    /// that case unless a full node was split on the way down.
+    pub fn insert_by<F>(&mut self, _key: hopeLeaf<C>, _gt: &mut F) -> Result<(), HopeError>
+    where
+        F: FnMut(&hopeLeaf<C>, &hopeLeaf<C>) -> Result<bool, HopeError>,
+    {
+        self.insert_at(_key, &mut |x, k| x.position_by(k, _gt))
+    }
+
+    /// Inserts _key in order, asking _oracle for one batch of comparisons
+    /// per visited node, see `insert_by`
+    pub fn insert_with<O>(&mut self, _key: hopeLeaf<C>, _oracle: &O) -> Result<(), HopeError>
+    where
+        O: ComparisonOracle<C> + ?Sized,
+    {
+        self.insert_at(_key, &mut |x, k| position_with(x, k, _oracle))
+    }
+
+    /// Inserts _key at the positions _position(node, key) on the way down.
+    /// Full nodes are split at their median before they are entered, so
+    /// the leaf that takes _key has room for it.
+    fn insert_at<P>(&mut self, _key: hopeLeaf<C>, _position: &mut P) -> Result<(), HopeError>
+    where
+        P: FnMut(&hopeNode<C>, &hopeLeaf<C>) -> Result<usize, HopeError>,
+    {
+        // Create a new root if filled, every code gains a digit
+        if self._root.as_ref().is_some_and(|root| root.is_full()) {
+            if let Some(old) = self._root.take() {
+                let (k, lnode, rnode) = old.split();
+                let mut root = hopeNode::new(self._degree, false);
+                root._num_cts = 1;
+                root._cts.push(k);
+                root._children.push(lnode);
+                root._children.push(rnode);
+                self._root = Some(root);
+                mark_dirty(&mut self._dirty, Vec::new(), 0);
+            }
+        }
+        let Tree { _root, _degree, _dirty, .. } = self;
+        match _root {
             None => {
-                let mut node = hopeNode::new(self._degree, true);
-                node.insert_key(_key);
-                self._root = Some(node);
+                let mut node = hopeNode::new(*_degree, true);
+                node._num_cts = 1;
+                node._cts.push(_key);
+                *_root = Some(node);
+                mark_dirty(_dirty, Vec::new(), 0);
+                Ok(())
             }
             Some(ref mut root) => {
-                // Create a new root if filled
-                if root.is_full() {
-                    // rendundant code here to get around rust's ownership checks
-                    let mut left = hopeNode::new(root._degree, true);
-                    let mut right = hopeNode::new(root._degree, true);
-                    let mut index = 0;
-                    while index < root.capacity() {
-                        let k = root.remove_key(_key.clone());
-                        if index < self._degree - 1 {
-                            left.insert_key(k.clone());
-                        } else if index > self._degree - 1 {
-                            right.insert_key(k.clone());
-                        } else {
-                            root._num_cts += 1;
-                            root._cts.push(k);
-                        }
-                        index += 1;
-                    }
-                    root._children.push(left);
-                    root._children.push(right);
-                    root._is_leaf = false;
-                }
-
                 let mut x = root;
+                // child indices from the root to x
+                let mut _path = Vec::new();
                 loop {
                     if x._is_leaf {
                         break;
                     }
 
-                    let mut i = 0;
-                    while i < x._num_cts {
-                        //&& _elem._c > x._cts[i] {
-                        i += 1;
-                    }
-
+                    let i = _position(x, &_key)?;
                     if x._children[i].is_full() {
-                        // Split the node if it's full
+                        // Split the node if it's full, the median of child i
+                        // lies between the keys i - 1 and i of x
                         let child = x._children.remove(i);
                         let (k, lnode, rnode) = child.split();
-                        let inserted_index = x.insert_key(k);
-                        x._children.insert(inserted_index, lnode);
-                        x._children.insert(inserted_index + 1, rnode);
+                        x._num_cts += 1;
+                        x._cts.insert(i, k);
+                        x._children.insert(i, lnode);
+                        x._children.insert(i + 1, rnode);
+                        mark_dirty(_dirty, _path.clone(), i);
                     } else {
                         x = &mut x._children[i];
+                        _path.push(i);
                     }
                 }
                 // Insert key
-                x.insert_key(_key);
+                let i = _position(x, &_key)?;
+                x._num_cts += 1;
+                x._cts.insert(i, _key);
+                mark_dirty(_dirty, _path, i);
+                Ok(())
             }
         }
     }
@@ -162,7 +307,42 @@ impl Tree {
     */
 }
 
-impl std::fmt::Display for Tree {
+/// Number of keys of _node that are less than _key, from one batch
+fn position_with<C, O>(_node: &hopeNode<C>, _key: &hopeLeaf<C>, _oracle: &O) -> Result<usize, HopeError>
+where
+    O: ComparisonOracle<C> + ?Sized,
+{
+    let _pairs: Vec<(&C, &C)> = _node._cts.iter().map(|k| (&k._c, &_key._c)).collect();
+    let _lt = _oracle.less_than_batch(&_pairs)?;
+    Ok(_lt.iter().take_while(|b| **b).count())
+}
+
+/// Records that the codes of the node at the child indices _path changed
+/// from index _from on, see `hopeNode::update_codes_from`. A change left of
+/// a recorded one may shift the child indices on its path, so two changes
+/// are merged into their lowest common node, from the first index on which
+/// their paths differ.
+fn mark_dirty(_dirty: &mut Option<(Vec<usize>, usize)>, _path: Vec<usize>, _from: usize) {
+    *_dirty = Some(match _dirty.take() {
+        None => (_path, _from),
+        Some((mut _old, _old_from)) => {
+            let c = _old.iter().zip(_path.iter()).take_while(|(a, b)| a == b).count();
+            let _first = _old.get(c).copied().unwrap_or(_old_from);
+            let _next = _path.get(c).copied().unwrap_or(_from);
+            _old.truncate(c);
+            (_old, _first.min(_next))
+        }
+    });
+}
+
+fn leaf_mut<'a, C>(_node: &'a mut hopeNode<C>, _id: &ObjectId) -> Option<&'a mut hopeLeaf<C>> {
+    match _node._cts.iter().position(|l| l._id == *_id) {
+        Some(i) => _node._cts.get_mut(i),
+        None => _node._children.iter_mut().find_map(|c| leaf_mut(c, _id)),
+    }
+}
+
+impl<C: std::fmt::Debug> std::fmt::Display for Tree<C> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
@@ -177,6 +357,107 @@ impl std::fmt::Display for Tree {
 #[cfg(test)]
 mod tests {
     use super::Tree;
+    use crate::error::HopeError;
+    use crate::oracle::ComparisonOracle;
+    use ::hope::protocol::model::{hopeLeaf, hopeNode};
+    use bson::oid::ObjectId;
+    use num_bigint::BigInt;
+
+    #[test]
+    fn insert_by_comparison() {
+        // leaves carry their plaintext, the comparison is done in the clear
+        let mut btree = Tree::new(4);
+        let mut gt = |a: &hopeLeaf, b: &hopeLeaf| Ok(a._c > b._c);
+        for m in &[30, -10, 20, 0] {
+            let leaf = hopeLeaf::new(ObjectId::new().unwrap(), BigInt::from(*m), 0);
+            btree.insert_by(leaf.clone(), &mut gt).unwrap();
+            assert!(btree.search_by(&leaf, &mut gt).is_ok());
+        }
+        let root = btree._root.clone().unwrap();
+        let cs: Vec<BigInt> = root._cts.iter().map(|l| l._c.clone()).collect();
+        assert_eq!(cs, vec![BigInt::from(-10), BigInt::from(0), BigInt::from(20), BigInt::from(30)]);
+        let leaf = hopeLeaf::new(ObjectId::new().unwrap(), BigInt::from(5), 0);
+        let _id = leaf._id.clone();
+        assert_eq!(btree.insert_by(leaf, &mut |_, _| Err(HopeError::Comparison)), Err(HopeError::Comparison));
+        assert_eq!(btree.code(_id.clone()), Err(HopeError::TreeMiss(_id)));
+    }
+
+    /// oracle for leaves that carry their plaintext
+    struct Plain;
+
+    impl ComparisonOracle<i64> for Plain {
+        fn less_than(&self, a: &i64, b: &i64) -> Result<bool, HopeError> {
+            Ok(a < b)
+        }
+    }
+
+    /// Leaves of the subtree of _node, checking the shape of a B-tree
+    fn leaves(_node: &hopeNode<i64>, _depth: usize, _leaf_depth: &mut Option<usize>, _out: &mut Vec<hopeLeaf<i64>>) {
+        assert!(_node._cts.len() <= _node._degree);
+        assert_eq!(_node._num_cts, _node._cts.len());
+        if _node._is_leaf {
+            assert!(_node._children.is_empty());
+            assert_eq!(*_leaf_depth.get_or_insert(_depth), _depth);
+        } else {
+            assert_eq!(_node._children.len(), _node._cts.len() + 1);
+        }
+        _out.extend(_node._cts.iter().cloned());
+        for _child in &_node._children {
+            leaves(_child, _depth + 1, _leaf_depth, _out);
+        }
+    }
+
+    #[test]
+    fn codes_follow_plaintext_order() {
+        for degree in 3..7 {
+            let mut btree: Tree<i64> = Tree::new(degree);
+            let mut ids = Vec::new();
+            let mut x: i64 = 7;
+            for n in 1..=300 {
+                // plaintexts with repetitions, in pseudo-random order
+                x = (x * 1103515245 + 12345) % 2147483648;
+                let leaf = hopeLeaf::new(ObjectId::new().unwrap(), x % 97 - 48, 0);
+                ids.push(leaf._id.clone());
+                btree.insert_with(leaf, &Plain).unwrap();
+                // several inserts between updates merge their changes
+                if x % 3 == 0 {
+                    continue;
+                }
+                btree.update_codes().unwrap();
+                let mut _leaves = Vec::new();
+                leaves(btree._root.as_ref().unwrap(), 1, &mut None, &mut _leaves);
+                assert_eq!(_leaves.len(), n);
+                _leaves.sort_by_key(|l| l._o);
+                for w in _leaves.windows(2) {
+                    assert!(w[0]._o < w[1]._o && w[0]._c <= w[1]._c);
+                }
+                for l in &_leaves {
+                    assert_eq!(btree.code(l._id.clone()), Ok(l._o));
+                }
+                // the renumbered codes are the codes of the whole tree
+                let (_bits, _levels) = btree.code_digits();
+                let mut _full = btree._root.clone().unwrap();
+                assert!(_full.update_codes(0, _bits, _levels));
+                let mut _all = Vec::new();
+                leaves(&_full, 1, &mut None, &mut _all);
+                _all.sort_by_key(|l| l._o);
+                for (a, l) in _all.iter().zip(_leaves.iter()) {
+                    assert!(a._id == l._id && a._o == l._o);
+                }
+            }
+            assert!(btree.depth() > 2);
+            let mut btree_by: Tree<i64> = Tree::new(degree);
+            let mut gt = |a: &hopeLeaf<i64>, b: &hopeLeaf<i64>| Ok(a._c > b._c);
+            for m in (0..50).rev() {
+                btree_by.insert_by(hopeLeaf::new(ObjectId::new().unwrap(), m, 0), &mut gt).unwrap();
+            }
+            btree_by.update_codes().unwrap();
+            let mut _leaves = Vec::new();
+            leaves(btree_by._root.as_ref().unwrap(), 1, &mut None, &mut _leaves);
+            _leaves.sort_by_key(|l| l._o);
+            assert_eq!(_leaves.iter().map(|l| l._c).collect::<Vec<i64>>(), (0..50).collect::<Vec<i64>>());
+        }
+    }
 /*
     #[test]
     fn it_has_ordered_inserts() {
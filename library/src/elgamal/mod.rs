@@ -0,0 +1,322 @@
+//! Exponential (additively homomorphic) ElGamal over the G1 group of `bn`.
+//!
+//! A plaintext m is encrypted as (r * G, m * G + r * H) for the generator G
+//! of G1 and the encryption key H = x * G. Ciphertexts add and subtract
+//! componentwise, decryption recovers m * G and solves the discrete
+//! logarithm with baby-step giant-step, so plaintexts have to be small.
+//! An `ElGamalTable` holds the baby steps for all plaintexts in
+//! [-bound, bound) and can be reused for any number of decryptions.
+/// distributed key generation and threshold decryption
+pub mod threshold;
+/// sigma protocols for decryption and re-randomisation
+pub mod proofs;
+use bn::{Fr, G1, Group};
+use crate::error::HopeError;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub use self::proofs::{DecryptionProof, DleqProof, RerandomizationProof, Transcript};
+pub use self::threshold::{ElGamalDkgParticipant, ElGamalThresholdKey, ThresholdElGamal};
+
+/// largest bound of an `ElGamalTable`, its table has about 2^24.5 entries
+/// and takes a few hundred MB
+pub const MAX_TABLE_BOUND: u64 = 1 << 48;
+/// order of G1, the plaintext modulus
+const ORDER: &str = "21888242871839275222246405745257275088548364400416034343698204186575808495617";
+
+/// ElGamal encryption key H = x * G
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ElGamalEncryptionKey {
+    pub h: G1,
+}
+
+/// ElGamal decryption key x
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ElGamalDecryptionKey {
+    pub x: Fr,
+}
+
+/// ElGamal ciphertext (r * G, m * G + r * H)
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ElGamalCiphertext {
+    pub c1: G1,
+    pub c2: G1,
+}
+
+/// Exponential ElGamal cryptosystem
+pub struct ElGamal;
+
+/// Decryption key together with the table for its plaintext range
+#[derive(Clone)]
+pub struct ElGamalDecryptor {
+    pub dk: ElGamalDecryptionKey,
+    pub table: Arc<ElGamalTable>,
+}
+
+/// Baby steps j * G for 0 <= j < m, indexed by a fingerprint of the point
+pub struct ElGamalTable {
+    baby: HashMap<u64, u64>,
+    m: u64,
+    bound: u64,
+}
+
+impl ElGamal {
+    pub fn keygen() -> (ElGamalEncryptionKey, ElGamalDecryptionKey) {
+        ElGamal::keygen_with_rng(&mut OsRng)
+    }
+
+    /// `keygen` with the secret drawn from rng
+    pub fn keygen_with_rng<R: RngCore + CryptoRng>(rng: &mut R) -> (ElGamalEncryptionKey, ElGamalDecryptionKey) {
+        let x = Fr::random_with_rng(rng);
+        (ElGamalEncryptionKey { h: G1::one() * x }, ElGamalDecryptionKey { x })
+    }
+
+    /// Encryption key of a decryption key
+    pub fn public_key(dk: &ElGamalDecryptionKey) -> ElGamalEncryptionKey {
+        ElGamalEncryptionKey { h: G1::one() * dk.x }
+    }
+
+    /// Encryption of m, which may be negative
+    pub fn encrypt(ek: &ElGamalEncryptionKey, m: &BigInt) -> ElGamalCiphertext {
+        ElGamal::encrypt_with_rng(ek, m, &mut OsRng)
+    }
+
+    /// `encrypt` with randomness drawn from rng
+    pub fn encrypt_with_rng<R: RngCore + CryptoRng>(
+        ek: &ElGamalEncryptionKey,
+        m: &BigInt,
+        rng: &mut R,
+    ) -> ElGamalCiphertext {
+        ElGamal::encrypt_with_r(ek, m, &Fr::random_with_rng(rng))
+    }
+
+    /// Encryption of m with the randomness r
+    pub fn encrypt_with_r(ek: &ElGamalEncryptionKey, m: &BigInt, r: &Fr) -> ElGamalCiphertext {
+        ElGamalCiphertext {
+            c1: G1::one() * *r,
+            c2: G1::one() * ElGamal::encode(m) + ek.h * *r,
+        }
+    }
+
+    /// m * G for the plaintext m of c
+    pub fn decrypt_point(dk: &ElGamalDecryptionKey, c: &ElGamalCiphertext) -> G1 {
+        c.c2 - c.c1 * dk.x
+    }
+
+    /// Plaintext of c, None if it is outside the range of table
+    pub fn decrypt(dk: &ElGamalDecryptionKey, c: &ElGamalCiphertext, table: &ElGamalTable) -> Option<BigInt> {
+        table.solve(&ElGamal::decrypt_point(dk, c))
+    }
+
+    /// Encryption of m1 + m2
+    pub fn add(c1: &ElGamalCiphertext, c2: &ElGamalCiphertext) -> ElGamalCiphertext {
+        ElGamalCiphertext {
+            c1: c1.c1 + c2.c1,
+            c2: c1.c2 + c2.c2,
+        }
+    }
+
+    /// Encryption of m1 - m2
+    pub fn sub(c1: &ElGamalCiphertext, c2: &ElGamalCiphertext) -> ElGamalCiphertext {
+        ElGamalCiphertext {
+            c1: c1.c1 - c2.c1,
+            c2: c1.c2 - c2.c2,
+        }
+    }
+
+    /// Encryption of -m
+    pub fn neg(c: &ElGamalCiphertext) -> ElGamalCiphertext {
+        ElGamalCiphertext { c1: -c.c1, c2: -c.c2 }
+    }
+
+    /// Encryption of m1 + m, m may be negative
+    pub fn add_const(c: &ElGamalCiphertext, m: &BigInt) -> ElGamalCiphertext {
+        ElGamalCiphertext {
+            c1: c.c1,
+            c2: c.c2 + G1::one() * ElGamal::encode(m),
+        }
+    }
+
+    /// Encryption of m1 * m, m may be negative
+    pub fn mul_const(c: &ElGamalCiphertext, m: &BigInt) -> ElGamalCiphertext {
+        let k = ElGamal::encode(m);
+        ElGamalCiphertext { c1: c.c1 * k, c2: c.c2 * k }
+    }
+
+    /// Fresh encryption of the plaintext of c
+    pub fn rerandomize(ek: &ElGamalEncryptionKey, c: &ElGamalCiphertext) -> ElGamalCiphertext {
+        ElGamal::rerandomize_with_rng(ek, c, &mut OsRng)
+    }
+
+    /// `rerandomize` with randomness drawn from rng
+    pub fn rerandomize_with_rng<R: RngCore + CryptoRng>(
+        ek: &ElGamalEncryptionKey,
+        c: &ElGamalCiphertext,
+        rng: &mut R,
+    ) -> ElGamalCiphertext {
+        let r = Fr::random_with_rng(rng);
+        ElGamalCiphertext {
+            c1: c.c1 + G1::one() * r,
+            c2: c.c2 + ek.h * r,
+        }
+    }
+
+    /// Order of G1
+    pub fn order() -> BigInt {
+        BigInt::parse_bytes(ORDER.as_bytes(), 10).unwrap()
+    }
+
+    /// m reduced modulo the order of G1
+    pub fn encode(m: &BigInt) -> Fr {
+        let (_, bytes) = m.mod_floor(&ElGamal::order()).to_bytes_be();
+        let mut buf = [0u8; 64];
+        buf[64 - bytes.len()..].copy_from_slice(&bytes);
+        Fr::interpret(&buf)
+    }
+}
+
+impl ElGamalDecryptor {
+    /// Decryptor for plaintexts in [-bound, bound), see `ElGamalTable::new`
+    pub fn new(dk: ElGamalDecryptionKey, bound: u64) -> Result<ElGamalDecryptor, HopeError> {
+        Ok(ElGamalDecryptor {
+            dk,
+            table: Arc::new(ElGamalTable::new(bound)?),
+        })
+    }
+
+    pub fn decrypt(&self, c: &ElGamalCiphertext) -> Option<BigInt> {
+        ElGamal::decrypt(&self.dk, c, &self.table)
+    }
+}
+
+impl ElGamalTable {
+    /// Table for plaintexts in [-bound, bound), with about sqrt(2 * bound)
+    /// entries. `InvalidArgument` unless 1 <= bound <= MAX_TABLE_BOUND.
+    pub fn new(bound: u64) -> Result<ElGamalTable, HopeError> {
+        if bound == 0 || bound > MAX_TABLE_BOUND {
+            return Err(HopeError::InvalidArgument(format!(
+                "table bound {} outside 1..=2^48",
+                bound
+            )));
+        }
+        let m = ((2 * bound) as f64).sqrt().ceil() as u64;
+        let mut baby = HashMap::with_capacity(m as usize);
+        let mut p = G1::zero();
+        for j in 0..m {
+            baby.entry(fingerprint(&p)).or_insert(j);
+            p = p + G1::one();
+        }
+        Ok(ElGamalTable {
+            baby,
+            m,
+            bound,
+        })
+    }
+
+    pub fn bound(&self) -> u64 {
+        self.bound
+    }
+
+    /// m with m * G = point and -bound <= m < bound
+    pub fn solve(&self, point: &G1) -> Option<BigInt> {
+        let n = 2 * self.bound;
+        let target = *point + G1::one() * ElGamal::encode(&BigInt::from(self.bound));
+        let giant = -(G1::one() * ElGamal::encode(&BigInt::from(self.m)));
+        let mut q = target;
+        let mut i = 0;
+        while i * self.m < n {
+            if let Some(j) = self.baby.get(&fingerprint(&q)) {
+                let k = i * self.m + j;
+                // rule out fingerprint collisions
+                if k < n && G1::one() * ElGamal::encode(&BigInt::from(k)) == target {
+                    return Some(BigInt::from(k) - BigInt::from(self.bound));
+                }
+            }
+            q = q + giant;
+            i += 1;
+        }
+        None
+    }
+}
+
+/// Encoding of the affine coordinates of p, zero encodes as [0]
+pub(crate) fn canonical(p: &G1) -> Vec<u8> {
+    if p.is_zero() {
+        return vec![0];
+    }
+    let mut g = *p;
+    g.normalize();
+    serde_json::to_vec(&g).unwrap()
+}
+
+/// 64 bit hash of the affine coordinates of p
+fn fingerprint(p: &G1) -> u64 {
+    if p.is_zero() {
+        return 0;
+    }
+    let digest = Sha256::digest(&canonical(p));
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand_chacha::ChaChaRng;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn elgamal_roundtrip() {
+        let (ek, dk) = ElGamal::keygen();
+        assert!(ElGamal::public_key(&dk) == ek);
+        let table = ElGamalTable::new(1 << 16).unwrap();
+        assert!(ElGamalTable::new(0).is_err());
+        assert!(ElGamalTable::new(MAX_TABLE_BOUND + 1).is_err());
+        for m in &[-65536i64, -1234, -1, 0, 1, 42, 65535] {
+            let c = ElGamal::encrypt(&ek, &BigInt::from(*m));
+            assert_eq!(ElGamal::decrypt(&dk, &c, &table), Some(BigInt::from(*m)));
+        }
+        let c = ElGamal::encrypt(&ek, &BigInt::from(65536));
+        assert!(ElGamal::decrypt(&dk, &c, &table).is_none());
+        let c = ElGamal::encrypt(&ek, &BigInt::from(-65537));
+        assert!(ElGamal::decrypt(&dk, &c, &table).is_none());
+    }
+
+    #[test]
+    fn elgamal_homomorphic() {
+        let (ek, dk) = ElGamal::keygen();
+        let table = ElGamalTable::new(1 << 20).unwrap();
+        let c1 = ElGamal::encrypt(&ek, &BigInt::from(1500));
+        let c2 = ElGamal::encrypt(&ek, &BigInt::from(-700));
+        let dec = |c: &ElGamalCiphertext| ElGamal::decrypt(&dk, c, &table).unwrap();
+        assert_eq!(dec(&ElGamal::add(&c1, &c2)), BigInt::from(800));
+        assert_eq!(dec(&ElGamal::sub(&c1, &c2)), BigInt::from(2200));
+        assert_eq!(dec(&ElGamal::neg(&c1)), BigInt::from(-1500));
+        assert_eq!(dec(&ElGamal::add_const(&c1, &BigInt::from(-2000))), BigInt::from(-500));
+        assert_eq!(dec(&ElGamal::mul_const(&c2, &BigInt::from(-3))), BigInt::from(2100));
+        let c3 = ElGamal::rerandomize(&ek, &c1);
+        assert!(c3 != c1);
+        assert_eq!(dec(&c3), BigInt::from(1500));
+    }
+
+    #[test]
+    fn elgamal_seeded_rng() {
+        let (ek1, dk1) = ElGamal::keygen_with_rng(&mut ChaChaRng::seed_from_u64(5));
+        let (ek2, _) = ElGamal::keygen_with_rng(&mut ChaChaRng::seed_from_u64(5));
+        assert!(ek1 == ek2);
+        let m = BigInt::from(7);
+        let c1 = ElGamal::encrypt_with_rng(&ek1, &m, &mut ChaChaRng::seed_from_u64(6));
+        let c2 = ElGamal::encrypt_with_rng(&ek1, &m, &mut ChaChaRng::seed_from_u64(6));
+        assert!(c1 == c2);
+        assert!(ElGamal::decrypt_point(&dk1, &c1) == G1::one() * ElGamal::encode(&m));
+        assert!(ElGamal::encode(&ElGamal::order()) == Fr::zero());
+        assert!(ElGamal::encode(&BigInt::from(-1)) == -Fr::one());
+    }
+}
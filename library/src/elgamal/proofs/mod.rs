@@ -0,0 +1,293 @@
+//! Non-interactive sigma protocols on G1.
+//!
+//! All proofs are Chaum-Pedersen proofs of discrete logarithm equality,
+//! made non-interactive with a Fiat-Shamir `Transcript`: every public point
+//! and commitment is absorbed with a label and the challenge is SHA-512 of
+//! everything absorbed so far. A correct decryption of (c1, c2) under
+//! H = x * G is shown by log_G(H) = log_c1(d) for d = x * c1, a
+//! re-randomisation c' = c + (r * G, r * H) by log_G(c1' - c1) =
+//! log_H(c2' - c2). Proofs and transcripts serialise with serde, so an
+//! auditor can check them later.
+use bn::{Fr, G1, Group};
+use num_bigint::BigInt;
+use num_traits::Zero;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use super::{canonical, ElGamal, ElGamalCiphertext, ElGamalDecryptionKey, ElGamalEncryptionKey};
+
+/// Fiat-Shamir transcript of the messages of a proof
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Transcript {
+    /// length-prefixed labels and messages absorbed so far
+    state: Vec<u8>,
+}
+
+/// Chaum-Pedersen proof that log_g(x) = log_u(d)
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct DleqProof {
+    pub e: Fr,
+    pub z: Fr,
+}
+
+/// Proof that d = x * c1 for the decryption key x of the encryption key
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct DecryptionProof {
+    pub d: G1,
+    pub proof: DleqProof,
+}
+
+/// Proof that a ciphertext re-randomises another one
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct RerandomizationProof {
+    pub proof: DleqProof,
+}
+
+impl Transcript {
+    /// Empty transcript for the protocol label
+    pub fn new(label: &str) -> Transcript {
+        let mut t = Transcript { state: Vec::new() };
+        t.append_bytes("protocol", label.as_bytes());
+        t
+    }
+
+    pub fn append_bytes(&mut self, label: &str, bytes: &[u8]) {
+        for m in &[label.as_bytes(), bytes] {
+            self.state.extend_from_slice(&(m.len() as u64).to_be_bytes());
+            self.state.extend_from_slice(m);
+        }
+    }
+
+    /// Absorbs the affine coordinates of p
+    pub fn append_point(&mut self, label: &str, p: &G1) {
+        self.append_bytes(label, &canonical(p));
+    }
+
+    pub fn append_scalar(&mut self, label: &str, s: &Fr) {
+        self.append_bytes(label, &serde_json::to_vec(s).unwrap());
+    }
+
+    /// Challenge derived from everything absorbed so far, which is absorbed
+    /// itself so that later challenges differ
+    pub fn challenge(&mut self, label: &str) -> Fr {
+        self.append_bytes(label, &[]);
+        let mut buf = [0u8; 64];
+        buf.copy_from_slice(&Sha512::digest(&self.state));
+        let e = Fr::interpret(&buf);
+        self.append_scalar("challenge", &e);
+        e
+    }
+}
+
+impl DleqProof {
+    /// Proof that x = w * g and d = w * u share the witness w
+    pub fn prove(g: &G1, u: &G1, w: &Fr) -> DleqProof {
+        DleqProof::prove_with_rng(g, u, w, &mut OsRng)
+    }
+
+    /// `prove` with randomness drawn from rng
+    pub fn prove_with_rng<R: RngCore + CryptoRng>(g: &G1, u: &G1, w: &Fr, rng: &mut R) -> DleqProof {
+        DleqProof::prove_with_transcript(&mut Transcript::new("dleq"), g, u, w, rng)
+    }
+
+    /// `prove_with_rng` bound to the messages already in t
+    pub fn prove_with_transcript<R: RngCore + CryptoRng>(
+        t: &mut Transcript,
+        g: &G1,
+        u: &G1,
+        w: &Fr,
+        rng: &mut R,
+    ) -> DleqProof {
+        let r = Fr::random_with_rng(rng);
+        let e = DleqProof::challenge(t, g, &(*g * *w), u, &(*u * *w), &(*g * r), &(*u * r));
+        DleqProof { e, z: r + e * *w }
+    }
+
+    /// Checks log_g(x) = log_u(d)
+    pub fn verify(&self, g: &G1, x: &G1, u: &G1, d: &G1) -> bool {
+        self.verify_with_transcript(&mut Transcript::new("dleq"), g, x, u, d)
+    }
+
+    /// `verify` for a proof bound to the messages already in t
+    pub fn verify_with_transcript(&self, t: &mut Transcript, g: &G1, x: &G1, u: &G1, d: &G1) -> bool {
+        let a = *g * self.z - *x * self.e;
+        let b = *u * self.z - *d * self.e;
+        self.e == DleqProof::challenge(t, g, x, u, d, &a, &b)
+    }
+
+    fn challenge(t: &mut Transcript, g: &G1, x: &G1, u: &G1, d: &G1, a: &G1, b: &G1) -> Fr {
+        t.append_point("g", g);
+        t.append_point("x", x);
+        t.append_point("u", u);
+        t.append_point("d", d);
+        t.append_point("a", a);
+        t.append_point("b", b);
+        t.challenge("e")
+    }
+}
+
+impl DecryptionProof {
+    /// Proof of the decryption of c with dk
+    pub fn prove(dk: &ElGamalDecryptionKey, c: &ElGamalCiphertext) -> DecryptionProof {
+        DecryptionProof::prove_with_rng(dk, c, &mut OsRng)
+    }
+
+    /// `prove` with randomness drawn from rng
+    pub fn prove_with_rng<R: RngCore + CryptoRng>(
+        dk: &ElGamalDecryptionKey,
+        c: &ElGamalCiphertext,
+        rng: &mut R,
+    ) -> DecryptionProof {
+        DecryptionProof {
+            d: c.c1 * dk.x,
+            proof: DleqProof::prove_with_transcript(&mut DecryptionProof::transcript(c), &G1::one(), &c.c1, &dk.x, rng),
+        }
+    }
+
+    /// m * G for the plaintext m of c, None if the proof does not hold
+    pub fn verify(&self, ek: &ElGamalEncryptionKey, c: &ElGamalCiphertext) -> Option<G1> {
+        if !self
+            .proof
+            .verify_with_transcript(&mut DecryptionProof::transcript(c), &G1::one(), &ek.h, &c.c1, &self.d)
+        {
+            return None;
+        }
+        Some(c.c2 - self.d)
+    }
+
+    /// Checks that m is the plaintext of c
+    pub fn verify_plaintext(&self, ek: &ElGamalEncryptionKey, c: &ElGamalCiphertext, m: &BigInt) -> bool {
+        self.verify(ek, c) == Some(G1::one() * ElGamal::encode(m))
+    }
+
+    fn transcript(c: &ElGamalCiphertext) -> Transcript {
+        let mut t = Transcript::new("elgamal-decryption");
+        t.append_point("c2", &c.c2);
+        t
+    }
+}
+
+impl RerandomizationProof {
+    /// Fresh encryption of the plaintext of c with a proof that it is one
+    pub fn rerandomize(ek: &ElGamalEncryptionKey, c: &ElGamalCiphertext) -> (ElGamalCiphertext, RerandomizationProof) {
+        RerandomizationProof::rerandomize_with_rng(ek, c, &mut OsRng)
+    }
+
+    /// `rerandomize` with randomness drawn from rng
+    pub fn rerandomize_with_rng<R: RngCore + CryptoRng>(
+        ek: &ElGamalEncryptionKey,
+        c: &ElGamalCiphertext,
+        rng: &mut R,
+    ) -> (ElGamalCiphertext, RerandomizationProof) {
+        let r = Fr::random_with_rng(rng);
+        let out = ElGamal::add(c, &ElGamal::encrypt_with_r(ek, &BigInt::zero(), &r));
+        let proof = RerandomizationProof::prove_with_rng(ek, c, &out, &r, rng);
+        (out, proof)
+    }
+
+    /// Proof that out = c + (r * G, r * H)
+    pub fn prove(
+        ek: &ElGamalEncryptionKey,
+        c: &ElGamalCiphertext,
+        out: &ElGamalCiphertext,
+        r: &Fr,
+    ) -> RerandomizationProof {
+        RerandomizationProof::prove_with_rng(ek, c, out, r, &mut OsRng)
+    }
+
+    /// `prove` with randomness drawn from rng
+    pub fn prove_with_rng<R: RngCore + CryptoRng>(
+        ek: &ElGamalEncryptionKey,
+        c: &ElGamalCiphertext,
+        out: &ElGamalCiphertext,
+        r: &Fr,
+        rng: &mut R,
+    ) -> RerandomizationProof {
+        let mut t = RerandomizationProof::transcript(c, out);
+        RerandomizationProof {
+            proof: DleqProof::prove_with_transcript(&mut t, &G1::one(), &ek.h, r, rng),
+        }
+    }
+
+    /// Checks that out encrypts the same plaintext as c
+    pub fn verify(&self, ek: &ElGamalEncryptionKey, c: &ElGamalCiphertext, out: &ElGamalCiphertext) -> bool {
+        let mut t = RerandomizationProof::transcript(c, out);
+        self.proof
+            .verify_with_transcript(&mut t, &G1::one(), &(out.c1 - c.c1), &ek.h, &(out.c2 - c.c2))
+    }
+
+    fn transcript(c: &ElGamalCiphertext, out: &ElGamalCiphertext) -> Transcript {
+        let mut t = Transcript::new("elgamal-rerandomization");
+        t.append_point("c1", &c.c1);
+        t.append_point("c2", &c.c2);
+        t.append_point("out1", &out.c1);
+        t.append_point("out2", &out.c2);
+        t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn dleq_proof() {
+        let w = Fr::random();
+        let u = G1::random();
+        let proof = DleqProof::prove(&G1::one(), &u, &w);
+        assert!(proof.verify(&G1::one(), &(G1::one() * w), &u, &(u * w)));
+        assert!(!proof.verify(&G1::one(), &(G1::one() * w), &u, &(u * (w + Fr::one()))));
+        let mut t = Transcript::new("context");
+        t.append_bytes("id", b"1");
+        let bound = DleqProof::prove_with_transcript(&mut t.clone(), &G1::one(), &u, &w, &mut OsRng);
+        assert!(bound.verify_with_transcript(&mut t.clone(), &G1::one(), &(G1::one() * w), &u, &(u * w)));
+        assert!(!bound.verify(&G1::one(), &(G1::one() * w), &u, &(u * w)));
+        let json = serde_json::to_string(&t).unwrap();
+        let mut t2: Transcript = serde_json::from_str(&json).unwrap();
+        assert!(t.challenge("e") == t2.challenge("e"));
+        assert!(t.challenge("e") != Transcript::new("context").challenge("e"));
+    }
+
+    #[test]
+    fn decryption_proof() {
+        let (ek, dk) = ElGamal::keygen();
+        let (other, _) = ElGamal::keygen();
+        let m = BigInt::from(-42);
+        let c = ElGamal::encrypt(&ek, &m);
+        let proof = DecryptionProof::prove(&dk, &c);
+        let json = serde_json::to_string(&proof).unwrap();
+        let proof: DecryptionProof = serde_json::from_str(&json).unwrap();
+        assert!(proof.verify(&ek, &c) == Some(ElGamal::decrypt_point(&dk, &c)));
+        assert!(proof.verify_plaintext(&ek, &c, &m));
+        assert!(!proof.verify_plaintext(&ek, &c, &BigInt::from(42)));
+        assert!(proof.verify(&other, &c).is_none());
+        // a wrong decryption share does not verify
+        let forged = DecryptionProof {
+            d: proof.d + G1::one(),
+            proof: proof.proof,
+        };
+        assert!(forged.verify(&ek, &c).is_none());
+        // the proof is bound to c
+        assert!(proof.verify(&ek, &ElGamal::add_const(&c, &BigInt::from(1))).is_none());
+    }
+
+    #[test]
+    fn rerandomization_proof() {
+        let (ek, _) = ElGamal::keygen();
+        let c = ElGamal::encrypt(&ek, &BigInt::from(7));
+        let d = ElGamal::encrypt(&ek, &BigInt::from(8));
+        let (out, proof) = RerandomizationProof::rerandomize(&ek, &c);
+        assert!(out != c);
+        assert!(proof.verify(&ek, &c, &out));
+        // swapped ciphertexts are detected
+        assert!(!proof.verify(&ek, &d, &out));
+        let (swapped, _) = RerandomizationProof::rerandomize(&ek, &d);
+        assert!(!proof.verify(&ek, &c, &swapped));
+        let shifted = ElGamal::add_const(&out, &BigInt::from(1));
+        assert!(!proof.verify(&ek, &c, &shifted));
+        let json = serde_json::to_string(&proof).unwrap();
+        let proof: RerandomizationProof = serde_json::from_str(&json).unwrap();
+        assert!(proof.verify(&ek, &c, &out));
+    }
+}
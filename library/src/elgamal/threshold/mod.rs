@@ -0,0 +1,377 @@
+//! Distributed key generation and threshold decryption for ElGamal on G1.
+//!
+//! Key generation follows Pedersen with Feldman commitments: each of the l
+//! participants deals a random polynomial f_i of degree t-1, broadcasts
+//! a_ik * G for its coefficients and sends f_i(j) privately to participant
+//! j, who checks the share against the commitments. The secret key is the
+//! sum of f_i(0) over the qualified dealers, which nobody ever learns. Each
+//! participant holds x_j = sum_i f_i(j) and publishes x_j * c1 for a
+//! ciphertext together with a Chaum-Pedersen proof that it used the same
+//! x_j as in its verification key x_j * G. Any t valid partials are
+//! combined by Lagrange interpolation in the exponent.
+use bn::{Fr, G1, Group};
+use num_bigint::BigInt;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use serde_derive::{Deserialize, Serialize};
+use super::proofs::DleqProof;
+use super::{ElGamal, ElGamalCiphertext, ElGamalEncryptionKey, ElGamalTable};
+
+/// Commitments a_ik * G of dealer i to its polynomial, broadcast to all
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ElGamalDkgCommitments {
+    pub i: usize,
+    pub c: Vec<G1>,
+}
+
+/// Share f_i(j) of dealer i for participant j, sent privately
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ElGamalDkgShare {
+    pub i: usize,
+    pub j: usize,
+    pub s: Fr,
+}
+
+/// State of participant i (1-based) during key generation
+pub struct ElGamalDkgParticipant {
+    pub i: usize,
+    pub t: usize,
+    pub l: usize,
+    coefficients: Vec<Fr>,
+    /// valid shares received, index k-1 belongs to dealer k
+    received: Vec<Option<Fr>>,
+    complaints: Vec<usize>,
+}
+
+/// Public data of a generated key, needed to verify and combine partials
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ElGamalThresholdKey {
+    /// number of partials needed to decrypt
+    pub t: usize,
+    /// number of participants
+    pub l: usize,
+    /// joint encryption key
+    pub ek: ElGamalEncryptionKey,
+    /// verification keys x_j * G, index j-1 belongs to participant j
+    pub vi: Vec<G1>,
+}
+
+/// Secret key share x_i of participant i
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ElGamalKeyShare {
+    pub i: usize,
+    pub xi: Fr,
+}
+
+/// Partial decryption x_i * c1 of one ciphertext by participant i
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ElGamalPartialDecryption {
+    pub i: usize,
+    pub di: G1,
+    /// proof of correct partial decryption
+    pub proof: DleqProof,
+}
+
+pub struct ThresholdElGamal;
+
+impl ElGamalDkgParticipant {
+    /// Participant i of l with threshold t, None unless 1 <= i <= l and
+    /// 1 <= t <= l
+    pub fn new(i: usize, t: usize, l: usize) -> Option<ElGamalDkgParticipant> {
+        ElGamalDkgParticipant::new_with_rng(i, t, l, &mut OsRng)
+    }
+
+    /// `new` with the polynomial drawn from rng
+    pub fn new_with_rng<R: RngCore + CryptoRng>(
+        i: usize,
+        t: usize,
+        l: usize,
+        rng: &mut R,
+    ) -> Option<ElGamalDkgParticipant> {
+        if i == 0 || i > l || t == 0 || t > l {
+            return None;
+        }
+        let coefficients: Vec<Fr> = (0..t).map(|_| Fr::random_with_rng(rng)).collect();
+        let mut received = vec![None; l];
+        received[i - 1] = Some(eval(&coefficients, i));
+        Some(ElGamalDkgParticipant {
+            i,
+            t,
+            l,
+            coefficients,
+            received,
+            complaints: Vec::new(),
+        })
+    }
+
+    /// Commitments to broadcast
+    pub fn commitments(&self) -> ElGamalDkgCommitments {
+        ElGamalDkgCommitments {
+            i: self.i,
+            c: self.coefficients.iter().map(|a| G1::one() * *a).collect(),
+        }
+    }
+
+    /// Share to send to participant j
+    pub fn share_for(&self, j: usize) -> Option<ElGamalDkgShare> {
+        if j == 0 || j > self.l {
+            return None;
+        }
+        Some(ElGamalDkgShare {
+            i: self.i,
+            j,
+            s: eval(&self.coefficients, j),
+        })
+    }
+
+    /// Checks a share dealt to this participant and keeps it if valid.
+    /// Invalid shares are recorded as complaint against the dealer.
+    pub fn receive(&mut self, commitments: &ElGamalDkgCommitments, share: &ElGamalDkgShare) -> bool {
+        if share.i == 0 || share.i > self.l || share.i == self.i {
+            return false;
+        }
+        if share.j == self.i
+            && commitments.i == share.i
+            && commitments.c.len() == self.t
+            && ThresholdElGamal::verify_share(commitments, share)
+        {
+            self.received[share.i - 1] = Some(share.s);
+            return true;
+        }
+        if !self.complaints.contains(&share.i) {
+            self.complaints.push(share.i);
+        }
+        false
+    }
+
+    /// Dealers whose shares failed verification
+    pub fn complaints(&self) -> &[usize] {
+        &self.complaints
+    }
+
+    /// Key share and public key from the dealers in qualified, whose
+    /// commitments must be among commitments. None if a valid share of a
+    /// qualified dealer is missing.
+    pub fn finish(
+        &self,
+        commitments: &[ElGamalDkgCommitments],
+        qualified: &[usize],
+    ) -> Option<(ElGamalThresholdKey, ElGamalKeyShare)> {
+        let tk = ThresholdElGamal::threshold_key(self.t, self.l, commitments, qualified)?;
+        let mut xi = Fr::zero();
+        for k in qualified {
+            let s: Option<Fr> = *self.received.get(k.checked_sub(1)?)?;
+            xi = xi + s?;
+        }
+        if G1::one() * xi != tk.vi[self.i - 1] {
+            return None;
+        }
+        Some((tk, ElGamalKeyShare { i: self.i, xi }))
+    }
+}
+
+impl ThresholdElGamal {
+    /// Checks s * G = sum_k j^k * c_k for a share against its commitments
+    pub fn verify_share(commitments: &ElGamalDkgCommitments, share: &ElGamalDkgShare) -> bool {
+        !commitments.c.is_empty() && G1::one() * share.s == eval_commitments(&commitments.c, share.j)
+    }
+
+    /// Joint encryption key and verification keys of the qualified dealers,
+    /// None if qualified is empty or a commitment is missing or malformed
+    pub fn threshold_key(
+        t: usize,
+        l: usize,
+        commitments: &[ElGamalDkgCommitments],
+        qualified: &[usize],
+    ) -> Option<ElGamalThresholdKey> {
+        if qualified.is_empty() {
+            return None;
+        }
+        let mut chosen: Vec<&ElGamalDkgCommitments> = Vec::with_capacity(qualified.len());
+        for k in qualified {
+            let c = commitments.iter().find(|c| c.i == *k)?;
+            if c.c.len() != t || chosen.iter().any(|d| d.i == *k) {
+                return None;
+            }
+            chosen.push(c);
+        }
+        let h = chosen.iter().fold(G1::zero(), |acc, c| acc + c.c[0]);
+        let vi = (1..=l)
+            .map(|j| chosen.iter().fold(G1::zero(), |acc, c| acc + eval_commitments(&c.c, j)))
+            .collect();
+        Some(ElGamalThresholdKey {
+            t,
+            l,
+            ek: ElGamalEncryptionKey { h },
+            vi,
+        })
+    }
+
+    /// Computes the partial decryption of c with share and proves it correct
+    pub fn partial_decrypt(share: &ElGamalKeyShare, c: &ElGamalCiphertext) -> ElGamalPartialDecryption {
+        ThresholdElGamal::partial_decrypt_with_rng(share, c, &mut OsRng)
+    }
+
+    /// `partial_decrypt` with randomness drawn from rng
+    pub fn partial_decrypt_with_rng<R: RngCore + CryptoRng>(
+        share: &ElGamalKeyShare,
+        c: &ElGamalCiphertext,
+        rng: &mut R,
+    ) -> ElGamalPartialDecryption {
+        let di = c.c1 * share.xi;
+        let proof = DleqProof::prove_with_rng(&G1::one(), &c.c1, &share.xi, rng);
+        ElGamalPartialDecryption {
+            i: share.i,
+            di,
+            proof,
+        }
+    }
+
+    /// Checks the proof attached to a partial decryption of c
+    pub fn verify_partial(
+        tk: &ElGamalThresholdKey,
+        c: &ElGamalCiphertext,
+        partial: &ElGamalPartialDecryption,
+    ) -> bool {
+        if partial.i == 0 || partial.i > tk.l {
+            return false;
+        }
+        partial
+            .proof
+            .verify(&G1::one(), &tk.vi[partial.i - 1], &c.c1, &partial.di)
+    }
+
+    /// Combines t valid partials of distinct participants to the plaintext
+    /// of c, None if there are not enough valid partials or the plaintext
+    /// is outside the range of table
+    pub fn combine(
+        tk: &ElGamalThresholdKey,
+        c: &ElGamalCiphertext,
+        partials: &[ElGamalPartialDecryption],
+        table: &ElGamalTable,
+    ) -> Option<BigInt> {
+        let mut valid: Vec<&ElGamalPartialDecryption> = Vec::with_capacity(tk.t);
+        for partial in partials {
+            if valid.len() == tk.t {
+                break;
+            }
+            if valid.iter().all(|v| v.i != partial.i) && ThresholdElGamal::verify_partial(tk, c, partial) {
+                valid.push(partial);
+            }
+        }
+        if valid.len() < tk.t {
+            return None;
+        }
+        let indices: Vec<usize> = valid.iter().map(|p| p.i).collect();
+        let mut xc1 = G1::zero();
+        for partial in &valid {
+            xc1 = xc1 + partial.di * ThresholdElGamal::lagrange(&indices, partial.i)?;
+        }
+        table.solve(&(c.c2 - xc1))
+    }
+
+    /// Lagrange coefficient at 0 of index i within indices
+    fn lagrange(indices: &[usize], i: usize) -> Option<Fr> {
+        let mut num = Fr::one();
+        let mut den = Fr::one();
+        for j in indices.iter().filter(|j| **j != i) {
+            num = num * scalar(*j);
+            den = den * (scalar(*j) - scalar(i));
+        }
+        Some(num * den.inverse()?)
+    }
+}
+
+/// f(j) for the polynomial with the given coefficients
+fn eval(coefficients: &[Fr], j: usize) -> Fr {
+    let x = scalar(j);
+    coefficients.iter().rev().fold(Fr::zero(), |acc, a| acc * x + *a)
+}
+
+/// sum_k j^k * c_k
+fn eval_commitments(c: &[G1], j: usize) -> G1 {
+    let x = scalar(j);
+    c.iter().rev().fold(G1::zero(), |acc, ck| acc * x + *ck)
+}
+
+fn scalar(j: usize) -> Fr {
+    ElGamal::encode(&BigInt::from(j))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// runs the DKG among l participants, dealer cheat sends a bad share to
+    /// participant 1
+    fn dkg(t: usize, l: usize, cheat: Option<usize>) -> (ElGamalThresholdKey, Vec<ElGamalKeyShare>, Vec<usize>) {
+        let mut parts: Vec<ElGamalDkgParticipant> =
+            (1..=l).map(|i| ElGamalDkgParticipant::new(i, t, l).unwrap()).collect();
+        let commitments: Vec<ElGamalDkgCommitments> = parts.iter().map(|p| p.commitments()).collect();
+        for i in 0..l {
+            for j in 0..l {
+                if i == j {
+                    continue;
+                }
+                let mut share = parts[i].share_for(j + 1).unwrap();
+                if cheat == Some(i + 1) && j == 0 {
+                    share.s = share.s + Fr::one();
+                }
+                parts[j].receive(&commitments[i], &share);
+            }
+        }
+        let mut qualified: Vec<usize> = (1..=l).collect();
+        for p in &parts {
+            qualified.retain(|k| !p.complaints().contains(k));
+        }
+        let mut tk = None;
+        let mut shares = Vec::new();
+        for p in &parts {
+            let (k, s) = p.finish(&commitments, &qualified).unwrap();
+            tk = Some(k);
+            shares.push(s);
+        }
+        (tk.unwrap(), shares, qualified)
+    }
+
+    #[test]
+    fn dkg_two_of_three() {
+        let (tk, shares, qualified) = dkg(2, 3, None);
+        assert_eq!(qualified, vec![1, 2, 3]);
+        let table = ElGamalTable::new(1 << 16).unwrap();
+        let c = ElGamal::encrypt(&tk.ek, &BigInt::from(-4711));
+        let partials: Vec<ElGamalPartialDecryption> =
+            shares.iter().map(|s| ThresholdElGamal::partial_decrypt(s, &c)).collect();
+        for partial in &partials {
+            assert!(ThresholdElGamal::verify_partial(&tk, &c, partial));
+        }
+        for pair in [[0, 1], [0, 2], [2, 1]].iter() {
+            let chosen = vec![partials[pair[0]].clone(), partials[pair[1]].clone()];
+            assert_eq!(ThresholdElGamal::combine(&tk, &c, &chosen, &table), Some(BigInt::from(-4711)));
+        }
+        // a single partial is not enough, neither are duplicates
+        assert!(ThresholdElGamal::combine(&tk, &c, &partials[..1], &table).is_none());
+        let dup = vec![partials[0].clone(), partials[0].clone()];
+        assert!(ThresholdElGamal::combine(&tk, &c, &dup, &table).is_none());
+    }
+
+    #[test]
+    fn dkg_rejects_bad_shares_and_partials() {
+        let (tk, shares, qualified) = dkg(2, 4, Some(3));
+        assert_eq!(qualified, vec![1, 2, 4]);
+        let table = ElGamalTable::new(1 << 16).unwrap();
+        let c = ElGamal::encrypt(&tk.ek, &BigInt::from(99));
+        let mut forged = ThresholdElGamal::partial_decrypt(&shares[0], &c);
+        forged.di = forged.di + G1::one();
+        assert!(!ThresholdElGamal::verify_partial(&tk, &c, &forged));
+        let mut wrong_index = ThresholdElGamal::partial_decrypt(&shares[0], &c);
+        wrong_index.i = 2;
+        assert!(!ThresholdElGamal::verify_partial(&tk, &c, &wrong_index));
+        let partials = vec![
+            forged,
+            ThresholdElGamal::partial_decrypt(&shares[1], &c),
+            ThresholdElGamal::partial_decrypt(&shares[3], &c),
+        ];
+        assert_eq!(ThresholdElGamal::combine(&tk, &c, &partials, &table), Some(BigInt::from(99)));
+    }
+}
@@ -0,0 +1,101 @@
+//! Errors of the scheme, the Paillier cryptosystem and the code tree
+use ::hope::protocol::response::ProtocolResult;
+use bson::oid::ObjectId;
+use std::error::Error;
+use std::fmt;
+
+/// Reasons an operation of `hope`, `Paillier` or `Tree` failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum HopeError {
+    /// the instance has no keypair
+    MissingKey,
+    /// the keypair has no decryption key
+    MissingDecryptionKey,
+    /// no primes of the requested size were found
+    KeyGeneration,
+    /// the key is too small for the operation
+    KeyTooSmall { bits: usize, required: usize },
+    /// the plaintext cannot be encoded or decrypted
+    PlaintextOutOfRange(String),
+    /// the value has no inverse modulo n or n^2
+    NotInvertible,
+    /// the key does not fit together or does not fit the operation
+    InvalidKey(String),
+    /// the divisor is zero
+    DivisionByZero,
+    /// the client is not connected or hung up
+    ClientUnavailable,
+    /// the client did not answer in time
+    ClientTimeout,
+    /// the answer of the client is not a valid response to the request
+    MalformedResponse(String),
+    /// the client answered with an error
+    Client(String),
+    /// the client could not compare two ciphertexts
+    Comparison,
+    /// no leaf with this id in the code tree
+    TreeMiss(ObjectId),
+    /// the tree refuses inserts while a key rotation is running
+    RotationInProgress,
+    /// the code tree is too deep for 64 bit order codes
+    CodeOverflow { depth: usize, levels: usize },
+    /// the token already belongs to another ciphertext
+    AplCollision { existing: ObjectId, new: ObjectId },
+    /// the ciphertext could not be read from or written to storage
+    Storage(String),
+    /// a value could not be serialized
+    Serialization(String),
+    /// the arguments do not fit together
+    InvalidArgument(String),
+}
+
+impl fmt::Display for HopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HopeError::MissingKey => write!(f, "no key"),
+            HopeError::MissingDecryptionKey => write!(f, "no decryption key"),
+            HopeError::KeyGeneration => write!(f, "key generation failed"),
+            HopeError::KeyTooSmall { bits, required } => {
+                write!(f, "key of {} bits is too small, {} bits required", bits, required)
+            }
+            HopeError::PlaintextOutOfRange(m) => write!(f, "plaintext out of range: {}", m),
+            HopeError::NotInvertible => write!(f, "value is not invertible"),
+            HopeError::InvalidKey(s) => write!(f, "invalid key: {}", s),
+            HopeError::DivisionByZero => write!(f, "division by zero"),
+            HopeError::ClientUnavailable => write!(f, "client unavailable"),
+            HopeError::ClientTimeout => write!(f, "client did not answer in time"),
+            HopeError::MalformedResponse(s) => write!(f, "malformed response: {}", s),
+            HopeError::Client(s) => write!(f, "client error: {}", s),
+            HopeError::Comparison => write!(f, "comparison failed"),
+            HopeError::TreeMiss(id) => write!(f, "no leaf {} in the tree", id),
+            HopeError::RotationInProgress => write!(f, "key rotation in progress"),
+            HopeError::CodeOverflow { depth, levels } => {
+                write!(f, "tree of depth {} exceeds the {} levels of a code", depth, levels)
+            }
+            HopeError::AplCollision { existing, new } => {
+                write!(f, "token of {} already belongs to {}", new, existing)
+            }
+            HopeError::Storage(s) => write!(f, "storage error: {}", s),
+            HopeError::Serialization(s) => write!(f, "serialization error: {}", s),
+            HopeError::InvalidArgument(s) => write!(f, "invalid argument: {}", s),
+        }
+    }
+}
+
+impl Error for HopeError {}
+
+impl From<serde_json::Error> for HopeError {
+    fn from(e: serde_json::Error) -> HopeError {
+        HopeError::Serialization(e.to_string())
+    }
+}
+
+impl From<ProtocolResult> for HopeError {
+    /// error for a result the client should not have sent
+    fn from(res: ProtocolResult) -> HopeError {
+        match res {
+            ProtocolResult::Error(s) => HopeError::Client(s),
+            _ => HopeError::MalformedResponse(String::from("unexpected result")),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Common interface of the additively homomorphic schemes.
+//!
+//! `hope` stores its ciphertexts through this trait, so a column can pick
+//! Paillier (large ciphertexts, arbitrary plaintexts, fast decryption) or
+//! exponential EC-ElGamal on G1 (two points per ciphertext, small
+//! plaintexts, decryption by baby-step giant-step).
+use crate::error::HopeError;
+use crate::elgamal::{ElGamal, ElGamalCiphertext, ElGamalDecryptor, ElGamalEncryptionKey};
+use crate::paillier::Paillier;
+use ::hope::protocol::model::{PaillierDecryptionKey, PaillierEncryptionKey};
+use num_bigint::BigInt;
+use rand_core::{CryptoRng, OsRng, RngCore};
+
+/// Additively homomorphic public key encryption of signed integers
+pub trait AdditiveHomomorphic {
+    type EncryptionKey: Clone;
+    type DecryptionKey: Clone;
+    type Ciphertext: Clone;
+
+    /// Encryption of m, which may be negative
+    fn encrypt(ek: &Self::EncryptionKey, m: &BigInt) -> Self::Ciphertext {
+        Self::encrypt_with_rng(ek, m, &mut OsRng)
+    }
+
+    /// `encrypt` with randomness drawn from rng
+    fn encrypt_with_rng<R: RngCore + CryptoRng>(
+        ek: &Self::EncryptionKey,
+        m: &BigInt,
+        rng: &mut R,
+    ) -> Self::Ciphertext;
+
+    /// Plaintext of c, `PlaintextOutOfRange` if the scheme cannot recover it
+    fn decrypt(dk: &Self::DecryptionKey, ek: &Self::EncryptionKey, c: &Self::Ciphertext) -> Result<BigInt, HopeError>;
+
+    /// Encryption of m1 + m2
+    fn add(ek: &Self::EncryptionKey, c1: &Self::Ciphertext, c2: &Self::Ciphertext) -> Self::Ciphertext;
+
+    /// Encryption of m1 - m2
+    fn sub(ek: &Self::EncryptionKey, c1: &Self::Ciphertext, c2: &Self::Ciphertext) -> Result<Self::Ciphertext, HopeError>;
+
+    /// Encryption of m1 + m
+    fn add_const(ek: &Self::EncryptionKey, c: &Self::Ciphertext, m: &BigInt) -> Self::Ciphertext;
+
+    /// Encryption of m1 * m
+    fn mul_const(ek: &Self::EncryptionKey, c: &Self::Ciphertext, m: &BigInt) -> Self::Ciphertext;
+
+    /// Fresh encryption of the plaintext of c
+    fn rerandomize(ek: &Self::EncryptionKey, c: &Self::Ciphertext) -> Self::Ciphertext {
+        Self::rerandomize_with_rng(ek, c, &mut OsRng)
+    }
+
+    /// `rerandomize` with randomness drawn from rng
+    fn rerandomize_with_rng<R: RngCore + CryptoRng>(
+        ek: &Self::EncryptionKey,
+        c: &Self::Ciphertext,
+        rng: &mut R,
+    ) -> Self::Ciphertext;
+}
+
+impl AdditiveHomomorphic for Paillier {
+    type EncryptionKey = PaillierEncryptionKey;
+    type DecryptionKey = PaillierDecryptionKey;
+    type Ciphertext = BigInt;
+
+    fn encrypt_with_rng<R: RngCore + CryptoRng>(ek: &PaillierEncryptionKey, m: &BigInt, rng: &mut R) -> BigInt {
+        Paillier::encrypt_with_rng(ek, m, rng)
+    }
+
+    fn decrypt(dk: &PaillierDecryptionKey, ek: &PaillierEncryptionKey, c: &BigInt) -> Result<BigInt, HopeError> {
+        Ok(Paillier::decrypt(dk, ek, c))
+    }
+
+    fn add(ek: &PaillierEncryptionKey, c1: &BigInt, c2: &BigInt) -> BigInt {
+        Paillier::add(ek, c1, c2)
+    }
+
+    fn sub(ek: &PaillierEncryptionKey, c1: &BigInt, c2: &BigInt) -> Result<BigInt, HopeError> {
+        Paillier::sub(ek, c1, c2)
+    }
+
+    fn add_const(ek: &PaillierEncryptionKey, c: &BigInt, m: &BigInt) -> BigInt {
+        Paillier::add_const(ek, c, m)
+    }
+
+    fn mul_const(ek: &PaillierEncryptionKey, c: &BigInt, m: &BigInt) -> BigInt {
+        Paillier::mul_const(ek, c, m)
+    }
+
+    fn rerandomize_with_rng<R: RngCore + CryptoRng>(ek: &PaillierEncryptionKey, c: &BigInt, rng: &mut R) -> BigInt {
+        Paillier::rerandomize_with_rng(ek, c, rng)
+    }
+}
+
+impl AdditiveHomomorphic for ElGamal {
+    type EncryptionKey = ElGamalEncryptionKey;
+    type DecryptionKey = ElGamalDecryptor;
+    type Ciphertext = ElGamalCiphertext;
+
+    fn encrypt_with_rng<R: RngCore + CryptoRng>(
+        ek: &ElGamalEncryptionKey,
+        m: &BigInt,
+        rng: &mut R,
+    ) -> ElGamalCiphertext {
+        ElGamal::encrypt_with_rng(ek, m, rng)
+    }
+
+    fn decrypt(dk: &ElGamalDecryptor, _ek: &ElGamalEncryptionKey, c: &ElGamalCiphertext) -> Result<BigInt, HopeError> {
+        dk.decrypt(c)
+            .ok_or_else(|| HopeError::PlaintextOutOfRange(format!("beyond +-{}", dk.table.bound())))
+    }
+
+    fn add(_ek: &ElGamalEncryptionKey, c1: &ElGamalCiphertext, c2: &ElGamalCiphertext) -> ElGamalCiphertext {
+        ElGamal::add(c1, c2)
+    }
+
+    fn sub(
+        _ek: &ElGamalEncryptionKey,
+        c1: &ElGamalCiphertext,
+        c2: &ElGamalCiphertext,
+    ) -> Result<ElGamalCiphertext, HopeError> {
+        Ok(ElGamal::sub(c1, c2))
+    }
+
+    fn add_const(_ek: &ElGamalEncryptionKey, c: &ElGamalCiphertext, m: &BigInt) -> ElGamalCiphertext {
+        ElGamal::add_const(c, m)
+    }
+
+    fn mul_const(_ek: &ElGamalEncryptionKey, c: &ElGamalCiphertext, m: &BigInt) -> ElGamalCiphertext {
+        ElGamal::mul_const(c, m)
+    }
+
+    fn rerandomize_with_rng<R: RngCore + CryptoRng>(
+        ek: &ElGamalEncryptionKey,
+        c: &ElGamalCiphertext,
+        rng: &mut R,
+    ) -> ElGamalCiphertext {
+        ElGamal::rerandomize_with_rng(ek, c, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// (m1 - m2) * 3 + 5 through the trait only
+    fn combine<S: AdditiveHomomorphic>(ek: &S::EncryptionKey, m1: i64, m2: i64) -> S::Ciphertext {
+        let c1 = S::encrypt(ek, &BigInt::from(m1));
+        let c2 = S::encrypt(ek, &BigInt::from(m2));
+        let c = S::sub(ek, &c1, &c2).unwrap();
+        let c = S::add_const(ek, &S::mul_const(ek, &c, &BigInt::from(3)), &BigInt::from(5));
+        S::rerandomize(ek, &S::add(ek, &c, &S::encrypt(ek, &BigInt::from(0))))
+    }
+
+    #[test]
+    fn paillier_and_elgamal_backends() {
+        let (ek, dk) = crate::paillier::test_keys();
+        let c = combine::<Paillier>(&ek, 17, 40);
+        assert_eq!(<Paillier as AdditiveHomomorphic>::decrypt(&dk, &ek, &c), Ok(BigInt::from(-64)));
+        let (ek, dk) = ElGamal::keygen();
+        let dk = ElGamalDecryptor::new(dk, 1 << 12).unwrap();
+        let c = combine::<ElGamal>(&ek, 17, 40);
+        assert_eq!(<ElGamal as AdditiveHomomorphic>::decrypt(&dk, &ek, &c), Ok(BigInt::from(-64)));
+        let c = <ElGamal as AdditiveHomomorphic>::encrypt(&ek, &BigInt::from(1 << 13));
+        assert!(<ElGamal as AdditiveHomomorphic>::decrypt(&dk, &ek, &c).is_err());
+    }
+}
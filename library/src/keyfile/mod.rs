@@ -0,0 +1,650 @@
+//! Armored key files for PAILLIER and ehOPE keys
+//!
+//! Keys are written as JSON, base64 encoded and wrapped into a text block
+//! that can be copied, mailed and stored next to each other:
+//!
+//! ```text
+//! -----BEGIN HOPE PAILLIER PUBLIC KEY-----
+//! Version: 1
+//! Key-Id: 3f6c0d9a5e1b7c2400d1e8a9b6f7c3e2
+//!
+//! eyJuIjpbMSxbMTI...
+//! -----END HOPE PAILLIER PUBLIC KEY-----
+//! ```
+//!
+//! The `Key-Id` is a fingerprint of the public part. A secret key block
+//! carries the id of its public key, so a mismatched pair is detected on load.
+//! Loaded keys are checked for consistency as well, and secret blocks are
+//! written to files only their owner can read.
+extern crate base64;
+extern crate serde_json;
+
+use ::hope::protocol::model::*;
+use crate::paillier::Paillier;
+use bn::{G1, G2, Group};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::One;
+use sha2::{Digest, Sha256};
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+/// current version of the armored format
+pub const KEYFILE_VERSION: u32 = 1;
+/// number of base64 characters per armored line
+const LINE_WIDTH: usize = 64;
+/// number of fingerprint bytes shown in the Key-Id header
+const KEY_ID_BYTES: usize = 16;
+
+/// Kind of key stored in an armored block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyKind {
+    /// a PaillierEncryptionKey
+    PaillierPublic,
+    /// a PaillierDecryptionKey
+    PaillierSecret,
+    /// the public part of a hopeK
+    HopePublic,
+    /// the secret part of a hopeK
+    HopeSecret,
+    /// a hopeSP
+    HopeParameters,
+}
+
+impl KeyKind {
+    /// label used in the BEGIN and END lines
+    pub fn label(&self) -> &'static str {
+        match self {
+            KeyKind::PaillierPublic => "HOPE PAILLIER PUBLIC KEY",
+            KeyKind::PaillierSecret => "HOPE PAILLIER SECRET KEY",
+            KeyKind::HopePublic => "HOPE PUBLIC KEY",
+            KeyKind::HopeSecret => "HOPE SECRET KEY",
+            KeyKind::HopeParameters => "HOPE SYSTEM PARAMETERS",
+        }
+    }
+
+    fn from_label(_label: &str) -> Option<KeyKind> {
+        [
+            KeyKind::PaillierPublic,
+            KeyKind::PaillierSecret,
+            KeyKind::HopePublic,
+            KeyKind::HopeSecret,
+            KeyKind::HopeParameters,
+        ]
+            .iter()
+            .find(|k| k.label() == _label)
+            .cloned()
+    }
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+/// Errors raised while reading or writing key files
+#[derive(Debug)]
+pub enum KeyFileError {
+    /// the file could not be read or written
+    Io(io::Error),
+    /// the text is not a well formed armored block
+    Malformed(String),
+    /// the block was written by an unknown version of the format
+    UnsupportedVersion(u32),
+    /// the block holds a different kind of key than requested
+    WrongKind { expected: KeyKind, found: KeyKind },
+    /// the body is not valid base64
+    Base64(base64::DecodeError),
+    /// the decoded body is not a valid key
+    Json(serde_json::Error),
+    /// the Key-Id header does not match the key (or its public key)
+    KeyIdMismatch { expected: String, found: String },
+    /// the key is inconsistent, or the secret key does not fit its public key
+    InvalidKey(String),
+}
+
+impl fmt::Display for KeyFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyFileError::Io(e) => write!(f, "key file i/o error: {}", e),
+            KeyFileError::Malformed(s) => write!(f, "malformed key file: {}", s),
+            KeyFileError::UnsupportedVersion(v) => {
+                write!(f, "unsupported key file version {}", v)
+            }
+            KeyFileError::WrongKind { expected, found } => {
+                write!(f, "expected a {} but found a {}", expected, found)
+            }
+            KeyFileError::Base64(e) => write!(f, "invalid key file encoding: {}", e),
+            KeyFileError::Json(e) => write!(f, "invalid key: {}", e),
+            KeyFileError::KeyIdMismatch { expected, found } => {
+                write!(f, "key id mismatch: expected {} but found {}", expected, found)
+            }
+            KeyFileError::InvalidKey(s) => write!(f, "invalid key: {}", s),
+        }
+    }
+}
+
+impl Error for KeyFileError {}
+
+impl From<io::Error> for KeyFileError {
+    fn from(e: io::Error) -> KeyFileError {
+        KeyFileError::Io(e)
+    }
+}
+
+impl From<base64::DecodeError> for KeyFileError {
+    fn from(e: base64::DecodeError) -> KeyFileError {
+        KeyFileError::Base64(e)
+    }
+}
+
+impl From<serde_json::Error> for KeyFileError {
+    fn from(e: serde_json::Error) -> KeyFileError {
+        KeyFileError::Json(e)
+    }
+}
+
+/// A decoded armored block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Armored {
+    /// kind of key in the body
+    pub kind: KeyKind,
+    /// format version
+    pub version: u32,
+    /// fingerprint of the (public) key
+    pub key_id: String,
+    /// raw body, usually JSON
+    pub body: Vec<u8>,
+}
+
+/// Wraps a body into an armored block
+pub fn armor(_kind: KeyKind, _key_id: &str, _body: &[u8]) -> String {
+    let encoded = base64::encode(_body);
+    let mut out = format!("-----BEGIN {}-----\n", _kind.label());
+    out.push_str(&format!("Version: {}\n", KEYFILE_VERSION));
+    out.push_str(&format!("Key-Id: {}\n\n", _key_id));
+    for line in encoded.as_bytes().chunks(LINE_WIDTH) {
+        out.push_str(std::str::from_utf8(line).unwrap());
+        out.push('\n');
+    }
+    out.push_str(&format!("-----END {}-----\n", _kind.label()));
+    out
+}
+
+/// Parses an armored block
+pub fn dearmor(_text: &str) -> Result<Armored, KeyFileError> {
+    let mut lines = _text.lines().map(|l| l.trim()).skip_while(|l| l.is_empty());
+    let begin = lines
+        .next()
+        .ok_or_else(|| KeyFileError::Malformed(String::from("empty input")))?;
+    let label = begin
+        .strip_prefix("-----BEGIN ")
+        .and_then(|l| l.strip_suffix("-----"))
+        .ok_or_else(|| KeyFileError::Malformed(String::from("missing BEGIN line")))?;
+    let kind = KeyKind::from_label(label)
+        .ok_or_else(|| KeyFileError::Malformed(format!("unknown block '{}'", label)))?;
+    let mut version = None;
+    let mut key_id = None;
+    for line in &mut lines {
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts
+            .next()
+            .ok_or_else(|| KeyFileError::Malformed(format!("bad header '{}'", line)))?
+            .trim();
+        match name {
+            "Version" => {
+                version = Some(value.parse::<u32>().map_err(|_| {
+                    KeyFileError::Malformed(format!("bad version '{}'", value))
+                })?)
+            }
+            "Key-Id" => key_id = Some(value.to_lowercase()),
+            _ => {}
+        }
+    }
+    let version = version
+        .ok_or_else(|| KeyFileError::Malformed(String::from("missing Version header")))?;
+    if version != KEYFILE_VERSION {
+        return Err(KeyFileError::UnsupportedVersion(version));
+    }
+    let key_id = key_id
+        .ok_or_else(|| KeyFileError::Malformed(String::from("missing Key-Id header")))?;
+    let end = format!("-----END {}-----", label);
+    let mut encoded = String::new();
+    let mut closed = false;
+    for line in &mut lines {
+        if line == end {
+            closed = true;
+            break;
+        }
+        encoded.push_str(line);
+    }
+    if !closed {
+        return Err(KeyFileError::Malformed(String::from("missing END line")));
+    }
+    Ok(Armored {
+        kind,
+        version,
+        key_id,
+        body: base64::decode(&encoded)?,
+    })
+}
+
+/// Fingerprint of a PAILLIER public key
+pub fn paillier_key_id(_ek: &PaillierEncryptionKey) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(b"hope-paillier-ek");
+    input_bigint(&mut hasher, &_ek.n);
+    key_id(hasher)
+}
+
+/// Fingerprint of ehOPE system parameters
+pub fn parameters_key_id(_sp: &hopeSP) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(b"hope-sp");
+    input_bytes(&mut hasher, _sp._name.as_bytes());
+    input_bytes(&mut hasher, &g1_bytes(&_sp._p));
+    input_bytes(&mut hasher, &g2_bytes(&_sp._q));
+    key_id(hasher)
+}
+
+/// Armors a PAILLIER public key
+pub fn armor_paillier_ek(_ek: &PaillierEncryptionKey) -> String {
+    armor(
+        KeyKind::PaillierPublic,
+        &paillier_key_id(_ek),
+        &serde_json::to_vec(_ek).unwrap(),
+    )
+}
+
+/// Armors a PAILLIER secret key, tagged with the id of its public key
+pub fn armor_paillier_dk(_ek: &PaillierEncryptionKey, _dk: &PaillierDecryptionKey) -> String {
+    armor(
+        KeyKind::PaillierSecret,
+        &paillier_key_id(_ek),
+        &serde_json::to_vec(_dk).unwrap(),
+    )
+}
+
+/// Armors the public part of an ehOPE key
+pub fn armor_hope_public(_k: &hopeK) -> String {
+    let public: hopeK = hopeK {
+        _dk: None,
+        _ek: _k._ek.clone(),
+    };
+    armor(
+        KeyKind::HopePublic,
+        &paillier_key_id(&_k._ek),
+        &serde_json::to_vec(&public).unwrap(),
+    )
+}
+
+/// Armors the secret part of an ehOPE key, if it holds one
+pub fn armor_hope_secret(_k: &hopeK) -> Option<String> {
+    _k._dk.as_ref().map(|dk| {
+        armor(
+            KeyKind::HopeSecret,
+            &paillier_key_id(&_k._ek),
+            &serde_json::to_vec(dk).unwrap(),
+        )
+    })
+}
+
+/// Armors ehOPE system parameters
+pub fn armor_parameters(_sp: &hopeSP) -> String {
+    armor(
+        KeyKind::HopeParameters,
+        &parameters_key_id(_sp),
+        &serde_json::to_vec(_sp).unwrap(),
+    )
+}
+
+/// Reads a PAILLIER public key and checks its fingerprint
+pub fn dearmor_paillier_ek(_text: &str) -> Result<PaillierEncryptionKey, KeyFileError> {
+    let (block, ek) = decode::<PaillierEncryptionKey>(_text, KeyKind::PaillierPublic)?;
+    check_key_id(&block.key_id, &paillier_key_id(&ek))?;
+    check_paillier_ek(&ek)?;
+    Ok(ek)
+}
+
+/// Reads a PAILLIER secret key; if `_ek` is given the pair is checked
+/// and the key must decrypt under `_ek`. Without `_ek` the key is only
+/// decoded, neither its Key-Id nor its consistency are checked.
+pub fn dearmor_paillier_dk(
+    _text: &str,
+    _ek: Option<&PaillierEncryptionKey>,
+) -> Result<PaillierDecryptionKey, KeyFileError> {
+    let (block, dk) = decode::<PaillierDecryptionKey>(_text, KeyKind::PaillierSecret)?;
+    if let Some(ek) = _ek {
+        check_key_id(&block.key_id, &paillier_key_id(ek))?;
+        check_paillier_ek(ek)?;
+        check_paillier_dk(ek, &dk)?;
+    }
+    Ok(dk)
+}
+
+/// Reads an ehOPE key from its public block and an optional secret block
+pub fn dearmor_hope_key(_public: &str, _secret: Option<&str>) -> Result<hopeK, KeyFileError> {
+    let (block, mut k) = decode::<hopeK>(_public, KeyKind::HopePublic)?;
+    check_key_id(&block.key_id, &paillier_key_id(&k._ek))?;
+    check_paillier_ek(&k._ek)?;
+    k._dk = match _secret {
+        Some(text) => {
+            let (block, dk) = decode::<PaillierDecryptionKey>(text, KeyKind::HopeSecret)?;
+            check_key_id(&block.key_id, &paillier_key_id(&k._ek))?;
+            check_paillier_dk(&k._ek, &dk)?;
+            Some(dk)
+        }
+        None => None,
+    };
+    Ok(k)
+}
+
+/// Reads ehOPE system parameters and checks their fingerprint
+pub fn dearmor_parameters(_text: &str) -> Result<hopeSP, KeyFileError> {
+    let (block, sp) = decode::<hopeSP>(_text, KeyKind::HopeParameters)?;
+    check_key_id(&block.key_id, &parameters_key_id(&sp))?;
+    Ok(sp)
+}
+
+/// Writes an armored block to a file, see `write_secret_keyfile` for
+/// secret keys
+pub fn write_keyfile<P: AsRef<Path>>(_path: P, _armored: &str) -> Result<(), KeyFileError> {
+    let mut file = File::create(_path)?;
+    file.write_all(_armored.as_bytes())?;
+    Ok(())
+}
+
+/// Writes an armored secret block to a file that only its owner can read
+/// and write (mode 0600 on unix), also if the file exists already
+pub fn write_secret_keyfile<P: AsRef<Path>>(_path: P, _armored: &str) -> Result<(), KeyFileError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(_path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(_armored.as_bytes())?;
+    Ok(())
+}
+
+/// Reads an armored block from a file
+pub fn read_keyfile<P: AsRef<Path>>(_path: P) -> Result<String, KeyFileError> {
+    let mut text = String::new();
+    File::open(_path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+fn decode<T: DeserializeOwned>(_text: &str, _kind: KeyKind) -> Result<(Armored, T), KeyFileError> {
+    let block = dearmor(_text)?;
+    if block.kind != _kind {
+        return Err(KeyFileError::WrongKind {
+            expected: _kind,
+            found: block.kind,
+        });
+    }
+    let value = serde_json::from_slice(&block.body)?;
+    Ok((block, value))
+}
+
+/// n2 = n^2 and g = n + 1, the generator of every key of `Paillier`
+fn check_paillier_ek(_ek: &PaillierEncryptionKey) -> Result<(), KeyFileError> {
+    if _ek.n <= BigInt::one() || _ek.n2 != &_ek.n * &_ek.n || _ek.g != &_ek.n + BigInt::one() {
+        return Err(KeyFileError::InvalidKey(String::from("n2 or g do not belong to n")));
+    }
+    Ok(())
+}
+
+/// L(g^lambda mod n^2) * mu = 1 mod n, and if the factors are given
+/// p*q = n and the CRT constants are the ones derived from p and q
+fn check_paillier_dk(_ek: &PaillierEncryptionKey, _dk: &PaillierDecryptionKey) -> Result<(), KeyFileError> {
+    if let Some(crt) = &_dk.crt {
+        if crt.p <= BigInt::one() || crt.q <= BigInt::one() || &crt.p * &crt.q != _ek.n {
+            return Err(KeyFileError::InvalidKey(String::from("p*q is not n")));
+        }
+        let _crt = Paillier::try_crt_key(_ek, &crt.p, &crt.q)
+            .map_err(|_| KeyFileError::InvalidKey(String::from("p and q have no CRT constants")))?;
+        if crt.p2 != _crt.p2 || crt.q2 != _crt.q2 || crt.hp != _crt.hp || crt.hq != _crt.hq || crt.qinv != _crt.qinv {
+            return Err(KeyFileError::InvalidKey(String::from("the CRT constants do not belong to p and q")));
+        }
+    }
+    let _l = (_ek.g.modpow(&_dk.lambda, &_ek.n2) - BigInt::one()) / &_ek.n;
+    if !(_l * &_dk.mu).mod_floor(&_ek.n).is_one() {
+        return Err(KeyFileError::InvalidKey(String::from("the secret key does not decrypt under n")));
+    }
+    Ok(())
+}
+
+fn check_key_id(_found: &str, _expected: &str) -> Result<(), KeyFileError> {
+    if _found == _expected {
+        Ok(())
+    } else {
+        Err(KeyFileError::KeyIdMismatch {
+            expected: _expected.to_string(),
+            found: _found.to_string(),
+        })
+    }
+}
+
+fn key_id(_hasher: Sha256) -> String {
+    _hasher.result()[..KEY_ID_BYTES]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn input_bytes(_hasher: &mut Sha256, _bytes: &[u8]) {
+    _hasher.input((_bytes.len() as u64).to_be_bytes());
+    _hasher.input(_bytes);
+}
+
+fn input_bigint(_hasher: &mut Sha256, _n: &BigInt) {
+    input_bytes(_hasher, &_n.to_signed_bytes_be());
+}
+
+/// group elements are serialized in jacobian coordinates, so the same point
+/// has many encodings; normalize first to get a stable fingerprint
+fn g1_bytes(_g: &G1) -> Vec<u8> {
+    canonical(_g)
+}
+
+fn g2_bytes(_g: &G2) -> Vec<u8> {
+    canonical(_g)
+}
+
+fn canonical<G: Group>(_g: &G) -> Vec<u8> {
+    if _g.is_zero() {
+        return vec![0];
+    }
+    let mut g = *_g;
+    g.normalize();
+    serde_json::to_vec(&g).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::paillier::test_keys;
+    use bn::Fr;
+    use bson::oid::ObjectId;
+
+    #[test]
+    fn keyfile_paillier_roundtrip() {
+        let (ek, dk) = test_keys();
+        let public = armor_paillier_ek(&ek);
+        let secret = armor_paillier_dk(&ek, &dk);
+        assert!(public.starts_with("-----BEGIN HOPE PAILLIER PUBLIC KEY-----\n"));
+        assert!(public.lines().all(|l| l.len() <= LINE_WIDTH || l.starts_with("-----")));
+        let ek2 = dearmor_paillier_ek(&public).unwrap();
+        let dk2 = dearmor_paillier_dk(&secret, Some(&ek2)).unwrap();
+        assert_eq!(ek2.n, ek.n);
+        assert_eq!(dk2.lambda, dk.lambda);
+        assert_eq!(dearmor(&public).unwrap().key_id, paillier_key_id(&ek));
+        assert_eq!(dearmor(&secret).unwrap().key_id, paillier_key_id(&ek));
+    }
+
+    #[test]
+    fn keyfile_rejects_tampering() {
+        let (ek, dk) = test_keys();
+        let public = armor_paillier_ek(&ek);
+        // wrong kind
+        match dearmor_paillier_dk(&public, None) {
+            Err(KeyFileError::WrongKind { .. }) => {}
+            _ => panic!("expected WrongKind"),
+        }
+        // edited Key-Id
+        let id = paillier_key_id(&ek);
+        let edited = public.replace(&id, &"0".repeat(id.len()));
+        match dearmor_paillier_ek(&edited) {
+            Err(KeyFileError::KeyIdMismatch { .. }) => {}
+            _ => panic!("expected KeyIdMismatch"),
+        }
+        // secret key of another pair
+        let other = Paillier::keygen_from_primes(
+            &BigInt::parse_bytes(b"2147483647", 10).unwrap(),
+            &BigInt::parse_bytes(b"2305843009213693951", 10).unwrap(),
+        );
+        let secret = armor_paillier_dk(&other.0, &other.1);
+        assert!(dearmor_paillier_dk(&secret, None).is_ok());
+        match dearmor_paillier_dk(&secret, Some(&ek)) {
+            Err(KeyFileError::KeyIdMismatch { .. }) => {}
+            _ => panic!("expected KeyIdMismatch"),
+        }
+        // unknown version and truncated block
+        let future = armor_paillier_dk(&ek, &dk).replace("Version: 1", "Version: 2");
+        match dearmor(&future) {
+            Err(KeyFileError::UnsupportedVersion(2)) => {}
+            _ => panic!("expected UnsupportedVersion"),
+        }
+        let truncated: String = public.lines().take(4).collect::<Vec<_>>().join("\n");
+        match dearmor(&truncated) {
+            Err(KeyFileError::Malformed(_)) => {}
+            _ => panic!("expected Malformed"),
+        }
+    }
+
+    #[test]
+    fn keyfile_checks_keys() {
+        let (ek, dk) = test_keys();
+        let mut bad_g = ek.clone();
+        bad_g.g = &ek.g + BigInt::one();
+        match dearmor_paillier_ek(&armor_paillier_ek(&bad_g)) {
+            Err(KeyFileError::InvalidKey(_)) => {}
+            _ => panic!("expected InvalidKey"),
+        }
+        let mut bad_mu = dk.clone();
+        bad_mu.mu = &dk.mu + BigInt::one();
+        match dearmor_paillier_dk(&armor_paillier_dk(&ek, &bad_mu), Some(&ek)) {
+            Err(KeyFileError::InvalidKey(_)) => {}
+            _ => panic!("expected InvalidKey"),
+        }
+        let mut bad_crt = dk.clone();
+        if let Some(crt) = bad_crt.crt.as_mut() {
+            crt.q = &crt.q + BigInt::from(2);
+        }
+        let k = hopeK {
+            _dk: Some(bad_crt),
+            _ek: ek.clone(),
+        };
+        match dearmor_hope_key(&armor_hope_public(&k), armor_hope_secret(&k).as_deref()) {
+            Err(KeyFileError::InvalidKey(_)) => {}
+            _ => panic!("expected InvalidKey"),
+        }
+        // every CRT constant is recomputed from p and q
+        let edits: [fn(&mut PaillierCrtKey); 5] = [
+            |crt| crt.p2 = &crt.p2 + BigInt::one(),
+            |crt| crt.q2 = &crt.q2 + BigInt::one(),
+            |crt| crt.hp = &crt.hp + BigInt::one(),
+            |crt| crt.hq = &crt.hq + BigInt::one(),
+            |crt| crt.qinv = &crt.qinv + BigInt::one(),
+        ];
+        for edit in edits.iter() {
+            let mut bad_const = dk.clone();
+            if let Some(crt) = bad_const.crt.as_mut() {
+                edit(crt);
+            }
+            match dearmor_paillier_dk(&armor_paillier_dk(&ek, &bad_const), Some(&ek)) {
+                Err(KeyFileError::InvalidKey(_)) => {}
+                _ => panic!("expected InvalidKey"),
+            }
+        }
+    }
+
+    #[test]
+    fn keyfile_hope_key() {
+        let (ek, dk) = test_keys();
+        let k = hopeK {
+            _dk: Some(dk.clone()),
+            _ek: ek.clone(),
+        };
+        let public = armor_hope_public(&k);
+        let secret = armor_hope_secret(&k).unwrap();
+        assert!(!public.contains(&base64::encode(&serde_json::to_vec(&dk).unwrap())));
+        let only_public = dearmor_hope_key(&public, None).unwrap();
+        assert!(only_public._dk.is_none());
+        let full = dearmor_hope_key(&public, Some(&secret)).unwrap();
+        assert_eq!(full._dk.unwrap().mu, dk.mu);
+        assert!(armor_hope_secret(&only_public).is_none());
+    }
+
+    #[test]
+    fn keyfile_parameters() {
+        let _p = G1::one() * Fr::from_str("7").unwrap();
+        let sp = hopeSP {
+            _id: ObjectId::new().unwrap(),
+            _name: String::from("demo"),
+            _p,
+            _q: G2::one(),
+        };
+        let text = armor_parameters(&sp);
+        let sp2 = dearmor_parameters(&text).unwrap();
+        assert!(sp2._p == sp._p);
+        // the same point in other jacobian coordinates has the same id
+        let mut sp3 = sp.clone();
+        sp3._p.normalize();
+        assert_eq!(parameters_key_id(&sp3), parameters_key_id(&sp));
+        sp3._name = String::from("other");
+        assert!(parameters_key_id(&sp3) != parameters_key_id(&sp));
+    }
+
+    #[test]
+    fn keyfile_files() {
+        let (ek, _) = test_keys();
+        let path = std::env::temp_dir().join(format!("hope-keyfile-{}.pub", std::process::id()));
+        write_keyfile(&path, &armor_paillier_ek(&ek)).unwrap();
+        let ek2 = dearmor_paillier_ek(&read_keyfile(&path).unwrap()).unwrap();
+        assert_eq!(ek2.n, ek.n);
+        std::fs::remove_file(&path).unwrap();
+        match read_keyfile(&path) {
+            Err(KeyFileError::Io(_)) => {}
+            _ => panic!("expected Io"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keyfile_secret_mode() {
+        let (ek, dk) = test_keys();
+        let path = std::env::temp_dir().join(format!("hope-keyfile-{}.sec", std::process::id()));
+        // a world-readable file is restricted when it is overwritten
+        write_keyfile(&path, "").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        write_secret_keyfile(&path, &armor_paillier_dk(&ek, &dk)).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(dearmor_paillier_dk(&read_keyfile(&path).unwrap(), Some(&ek)).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
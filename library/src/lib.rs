@@ -1,14 +1,34 @@
 //! The backend library
 //#![deny(missing_docs)]
 
+pub mod error;
 pub mod bplus;
 pub mod scheme;
 pub mod elgamal;
+pub mod proxy;
+pub mod homomorphic;
+pub mod oracle;
 pub mod paillier;
+pub mod keyfile;
 pub mod websocket;
 
+pub use crate::error::*;
 pub use crate::scheme::*;
-pub use crate::elgamal::*;
-pub use crate::paillier::*;
+// both schemes have `threshold` and `proofs` submodules, so only their
+// items are re-exported
+pub use crate::elgamal::{
+    DecryptionProof, DleqProof, ElGamal, ElGamalCiphertext, ElGamalDecryptionKey, ElGamalDecryptor,
+    ElGamalDkgParticipant, ElGamalEncryptionKey, ElGamalTable, ElGamalThresholdKey, RerandomizationProof,
+    ThresholdElGamal, Transcript,
+};
+pub use crate::proxy::*;
+pub use crate::homomorphic::*;
+pub use crate::oracle::*;
+pub use crate::paillier::{
+    challenge, invert, random_below, random_below_with_rng, random_unit, random_unit_with_rng, CatalanoFiore,
+    DamgardJurik, EncodedMatrix, EncodedNumber, EncodedVector, EncryptedNumber, EncryptedVector, Level1Ciphertext,
+    Level2Ciphertext, PackedCiphertext, Paillier, PaillierPacker, PaillierProofs, RandomnessPool, ThresholdPaillier,
+};
+pub use crate::keyfile::*;
 pub use crate::bplus::*;
 pub use crate::websocket::*;
@@ -0,0 +1,178 @@
+//! Comparison oracles of the code tree.
+//!
+//! The tree orders ciphertexts it cannot decrypt, so every comparison is
+//! answered by an oracle. `LocalOracle` decrypts in process, which suits
+//! tests and single-tenant deployments. `WebSocketOracle` asks the client
+//! that holds the decryption key, and `BatchingOracle` bounds the number of
+//! comparisons any oracle answers at once.
+use crate::error::HopeError;
+use crate::homomorphic::AdditiveHomomorphic;
+use crate::paillier::Paillier;
+use crate::websocket::{ClientComparable, HopeWebSocket};
+
+/// Answers comparisons of the plaintexts of two ciphertexts of type C
+pub trait ComparisonOracle<C> {
+    /// Whether the plaintext of a is less than the plaintext of b
+    fn less_than(&self, a: &C, b: &C) -> Result<bool, HopeError>;
+
+    /// `less_than` for every pair (a, b) of pairs, in the same order
+    fn less_than_batch(&self, pairs: &[(&C, &C)]) -> Result<Vec<bool>, HopeError> {
+        pairs.iter().map(|(a, b)| self.less_than(a, b)).collect()
+    }
+}
+
+/// Oracle holding the decryption key of the scheme S
+#[derive(Clone)]
+pub struct LocalOracle<S: AdditiveHomomorphic = Paillier> {
+    /// encryption key
+    pub _ek: S::EncryptionKey,
+    /// decryption key
+    pub _dk: S::DecryptionKey,
+}
+
+impl<S: AdditiveHomomorphic> LocalOracle<S> {
+    pub fn new(_ek: S::EncryptionKey, _dk: S::DecryptionKey) -> LocalOracle<S> {
+        LocalOracle { _ek, _dk }
+    }
+}
+
+impl<S: AdditiveHomomorphic> ComparisonOracle<S::Ciphertext> for LocalOracle<S> {
+    fn less_than(&self, a: &S::Ciphertext, b: &S::Ciphertext) -> Result<bool, HopeError> {
+        Ok(S::decrypt(&self._dk, &self._ek, a)? < S::decrypt(&self._dk, &self._ek, b)?)
+    }
+}
+
+/// Oracle asking the client connected to `_ws`, one request per batch of
+/// comparisons. Schemes without a batch request fall back to one request
+/// per comparison, see `ClientComparable::greater_than_batch`.
+pub struct WebSocketOracle<'a, S: ClientComparable = Paillier> {
+    /// channel to the client
+    pub _ws: &'a HopeWebSocket,
+    /// encryption key of the compared ciphertexts
+    pub _ek: S::EncryptionKey,
+}
+
+impl<'a, S: ClientComparable> WebSocketOracle<'a, S> {
+    pub fn new(_ws: &'a HopeWebSocket, _ek: S::EncryptionKey) -> WebSocketOracle<'a, S> {
+        WebSocketOracle { _ws, _ek }
+    }
+
+    /// `new` behind a trait object, see `hope::with_key`
+    pub fn boxed(_ws: &'a HopeWebSocket, _ek: S::EncryptionKey) -> Box<dyn ComparisonOracle<S::Ciphertext> + 'a>
+    where
+        S: 'a,
+    {
+        Box::new(WebSocketOracle::<S>::new(_ws, _ek))
+    }
+}
+
+impl<S: ClientComparable> ComparisonOracle<S::Ciphertext> for WebSocketOracle<'_, S> {
+    fn less_than(&self, a: &S::Ciphertext, b: &S::Ciphertext) -> Result<bool, HopeError> {
+        S::greater_than(self._ws, &self._ek, b, a)
+    }
+
+    fn less_than_batch(&self, pairs: &[(&S::Ciphertext, &S::Ciphertext)]) -> Result<Vec<bool>, HopeError> {
+        // a < b is b > a
+        let _swapped: Vec<_> = pairs.iter().map(|(a, b)| (*b, *a)).collect();
+        S::greater_than_batch(self._ws, &self._ek, &_swapped)
+    }
+}
+
+/// Oracle passing at most `_size` comparisons at a time to `_oracle`
+pub struct BatchingOracle<O> {
+    /// oracle answering the batches
+    pub _oracle: O,
+    /// largest number of comparisons per batch
+    pub _size: usize,
+}
+
+impl<O> BatchingOracle<O> {
+    pub fn new(_oracle: O, _size: usize) -> BatchingOracle<O> {
+        BatchingOracle {
+            _oracle,
+            _size: _size.max(1),
+        }
+    }
+}
+
+impl<C, O: ComparisonOracle<C>> ComparisonOracle<C> for BatchingOracle<O> {
+    fn less_than(&self, a: &C, b: &C) -> Result<bool, HopeError> {
+        self._oracle.less_than(a, b)
+    }
+
+    fn less_than_batch(&self, pairs: &[(&C, &C)]) -> Result<Vec<bool>, HopeError> {
+        let mut _lt = Vec::with_capacity(pairs.len());
+        for _chunk in pairs.chunks(self._size) {
+            _lt.extend(self._oracle.less_than_batch(_chunk)?);
+        }
+        Ok(_lt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::websocket::HopeClient;
+    use ::hope::protocol::model::hopeSP;
+    use ::hope::protocol::request::ProtocolOp;
+    use num_bigint::BigInt;
+    use std::thread;
+
+    #[test]
+    fn local_and_client_oracles_agree() {
+        let p = (BigInt::from(1) << 521) - 1;
+        let q = (BigInt::from(1) << 607) - 1;
+        let (ek, dk) = Paillier::keygen_from_primes(&p, &q);
+        let client = HopeClient::new(hopeSP::new(String::from("test")), ek.clone(), dk.clone());
+        let (ws, end) = HopeWebSocket::connect();
+        // counts the requests per operation
+        let server = thread::spawn(move || {
+            let (mut single, mut batch) = (0, 0);
+            end.serve(|req| {
+                match req.op {
+                    ProtocolOp::Compare { .. } => single += 1,
+                    ProtocolOp::CompareBatch { .. } => batch += 1,
+                    _ => {}
+                }
+                client.handle(req)
+            });
+            (single, batch)
+        });
+        let ms = [-7, 0, 3, 3, 12];
+        let cs: Vec<BigInt> = ms.iter().map(|m| Paillier::encrypt(&ek, &BigInt::from(*m))).collect();
+        let mut pairs = Vec::new();
+        for a in &cs {
+            for b in &cs {
+                pairs.push((a, b));
+            }
+        }
+        let expected: Vec<bool> = ms.iter().flat_map(|a| ms.iter().map(move |b| a < b)).collect();
+        let local: LocalOracle = LocalOracle::new(ek.clone(), dk);
+        assert_eq!(local.less_than_batch(&pairs), Ok(expected.clone()));
+        let single: WebSocketOracle = WebSocketOracle::new(&ws, ek.clone());
+        for (i, (a, b)) in pairs[..5].iter().enumerate() {
+            assert_eq!(single.less_than(a, b), Ok(expected[i]));
+        }
+        let batching = BatchingOracle::new(WebSocketOracle::<Paillier>::new(&ws, ek), 10);
+        assert_eq!(batching.less_than_batch(&pairs), Ok(expected.clone()));
+        assert_eq!(batching.less_than_batch(&[]), Ok(vec![]));
+        // batches of local comparisons, no request is sent
+        let batching = BatchingOracle::new(local, 4);
+        assert_eq!(batching.less_than_batch(&pairs), Ok(expected));
+        drop(single);
+        drop(ws);
+        assert_eq!(server.join().unwrap(), (5, 3));
+    }
+
+    #[test]
+    fn unconnected_oracle_fails() {
+        let p = (BigInt::from(1) << 521) - 1;
+        let q = (BigInt::from(1) << 607) - 1;
+        let (ek, _) = Paillier::keygen_from_primes(&p, &q);
+        let ws = HopeWebSocket::new();
+        let _c = Paillier::encrypt(&ek, &BigInt::from(1));
+        let oracle: WebSocketOracle = WebSocketOracle::new(&ws, ek);
+        assert_eq!(oracle.less_than(&_c, &_c), Err(HopeError::ClientUnavailable));
+    }
+}
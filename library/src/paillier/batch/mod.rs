@@ -0,0 +1,118 @@
+//! Batch encryption and homomorphic aggregation of many Paillier
+//! ciphertexts.
+//!
+//! All functions spread the work over the cores with rayon. Products of
+//! ciphertexts are computed as a parallel fold over chunks followed by a
+//! reduction of the partial products, which is exact since multiplication
+//! modulo n^2 is associative and commutative.
+use num_bigint::BigInt;
+use num_traits::One;
+use rayon::prelude::*;
+use hope::protocol::model::*;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use crate::error::HopeError;
+use super::{random_unit_with_rng, Paillier};
+
+impl Paillier {
+    /// Encryption of the sum of the plaintexts of cts. The empty sum is the
+    /// trivial encryption 1 of 0.
+    pub fn sum<'a, I>(ek: &PaillierEncryptionKey, cts: I) -> BigInt
+    where
+        I: IntoIterator<Item = &'a BigInt>,
+        I::IntoIter: Send,
+    {
+        cts.into_iter()
+            .par_bridge()
+            .fold(BigInt::one, |acc, c| (acc * c) % &ek.n2)
+            .reduce(BigInt::one, |a, b| (a * b) % &ek.n2)
+    }
+
+    /// Encryption of sum_i w_i * m_i, the inner product of the plaintexts
+    /// of cts with weights. `InvalidArgument` if the lengths differ.
+    pub fn inner_product(ek: &PaillierEncryptionKey, cts: &[BigInt], weights: &[BigInt]) -> Result<BigInt, HopeError> {
+        if cts.len() != weights.len() {
+            return Err(HopeError::InvalidArgument(format!(
+                "{} ciphertexts, {} weights",
+                cts.len(),
+                weights.len()
+            )));
+        }
+        Ok(
+            cts.par_iter()
+                .zip(weights.par_iter())
+                .map(|(c, w)| Paillier::mul_const(ek, c, w))
+                .reduce(BigInt::one, |a, b| (a * b) % &ek.n2),
+        )
+    }
+
+    /// Encrypts every element of ms
+    pub fn encrypt_batch(ek: &PaillierEncryptionKey, ms: &[BigInt]) -> Vec<BigInt> {
+        Paillier::encrypt_batch_with_rng(ek, ms, &mut OsRng)
+    }
+
+    /// `encrypt_batch` with randomness drawn from rng. The r are drawn in
+    /// order, so the result only depends on the state of rng.
+    pub fn encrypt_batch_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        ms: &[BigInt],
+        rng: &mut R,
+    ) -> Vec<BigInt> {
+        let rs: Vec<BigInt> = ms.iter().map(|_| random_unit_with_rng(&ek.n, rng)).collect();
+        ms.par_iter()
+            .zip(rs.par_iter())
+            .map(|(m, r)| Paillier::encrypt_with_r(ek, m, r))
+            .collect()
+    }
+
+    /// Decrypts every element of cs
+    pub fn decrypt_batch(dk: &PaillierDecryptionKey, ek: &PaillierEncryptionKey, cs: &[BigInt]) -> Vec<BigInt> {
+        cs.par_iter().map(|c| Paillier::decrypt(dk, ek, c)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::paillier::test_keys;
+    use rand_chacha::ChaChaRng;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn batch_roundtrip() {
+        let (ek, dk) = test_keys();
+        let ms: Vec<BigInt> = (-500..500).map(BigInt::from).collect();
+        let cs = Paillier::encrypt_batch(&ek, &ms);
+        assert_eq!(Paillier::decrypt_batch(&dk, &ek, &cs), ms);
+        let c1 = Paillier::encrypt_batch_with_rng(&ek, &ms, &mut ChaChaRng::seed_from_u64(1));
+        let c2 = Paillier::encrypt_batch_with_rng(&ek, &ms, &mut ChaChaRng::seed_from_u64(1));
+        assert_eq!(c1, c2);
+    }
+
+    #[test]
+    fn batch_sum() {
+        let (ek, dk) = test_keys();
+        let ms: Vec<BigInt> = (0..2000).map(|i| BigInt::from(i * 7 - 3000)).collect();
+        let cs = Paillier::encrypt_batch(&ek, &ms);
+        let expected: BigInt = ms.iter().sum();
+        assert_eq!(Paillier::decrypt(&dk, &ek, &Paillier::sum(&ek, &cs)), expected);
+        // any iterator, e.g. every second ciphertext
+        let even: BigInt = ms.iter().step_by(2).sum();
+        let c = Paillier::sum(&ek, cs.iter().step_by(2));
+        assert_eq!(Paillier::decrypt(&dk, &ek, &c), even);
+        let empty: Vec<BigInt> = Vec::new();
+        assert_eq!(Paillier::decrypt(&dk, &ek, &Paillier::sum(&ek, &empty)), BigInt::from(0));
+    }
+
+    #[test]
+    fn batch_inner_product() {
+        let (ek, dk) = test_keys();
+        let ms: Vec<BigInt> = (1..=100).map(BigInt::from).collect();
+        let ws: Vec<BigInt> = (1..=100).map(|i| BigInt::from(if i % 2 == 0 { i } else { -i })).collect();
+        let cs = Paillier::encrypt_batch(&ek, &ms);
+        let c = Paillier::inner_product(&ek, &cs, &ws).unwrap();
+        let expected: BigInt = ms.iter().zip(ws.iter()).map(|(m, w)| m * w).sum();
+        assert_eq!(Paillier::decrypt(&dk, &ek, &c), expected);
+        assert!(Paillier::inner_product(&ek, &cs, &ws[1..]).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! Catalano-Fiore transformation of Paillier into a level-2 homomorphic
+//! scheme, which supports additions and one multiplication.
+//!
+//! A level-1 ciphertext of m is (a, Enc(b)) for a random b and a = m - b in
+//! the clear. Level-1 ciphertexts add componentwise. The product of two of
+//! them is the level-2 ciphertext
+//! alpha = Enc(a1 * a2) * Enc(b1)^a2 * Enc(b2)^a1 = Enc(m1 * m2 - b1 * b2)
+//! together with the pair (Enc(b1), Enc(b2)). Level-2 ciphertexts add by
+//! multiplying the alphas and concatenating the pairs, and decrypt to
+//! Dec(alpha) + sum Dec(beta1) * Dec(beta2). All arithmetic is modulo n,
+//! results are decoded to signed values like `Paillier::decrypt`.
+use num_bigint::BigInt;
+use num_integer::Integer;
+use serde_derive::{Deserialize, Serialize};
+use hope::protocol::model::*;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use super::{random_below_with_rng, Paillier};
+
+/// Level-1 ciphertext (a, beta) = (m - b, Enc(b))
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Level1Ciphertext {
+    pub a: BigInt,
+    pub beta: BigInt,
+}
+
+/// Level-2 ciphertext, alpha and the beta pairs of the multiplications
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Level2Ciphertext {
+    pub alpha: BigInt,
+    pub betas: Vec<(BigInt, BigInt)>,
+}
+
+pub struct CatalanoFiore;
+
+impl CatalanoFiore {
+    /// Level-1 encryption of m, which may be negative
+    pub fn encrypt(ek: &PaillierEncryptionKey, m: &BigInt) -> Level1Ciphertext {
+        CatalanoFiore::encrypt_with_rng(ek, m, &mut OsRng)
+    }
+
+    /// `encrypt` with randomness drawn from rng
+    pub fn encrypt_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        m: &BigInt,
+        rng: &mut R,
+    ) -> Level1Ciphertext {
+        let b = random_below_with_rng(&ek.n, rng);
+        Level1Ciphertext {
+            a: (Paillier::encode_signed(ek, m) - &b).mod_floor(&ek.n),
+            beta: Paillier::encrypt_with_rng(ek, &b, rng),
+        }
+    }
+
+    /// Signed plaintext of a level-1 ciphertext
+    pub fn decrypt(dk: &PaillierDecryptionKey, ek: &PaillierEncryptionKey, c: &Level1Ciphertext) -> BigInt {
+        Paillier::decode_signed(ek, &(&c.a + Paillier::decrypt(dk, ek, &c.beta)))
+    }
+
+    /// Level-1 encryption of m1 + m2
+    pub fn add(ek: &PaillierEncryptionKey, c1: &Level1Ciphertext, c2: &Level1Ciphertext) -> Level1Ciphertext {
+        Level1Ciphertext {
+            a: (&c1.a + &c2.a).mod_floor(&ek.n),
+            beta: Paillier::add(ek, &c1.beta, &c2.beta),
+        }
+    }
+
+    /// Level-1 encryption of m1 - m2, None if c2 is not invertible
+    pub fn sub(ek: &PaillierEncryptionKey, c1: &Level1Ciphertext, c2: &Level1Ciphertext) -> Option<Level1Ciphertext> {
+        Some(Level1Ciphertext {
+            a: (&c1.a - &c2.a).mod_floor(&ek.n),
+            beta: Paillier::sub(ek, &c1.beta, &c2.beta).ok()?,
+        })
+    }
+
+    /// Level-1 encryption of m1 + m, m may be negative
+    pub fn add_const(ek: &PaillierEncryptionKey, c: &Level1Ciphertext, m: &BigInt) -> Level1Ciphertext {
+        Level1Ciphertext {
+            a: (&c.a + m).mod_floor(&ek.n),
+            beta: c.beta.clone(),
+        }
+    }
+
+    /// Level-1 encryption of m1 * m, m may be negative
+    pub fn mul_const(ek: &PaillierEncryptionKey, c: &Level1Ciphertext, m: &BigInt) -> Level1Ciphertext {
+        Level1Ciphertext {
+            a: (&c.a * m).mod_floor(&ek.n),
+            beta: Paillier::mul_const(ek, &c.beta, m),
+        }
+    }
+
+    /// Level-2 encryption of m1 * m2
+    pub fn mul(ek: &PaillierEncryptionKey, c1: &Level1Ciphertext, c2: &Level1Ciphertext) -> Level2Ciphertext {
+        // Enc(a1 * a2) needs no randomness, the betas hide the product
+        let alpha = Paillier::add_const(ek, &BigInt::from(1), &(&c1.a * &c2.a));
+        let alpha = Paillier::add(ek, &alpha, &Paillier::mul_const(ek, &c1.beta, &c2.a));
+        let alpha = Paillier::add(ek, &alpha, &Paillier::mul_const(ek, &c2.beta, &c1.a));
+        Level2Ciphertext {
+            alpha,
+            betas: vec![(c1.beta.clone(), c2.beta.clone())],
+        }
+    }
+
+    /// Level-2 encryption of sum_i m1_i * m2_i, None if the lengths differ
+    pub fn inner_product(
+        ek: &PaillierEncryptionKey,
+        c1: &[Level1Ciphertext],
+        c2: &[Level1Ciphertext],
+    ) -> Option<Level2Ciphertext> {
+        if c1.len() != c2.len() {
+            return None;
+        }
+        let zero = Level2Ciphertext {
+            alpha: BigInt::from(1),
+            betas: Vec::with_capacity(c1.len()),
+        };
+        Some(c1.iter().zip(c2.iter()).fold(zero, |acc, (x, y)| {
+            CatalanoFiore::add_level2(ek, &acc, &CatalanoFiore::mul(ek, x, y))
+        }))
+    }
+
+    /// Level-2 ciphertext with the plaintext of c
+    pub fn to_level2(ek: &PaillierEncryptionKey, c: &Level1Ciphertext) -> Level2Ciphertext {
+        Level2Ciphertext {
+            alpha: Paillier::add_const(ek, &c.beta, &c.a),
+            betas: Vec::new(),
+        }
+    }
+
+    /// Level-2 encryption of m1 + m2
+    pub fn add_level2(ek: &PaillierEncryptionKey, d1: &Level2Ciphertext, d2: &Level2Ciphertext) -> Level2Ciphertext {
+        let mut betas = d1.betas.clone();
+        betas.extend(d2.betas.iter().cloned());
+        Level2Ciphertext {
+            alpha: Paillier::add(ek, &d1.alpha, &d2.alpha),
+            betas,
+        }
+    }
+
+    /// Level-2 encryption of m1 + m, m may be negative
+    pub fn add_const_level2(ek: &PaillierEncryptionKey, d: &Level2Ciphertext, m: &BigInt) -> Level2Ciphertext {
+        Level2Ciphertext {
+            alpha: Paillier::add_const(ek, &d.alpha, m),
+            betas: d.betas.clone(),
+        }
+    }
+
+    /// Level-2 encryption of m1 * m, m may be negative
+    pub fn mul_const_level2(ek: &PaillierEncryptionKey, d: &Level2Ciphertext, m: &BigInt) -> Level2Ciphertext {
+        Level2Ciphertext {
+            alpha: Paillier::mul_const(ek, &d.alpha, m),
+            betas: d
+                .betas
+                .iter()
+                .map(|(b1, b2)| (Paillier::mul_const(ek, b1, m), b2.clone()))
+                .collect(),
+        }
+    }
+
+    /// Signed plaintext of a level-2 ciphertext
+    pub fn decrypt_level2(dk: &PaillierDecryptionKey, ek: &PaillierEncryptionKey, d: &Level2Ciphertext) -> BigInt {
+        let m = d.betas.iter().fold(Paillier::decrypt(dk, ek, &d.alpha), |acc, (b1, b2)| {
+            (acc + Paillier::decrypt(dk, ek, b1) * Paillier::decrypt(dk, ek, b2)).mod_floor(&ek.n)
+        });
+        Paillier::decode_signed(ek, &m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::paillier::test_keys;
+
+    #[test]
+    fn catalano_fiore_level1() {
+        let (ek, dk) = test_keys();
+        let c1 = CatalanoFiore::encrypt(&ek, &BigInt::from(-17));
+        let c2 = CatalanoFiore::encrypt(&ek, &BigInt::from(45));
+        assert_eq!(CatalanoFiore::decrypt(&dk, &ek, &c1), BigInt::from(-17));
+        assert_eq!(CatalanoFiore::decrypt(&dk, &ek, &CatalanoFiore::add(&ek, &c1, &c2)), BigInt::from(28));
+        let d = CatalanoFiore::sub(&ek, &c1, &c2).unwrap();
+        assert_eq!(CatalanoFiore::decrypt(&dk, &ek, &d), BigInt::from(-62));
+        let c3 = CatalanoFiore::add_const(&ek, &c1, &BigInt::from(20));
+        assert_eq!(CatalanoFiore::decrypt(&dk, &ek, &c3), BigInt::from(3));
+        let c4 = CatalanoFiore::mul_const(&ek, &c2, &BigInt::from(-3));
+        assert_eq!(CatalanoFiore::decrypt(&dk, &ek, &c4), BigInt::from(-135));
+    }
+
+    #[test]
+    fn catalano_fiore_level2() {
+        let (ek, dk) = test_keys();
+        let c1 = CatalanoFiore::encrypt(&ek, &BigInt::from(-17));
+        let c2 = CatalanoFiore::encrypt(&ek, &BigInt::from(45));
+        let d = CatalanoFiore::mul(&ek, &c1, &c2);
+        assert_eq!(CatalanoFiore::decrypt_level2(&dk, &ek, &d), BigInt::from(-765));
+        let e = CatalanoFiore::add_level2(&ek, &d, &CatalanoFiore::mul(&ek, &c2, &c2));
+        assert_eq!(CatalanoFiore::decrypt_level2(&dk, &ek, &e), BigInt::from(-765 + 2025));
+        let e = CatalanoFiore::add_level2(&ek, &e, &CatalanoFiore::to_level2(&ek, &c1));
+        assert_eq!(CatalanoFiore::decrypt_level2(&dk, &ek, &e), BigInt::from(-765 + 2025 - 17));
+        let f = CatalanoFiore::mul_const_level2(&ek, &e, &BigInt::from(-2));
+        let f = CatalanoFiore::add_const_level2(&ek, &f, &BigInt::from(10));
+        assert_eq!(CatalanoFiore::decrypt_level2(&dk, &ek, &f), BigInt::from(-2 * (-765 + 2025 - 17) + 10));
+    }
+
+    #[test]
+    fn catalano_fiore_variance() {
+        let (ek, dk) = test_keys();
+        let xs: Vec<i64> = vec![3, -1, 4, 1, -5, 9, 2, 6];
+        let ys: Vec<i64> = vec![2, 7, -1, 8, 2, 8, -1, 8];
+        let cx: Vec<Level1Ciphertext> = xs.iter().map(|x| CatalanoFiore::encrypt(&ek, &BigInt::from(*x))).collect();
+        let cy: Vec<Level1Ciphertext> = ys.iter().map(|y| CatalanoFiore::encrypt(&ek, &BigInt::from(*y))).collect();
+        let dot = CatalanoFiore::inner_product(&ek, &cx, &cy).unwrap();
+        let expected: i64 = xs.iter().zip(ys.iter()).map(|(x, y)| x * y).sum();
+        assert_eq!(CatalanoFiore::decrypt_level2(&dk, &ek, &dot), BigInt::from(expected));
+        // n * sum x^2 - (sum x)^2 = n^2 * variance
+        let n = BigInt::from(xs.len());
+        let sum = cx.iter().skip(1).fold(cx[0].clone(), |acc, c| CatalanoFiore::add(&ek, &acc, c));
+        let squares = CatalanoFiore::inner_product(&ek, &cx, &cx).unwrap();
+        let var = CatalanoFiore::add_level2(
+            &ek,
+            &CatalanoFiore::mul_const_level2(&ek, &squares, &n),
+            &CatalanoFiore::mul_const_level2(&ek, &CatalanoFiore::mul(&ek, &sum, &sum), &BigInt::from(-1)),
+        );
+        let s: i64 = xs.iter().sum();
+        let s2: i64 = xs.iter().map(|x| x * x).sum();
+        assert_eq!(CatalanoFiore::decrypt_level2(&dk, &ek, &var), BigInt::from(8 * s2 - s * s));
+        assert!(CatalanoFiore::inner_product(&ek, &cx, &cy[1..]).is_none());
+    }
+}
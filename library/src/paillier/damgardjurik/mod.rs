@@ -0,0 +1,251 @@
+//! Damgard-Jurik cryptosystem, the generalisation of Paillier to the
+//! plaintext space Z_(n^s) and the ciphertext space Z_(n^(s+1)).
+//! For s = 1 it coincides with `Paillier`.
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Zero};
+use hope::protocol::model::*;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use crate::error::HopeError;
+use super::{invert, random_unit_with_rng, Paillier};
+
+pub struct DamgardJurik {
+    pub pk: DamgardJurikEncryptionKey,
+    pub sk: Option<DamgardJurikDecryptionKey>,
+}
+
+impl DamgardJurik {
+    pub fn new(keysize: usize, s: usize) -> Result<DamgardJurik, HopeError> {
+        let (pk, sk) = DamgardJurik::keygen(keysize, s)?;
+        Ok(DamgardJurik {
+            pk,
+            sk: Some(sk),
+        })
+    }
+
+    /// Generates n like `Paillier::keygen` and lifts it to exponent s
+    pub fn keygen(keysize: usize, s: usize) -> Result<(DamgardJurikEncryptionKey, DamgardJurikDecryptionKey), HopeError> {
+        let (ek, dk) = Paillier::keygen(keysize)?;
+        DamgardJurik::from_paillier(&ek, &dk, s)
+    }
+
+    /// Lifts a Paillier key pair to exponent s, `InvalidArgument` if s is
+    /// 0, `InvalidKey` if lambda has no inverse modulo n^s
+    pub fn from_paillier(
+        ek: &PaillierEncryptionKey,
+        dk: &PaillierDecryptionKey,
+        s: usize,
+    ) -> Result<(DamgardJurikEncryptionKey, DamgardJurikDecryptionKey), HopeError> {
+        if s < 1 {
+            return Err(HopeError::InvalidArgument(format!("exponent s = {}", s)));
+        }
+        let ns = num_traits::pow(ek.n.clone(), s);
+        let ns1 = &ns * &ek.n;
+        let mu = invert(&dk.lambda, &ns)
+            .map_err(|_| HopeError::InvalidKey(String::from("lambda is not invertible modulo n^s")))?;
+        Ok((
+            DamgardJurikEncryptionKey {
+                s,
+                n: ek.n.clone(),
+                ns,
+                ns1,
+            },
+            DamgardJurikDecryptionKey {
+                lambda: dk.lambda.clone(),
+                mu,
+            },
+        ))
+    }
+
+    pub fn encrypt(ek: &DamgardJurikEncryptionKey, m: &BigInt) -> BigInt {
+        DamgardJurik::encrypt_with_rng(ek, m, &mut OsRng)
+    }
+
+    /// `encrypt` with randomness drawn from rng
+    pub fn encrypt_with_rng<R: RngCore + CryptoRng>(
+        ek: &DamgardJurikEncryptionKey,
+        m: &BigInt,
+        rng: &mut R,
+    ) -> BigInt {
+        let r = random_unit_with_rng(&ek.n, rng);
+        let rns = r.modpow(&ek.ns, &ek.ns1);
+        let gm = DamgardJurik::g(ek).modpow(&DamgardJurik::encode_signed(ek, m), &ek.ns1);
+        (&gm * &rns) % &ek.ns1
+    }
+
+    /// Decrypts c to a signed plaintext in (-n^s/2, n^s/2], `InvalidKey`
+    /// if n has a prime factor up to s
+    pub fn decrypt(dk: &DamgardJurikDecryptionKey, ek: &DamgardJurikEncryptionKey, c: &BigInt) -> Result<BigInt, HopeError> {
+        let cl = c.modpow(&dk.lambda, &ek.ns1);
+        let m = (DamgardJurik::dlog(ek, &cl)? * &dk.mu).mod_floor(&ek.ns);
+        Ok(DamgardJurik::decode_signed(ek, &m))
+    }
+
+    /// Maps a signed plaintext into Z_(n^s)
+    pub fn encode_signed(ek: &DamgardJurikEncryptionKey, m: &BigInt) -> BigInt {
+        m.mod_floor(&ek.ns)
+    }
+
+    /// Maps a residue in Z_(n^s) back to a signed plaintext
+    pub fn decode_signed(ek: &DamgardJurikEncryptionKey, m: &BigInt) -> BigInt {
+        let m = m.mod_floor(&ek.ns);
+        if m > (&ek.ns >> 1) {
+            m - &ek.ns
+        } else {
+            m
+        }
+    }
+
+    pub fn rerandomize(ek: &DamgardJurikEncryptionKey, c: &BigInt) -> BigInt {
+        DamgardJurik::rerandomize_with_rng(ek, c, &mut OsRng)
+    }
+
+    /// `rerandomize` with randomness drawn from rng
+    pub fn rerandomize_with_rng<R: RngCore + CryptoRng>(
+        ek: &DamgardJurikEncryptionKey,
+        c: &BigInt,
+        rng: &mut R,
+    ) -> BigInt {
+        let r = random_unit_with_rng(&ek.n, rng);
+        (c * r.modpow(&ek.ns, &ek.ns1)) % &ek.ns1
+    }
+
+    pub fn add(ek: &DamgardJurikEncryptionKey, c1: &BigInt, c2: &BigInt) -> BigInt {
+        (c1 * c2) % &ek.ns1
+    }
+
+    /// Encryption of m1 - m2, `NotInvertible` if c2 is not invertible
+    /// modulo n^(s+1)
+    pub fn sub(ek: &DamgardJurikEncryptionKey, c1: &BigInt, c2: &BigInt) -> Result<BigInt, HopeError> {
+        Ok((c1 * DamgardJurik::neg(ek, c2)?) % &ek.ns1)
+    }
+
+    /// Encryption of -m, `NotInvertible` if c is not invertible modulo
+    /// n^(s+1)
+    pub fn neg(ek: &DamgardJurikEncryptionKey, c: &BigInt) -> Result<BigInt, HopeError> {
+        invert(c, &ek.ns1)
+    }
+
+    pub fn add_const(ek: &DamgardJurikEncryptionKey, c: &BigInt, m: &BigInt) -> BigInt {
+        let gm = DamgardJurik::g(ek).modpow(&DamgardJurik::encode_signed(ek, m), &ek.ns1);
+        DamgardJurik::add(ek, c, &gm)
+    }
+
+    pub fn mul_const(ek: &DamgardJurikEncryptionKey, c: &BigInt, m: &BigInt) -> BigInt {
+        c.modpow(&DamgardJurik::encode_signed(ek, m), &ek.ns1)
+    }
+
+    /// the generator g = n + 1
+    fn g(ek: &DamgardJurikEncryptionKey) -> BigInt {
+        &ek.n + BigInt::one()
+    }
+
+    /// Recovers i mod n^s from a = (1+n)^i mod n^(s+1), one power of n at a
+    /// time. `InvalidKey` if some k! with k <= s is not invertible modulo n.
+    fn dlog(ek: &DamgardJurikEncryptionKey, a: &BigInt) -> Result<BigInt, HopeError> {
+        let mut i = BigInt::zero();
+        let mut nj = ek.n.clone();
+        for j in 1..=ek.s {
+            let nj1 = &nj * &ek.n;
+            let mut t1 = ((a % &nj1) - BigInt::one()) / &ek.n;
+            let mut t2 = i.clone();
+            let mut nk = BigInt::one();
+            let mut kfac = BigInt::one();
+            for k in 2..=j {
+                i -= BigInt::one();
+                t2 = (&t2 * &i).mod_floor(&nj);
+                nk *= &ek.n;
+                kfac *= BigInt::from(k);
+                let kinv = invert(&kfac, &nj)
+                    .map_err(|_| HopeError::InvalidKey(format!("n has a prime factor up to {}", k)))?;
+                t1 = (t1 - &t2 * &nk * kinv).mod_floor(&nj);
+            }
+            i = t1;
+            nj = nj1;
+        }
+        Ok(i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn test_keys(s: usize) -> (DamgardJurikEncryptionKey, DamgardJurikDecryptionKey) {
+        let (ek, dk) = crate::paillier::test_keys();
+        DamgardJurik::from_paillier(&ek, &dk, s).unwrap()
+    }
+
+    fn encrypt_r(ek: &DamgardJurikEncryptionKey, m: &BigInt, r: u64) -> BigInt {
+        let gm = DamgardJurik::g(ek).modpow(&DamgardJurik::encode_signed(ek, m), &ek.ns1);
+        (gm * BigInt::from(r).modpow(&ek.ns, &ek.ns1)) % &ek.ns1
+    }
+
+    #[test]
+    fn damgardjurik_large_plaintext() {
+        let (ek, dk) = test_keys(3);
+        // larger than n, still below n^3 / 2
+        let m = &ek.n * &ek.n + BigInt::from(4711);
+        let c = encrypt_r(&ek, &m, 1337);
+        assert_eq!(DamgardJurik::decrypt(&dk, &ek, &c).unwrap(), m);
+        let c = encrypt_r(&ek, &-&m, 1337);
+        assert_eq!(DamgardJurik::decrypt(&dk, &ek, &c).unwrap(), -m);
+    }
+
+    #[test]
+    fn damgardjurik_homomorphic() {
+        let (ek, dk) = test_keys(2);
+        let m1 = &ek.n * BigInt::from(5);
+        let m2 = BigInt::from(123456789);
+        let c1 = encrypt_r(&ek, &m1, 17);
+        let c2 = encrypt_r(&ek, &m2, 19);
+        let c = DamgardJurik::rerandomize(&ek, &DamgardJurik::add(&ek, &c1, &c2));
+        assert_eq!(DamgardJurik::decrypt(&dk, &ek, &c).unwrap(), &m1 + &m2);
+        let c = DamgardJurik::sub(&ek, &c2, &c1).unwrap();
+        assert_eq!(DamgardJurik::decrypt(&dk, &ek, &c).unwrap(), &m2 - &m1);
+        let c = DamgardJurik::add_const(&ek, &c1, &BigInt::from(-1));
+        assert_eq!(DamgardJurik::decrypt(&dk, &ek, &c).unwrap(), &m1 - BigInt::one());
+        let c = DamgardJurik::mul_const(&ek, &c2, &ek.n);
+        assert_eq!(DamgardJurik::decrypt(&dk, &ek, &c).unwrap(), &m2 * &ek.n);
+    }
+
+    #[test]
+    fn damgardjurik_s1_is_paillier() {
+        let (pek, pdk) = crate::paillier::test_keys();
+        let (ek, _) = DamgardJurik::from_paillier(&pek, &pdk, 1).unwrap();
+        let c = encrypt_r(&ek, &BigInt::from(-42), 23);
+        assert_eq!(Paillier::decrypt(&pdk, &pek, &c), BigInt::from(-42));
+    }
+
+    #[test]
+    fn damgardjurik_invalid_keys() {
+        let (pek, pdk) = crate::paillier::test_keys();
+        assert!(matches!(
+            DamgardJurik::from_paillier(&pek, &pdk, 0),
+            Err(HopeError::InvalidArgument(_))
+        ));
+        let mut bad_dk = pdk.clone();
+        bad_dk.lambda = pek.n.clone();
+        assert!(matches!(
+            DamgardJurik::from_paillier(&pek, &bad_dk, 2),
+            Err(HopeError::InvalidKey(_))
+        ));
+        // 2! has no inverse modulo an even n
+        let n = BigInt::from(2 * 1000003);
+        let ek = DamgardJurikEncryptionKey {
+            s: 2,
+            n: n.clone(),
+            ns: &n * &n,
+            ns1: &n * &n * &n,
+        };
+        let dk = DamgardJurikDecryptionKey {
+            lambda: BigInt::from(1000002),
+            mu: BigInt::one(),
+        };
+        assert!(matches!(
+            DamgardJurik::decrypt(&dk, &ek, &BigInt::from(3)),
+            Err(HopeError::InvalidKey(_))
+        ));
+    }
+}
@@ -0,0 +1,438 @@
+//! Fixed- and floating-point encoding of Paillier plaintexts.
+//!
+//! A value is stored as mantissa * base^exponent. Only the mantissa is
+//! encrypted, base and exponent stay public. Before two values are added
+//! the one with the larger exponent is scaled down to the smaller one, on
+//! ciphertexts with `Paillier::mul_const`. The mantissa of every result
+//! must stay below n/2 in absolute value, the operations return
+//! `PlaintextOutOfRange` where they can tell it does not.
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
+use serde_derive::{Deserialize, Serialize};
+use hope::protocol::model::*;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use crate::error::HopeError;
+use super::Paillier;
+
+/// base of exact f64 encodings
+pub const BASE_BINARY: u32 = 2;
+/// base of decimal encodings
+pub const BASE_DECIMAL: u32 = 10;
+/// bound on the bits of base^(exponent - new_exponent) when plaintexts are
+/// aligned, far above any key size. Encrypted values are bounded by n.
+pub const MAX_FACTOR_BITS: usize = 1 << 16;
+
+/// A plaintext value mantissa * base^exponent
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EncodedNumber {
+    pub mantissa: BigInt,
+    pub base: u32,
+    pub exponent: i32,
+}
+
+/// An encrypted mantissa with its public base and exponent
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptedNumber {
+    pub c: BigInt,
+    pub base: u32,
+    pub exponent: i32,
+}
+
+impl EncodedNumber {
+    /// `InvalidArgument` if base is below 2
+    pub fn new(mantissa: BigInt, base: u32, exponent: i32) -> Result<EncodedNumber, HopeError> {
+        if base < 2 {
+            return Err(HopeError::InvalidArgument(format!("base {}", base)));
+        }
+        Ok(EncodedNumber {
+            mantissa,
+            base,
+            exponent,
+        })
+    }
+
+    /// Encodes an integer with exponent 0
+    pub fn from_bigint(m: &BigInt, base: u32) -> Result<EncodedNumber, HopeError> {
+        EncodedNumber::new(m.clone(), base, 0)
+    }
+
+    /// Exact binary encoding of a finite f64, None for NaN and infinity
+    pub fn from_f64(x: f64) -> Option<EncodedNumber> {
+        if !x.is_finite() {
+            return None;
+        }
+        let bits = x.to_bits();
+        let biased = ((bits >> 52) & 0x7ff) as i32;
+        let fraction = bits & ((1u64 << 52) - 1);
+        let (mut mantissa, mut exponent) = if biased == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | (1u64 << 52), biased - 1075)
+        };
+        if mantissa == 0 {
+            return EncodedNumber::new(BigInt::zero(), BASE_BINARY, 0).ok();
+        }
+        let zeros = mantissa.trailing_zeros();
+        mantissa >>= zeros;
+        exponent += zeros as i32;
+        let mantissa = BigInt::from(mantissa);
+        let mantissa = if x < 0.0 { -mantissa } else { mantissa };
+        EncodedNumber::new(mantissa, BASE_BINARY, exponent).ok()
+    }
+
+    /// Fixed-point encoding of x, rounded to the nearest multiple of
+    /// base^exponent, None if base is below 2
+    pub fn from_f64_fixed(x: f64, base: u32, exponent: i32) -> Option<EncodedNumber> {
+        let scaled = x / (base as f64).powi(exponent);
+        let mantissa = BigInt::from_f64(scaled.round())?;
+        EncodedNumber::new(mantissa, base, exponent).ok()
+    }
+
+    /// Exact decimal encoding of strings like "-12.345"
+    pub fn from_decimal_str(s: &str) -> Option<EncodedNumber> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let mut parts = digits.splitn(2, '.');
+        let int_part = parts.next().unwrap_or("");
+        let frac_part = parts.next().unwrap_or("");
+        if int_part.is_empty() && frac_part.is_empty() {
+            return None;
+        }
+        let all: String = [int_part, frac_part].concat();
+        if !all.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let mantissa = BigInt::parse_bytes(all.as_bytes(), 10)?;
+        let mantissa = if negative { -mantissa } else { mantissa };
+        EncodedNumber::new(mantissa, BASE_DECIMAL, -(frac_part.len() as i32)).ok()
+    }
+
+    /// The same value with a smaller exponent, the mantissa grows by
+    /// base^(exponent - new_exponent). `InvalidArgument` if new_exponent is
+    /// larger than the exponent, `PlaintextOutOfRange` if the factor has
+    /// MAX_FACTOR_BITS bits or more.
+    pub fn decrease_exponent_to(&self, new_exponent: i32) -> Result<EncodedNumber, HopeError> {
+        let factor = exponent_factor(self.base, self.exponent, new_exponent)?;
+        EncodedNumber::new(&self.mantissa * factor, self.base, new_exponent)
+    }
+
+    /// Nearest f64 to the encoded value, infinite or zero once base^exponent
+    /// leaves the range of f64
+    pub fn to_f64(&self) -> f64 {
+        let base = BigInt::from(self.base);
+        let sign = if self.mantissa.is_negative() { -1.0 } else { 1.0 };
+        // |value| >= 2^(exponent * log2_floor(base)) for positive and
+        // < 2^(mantissa.bits() + exponent * log2_floor(base)) for negative
+        // exponents
+        let scale = self.exponent as i64 * log2_floor(self.base) as i64;
+        if self.mantissa.is_zero() {
+            return 0.0;
+        } else if scale >= 1024 {
+            return sign * f64::INFINITY;
+        } else if scale + (self.mantissa.bits() as i64) < -1075 {
+            return sign * 0.0;
+        }
+        if self.exponent >= 0 {
+            let value = &self.mantissa * num_traits::pow(base, self.exponent as usize);
+            return value.to_f64().unwrap_or(f64::NAN);
+        }
+        // divide with 64 significant bits left in the quotient
+        let den = num_traits::pow(base, (-self.exponent) as usize);
+        let shift = std::cmp::max(0, 64 + den.bits() as i64 - self.mantissa.bits() as i64) as usize;
+        let quotient = (&self.mantissa << shift).div_floor(&den);
+        ldexp(quotient.to_f64().unwrap_or(f64::NAN), -(shift as i64))
+    }
+
+    /// Exact decimal representation, None unless the base is 2 or 10
+    pub fn to_decimal_string(&self) -> Option<String> {
+        let (mantissa, exponent) = match self.base {
+            BASE_DECIMAL => (self.mantissa.clone(), self.exponent),
+            BASE_BINARY if self.exponent < 0 => {
+                // 2^-k = 5^k / 10^k
+                let five = num_traits::pow(BigInt::from(5), (-self.exponent) as usize);
+                (&self.mantissa * five, self.exponent)
+            }
+            BASE_BINARY => (&self.mantissa << (self.exponent as usize), 0),
+            _ => return None,
+        };
+        if exponent >= 0 {
+            let scaled = mantissa * num_traits::pow(BigInt::from(10), exponent as usize);
+            return Some(scaled.to_string());
+        }
+        let digits = mantissa.abs().to_string();
+        let places = (-exponent) as usize;
+        let padded = format!("{:0>width$}", digits, width = places + 1);
+        let (int_part, frac_part) = padded.split_at(padded.len() - places);
+        let sign = if mantissa.is_negative() { "-" } else { "" };
+        Some(format!("{}{}.{}", sign, int_part, frac_part))
+    }
+}
+
+impl EncryptedNumber {
+    pub fn encrypt(ek: &PaillierEncryptionKey, x: &EncodedNumber) -> EncryptedNumber {
+        EncryptedNumber::encrypt_with_rng(ek, x, &mut OsRng)
+    }
+
+    /// `encrypt` with randomness drawn from rng
+    pub fn encrypt_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        x: &EncodedNumber,
+        rng: &mut R,
+    ) -> EncryptedNumber {
+        EncryptedNumber {
+            c: Paillier::encrypt_with_rng(ek, &x.mantissa, rng),
+            base: x.base,
+            exponent: x.exponent,
+        }
+    }
+
+    pub fn decrypt(dk: &PaillierDecryptionKey, ek: &PaillierEncryptionKey, x: &EncryptedNumber) -> EncodedNumber {
+        EncodedNumber {
+            mantissa: Paillier::decrypt(dk, ek, &x.c),
+            base: x.base,
+            exponent: x.exponent,
+        }
+    }
+
+    pub fn rerandomize(ek: &PaillierEncryptionKey, x: &EncryptedNumber) -> EncryptedNumber {
+        EncryptedNumber::rerandomize_with_rng(ek, x, &mut OsRng)
+    }
+
+    /// `rerandomize` with randomness drawn from rng
+    pub fn rerandomize_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        x: &EncryptedNumber,
+        rng: &mut R,
+    ) -> EncryptedNumber {
+        EncryptedNumber {
+            c: Paillier::rerandomize_with_rng(ek, &x.c, rng),
+            base: x.base,
+            exponent: x.exponent,
+        }
+    }
+
+    /// The same value with a smaller exponent. `InvalidArgument` if
+    /// new_exponent is larger than the exponent, `PlaintextOutOfRange` if
+    /// the scaling factor alone reaches n/2. The mantissa times the factor
+    /// must stay below n/2 as well, which only the key holder can check.
+    pub fn decrease_exponent_to(
+        ek: &PaillierEncryptionKey,
+        x: &EncryptedNumber,
+        new_exponent: i32,
+    ) -> Result<EncryptedNumber, HopeError> {
+        let factor = exponent_factor_within(x.base, x.exponent, new_exponent, ek.n.bits())?;
+        check_range(ek, &factor)?;
+        Ok(EncryptedNumber {
+            c: Paillier::mul_const(ek, &x.c, &factor),
+            base: x.base,
+            exponent: new_exponent,
+        })
+    }
+
+    /// Sum of two encrypted values, `InvalidArgument` if their bases differ
+    pub fn add(ek: &PaillierEncryptionKey, a: &EncryptedNumber, b: &EncryptedNumber) -> Result<EncryptedNumber, HopeError> {
+        check_bases(a.base, b.base)?;
+        let exponent = std::cmp::min(a.exponent, b.exponent);
+        let a = EncryptedNumber::decrease_exponent_to(ek, a, exponent)?;
+        let b = EncryptedNumber::decrease_exponent_to(ek, b, exponent)?;
+        Ok(EncryptedNumber {
+            c: Paillier::add(ek, &a.c, &b.c),
+            base: a.base,
+            exponent,
+        })
+    }
+
+    /// Sum of an encrypted and a plaintext value, `InvalidArgument` if their
+    /// bases differ, `PlaintextOutOfRange` if the scaled b reaches n/2
+    pub fn add_const(ek: &PaillierEncryptionKey, a: &EncryptedNumber, b: &EncodedNumber) -> Result<EncryptedNumber, HopeError> {
+        check_bases(a.base, b.base)?;
+        let exponent = std::cmp::min(a.exponent, b.exponent);
+        let a = EncryptedNumber::decrease_exponent_to(ek, a, exponent)?;
+        let b = &b.mantissa * exponent_factor_within(b.base, b.exponent, exponent, ek.n.bits())?;
+        check_range(ek, &b)?;
+        Ok(EncryptedNumber {
+            c: Paillier::add_const(ek, &a.c, &b),
+            base: a.base,
+            exponent,
+        })
+    }
+
+    /// Product of an encrypted and a plaintext value, `InvalidArgument` if
+    /// their bases differ or the exponent overflows
+    pub fn mul_const(ek: &PaillierEncryptionKey, a: &EncryptedNumber, b: &EncodedNumber) -> Result<EncryptedNumber, HopeError> {
+        check_bases(a.base, b.base)?;
+        check_range(ek, &b.mantissa)?;
+        let exponent = a
+            .exponent
+            .checked_add(b.exponent)
+            .ok_or_else(|| HopeError::InvalidArgument(format!("exponent {} + {}", a.exponent, b.exponent)))?;
+        Ok(EncryptedNumber {
+            c: Paillier::mul_const(ek, &a.c, &b.mantissa),
+            base: a.base,
+            exponent,
+        })
+    }
+}
+
+/// base^(exponent - new_exponent), see `exponent_factor_within`, below
+/// 2^MAX_FACTOR_BITS
+pub(crate) fn exponent_factor(base: u32, exponent: i32, new_exponent: i32) -> Result<BigInt, HopeError> {
+    exponent_factor_within(base, exponent, new_exponent, MAX_FACTOR_BITS)
+}
+
+/// base^(exponent - new_exponent), `InvalidArgument` if new_exponent is
+/// larger than exponent. `PlaintextOutOfRange` before the power is computed
+/// if it has at least max_bits bits, e.g. the bits of n, since every factor
+/// that large is out of range anyway.
+pub(crate) fn exponent_factor_within(
+    base: u32,
+    exponent: i32,
+    new_exponent: i32,
+    max_bits: usize,
+) -> Result<BigInt, HopeError> {
+    if new_exponent > exponent {
+        return Err(HopeError::InvalidArgument(format!(
+            "exponent {} cannot grow to {}",
+            exponent, new_exponent
+        )));
+    }
+    let diff = (exponent as i64 - new_exponent as i64) as u64;
+    // base^diff >= 2^(diff * log2_floor(base))
+    if diff * log2_floor(base) as u64 >= max_bits as u64 {
+        return Err(HopeError::PlaintextOutOfRange(format!("{}^{}", base, diff)));
+    }
+    Ok(num_traits::pow(BigInt::from(base), diff as usize))
+}
+
+/// floor(log2(base)), 0 for base 0 and 1
+fn log2_floor(base: u32) -> u32 {
+    31u32.saturating_sub(base.leading_zeros())
+}
+
+/// `PlaintextOutOfRange` unless |m| < n/2
+pub(crate) fn check_range(ek: &PaillierEncryptionKey, m: &BigInt) -> Result<(), HopeError> {
+    if m.abs() * 2 >= ek.n {
+        return Err(HopeError::PlaintextOutOfRange(m.to_string()));
+    }
+    Ok(())
+}
+
+/// `InvalidArgument` unless both bases are equal
+fn check_bases(a: u32, b: u32) -> Result<(), HopeError> {
+    if a != b {
+        return Err(HopeError::InvalidArgument(format!("bases {} and {}", a, b)));
+    }
+    Ok(())
+}
+
+/// x * 2^e without intermediate overflow or underflow of 2^e
+fn ldexp(mut x: f64, mut e: i64) -> f64 {
+    while e > 1000 {
+        x *= 2f64.powi(1000);
+        e -= 1000;
+    }
+    while e < -1000 {
+        x *= 2f64.powi(-1000);
+        e += 1000;
+    }
+    x * 2f64.powi(e as i32)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::paillier::test_keys;
+    use num_traits::One;
+
+
+    #[test]
+    fn encoding_f64() {
+        for x in [0.0, 1.0, -2.5, 0.1, 1e-10, -123456.789, 5e-324, 1.7976931348623157e308].iter() {
+            let e = EncodedNumber::from_f64(*x).unwrap();
+            assert_eq!(e.to_f64(), *x);
+        }
+        assert!(EncodedNumber::from_f64(f64::NAN).is_none());
+        let half = EncodedNumber::from_f64(0.5).unwrap();
+        assert_eq!(half, EncodedNumber::new(BigInt::one(), BASE_BINARY, -1).unwrap());
+        assert!(EncodedNumber::new(BigInt::one(), 1, 0).is_err());
+        assert_eq!(half.to_decimal_string().unwrap(), "0.5");
+        let price = EncodedNumber::from_f64_fixed(19.99, BASE_DECIMAL, -2).unwrap();
+        assert_eq!(price.mantissa, BigInt::from(1999));
+        // extreme exponents saturate without computing base^exponent
+        let huge = EncodedNumber::new(BigInt::from(-3), BASE_DECIMAL, i32::MAX).unwrap();
+        assert_eq!(huge.to_f64(), f64::NEG_INFINITY);
+        let tiny = EncodedNumber::new(BigInt::from(3), BASE_DECIMAL, i32::MIN).unwrap();
+        assert_eq!(tiny.to_f64(), 0.0);
+        assert_eq!(EncodedNumber::new(BigInt::zero(), BASE_DECIMAL, i32::MAX).unwrap().to_f64(), 0.0);
+        assert_eq!(EncodedNumber::new(BigInt::one(), BASE_BINARY, 1023).unwrap().to_f64(), 2f64.powi(1023));
+    }
+
+    #[test]
+    fn encoding_decimal() {
+        let e = EncodedNumber::from_decimal_str("-12.345").unwrap();
+        assert_eq!(e, EncodedNumber::new(BigInt::from(-12345), BASE_DECIMAL, -3).unwrap());
+        assert_eq!(e.to_decimal_string().unwrap(), "-12.345");
+        assert_eq!(e.to_f64(), -12.345);
+        assert_eq!(EncodedNumber::from_decimal_str("0.05").unwrap().to_decimal_string().unwrap(), "0.05");
+        assert_eq!(EncodedNumber::from_decimal_str("42").unwrap().exponent, 0);
+        assert!(EncodedNumber::from_decimal_str("1.2.3").is_none());
+        assert!(EncodedNumber::from_decimal_str("-").is_none());
+        assert_eq!(e.decrease_exponent_to(-5).unwrap().to_decimal_string().unwrap(), "-12.34500");
+        assert!(e.decrease_exponent_to(-2).is_err());
+        assert!(matches!(
+            e.decrease_exponent_to(i32::MIN),
+            Err(HopeError::PlaintextOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn encoding_encrypted() {
+        let (ek, dk) = test_keys();
+        let a = EncryptedNumber::encrypt(&ek, &EncodedNumber::from_decimal_str("12.34").unwrap());
+        let b = EncryptedNumber::encrypt(&ek, &EncodedNumber::from_decimal_str("-0.001").unwrap());
+        let sum = EncryptedNumber::add(&ek, &a, &b).unwrap();
+        assert_eq!(sum.exponent, -3);
+        let plain = EncryptedNumber::decrypt(&dk, &ek, &sum);
+        assert_eq!(plain.to_decimal_string().unwrap(), "12.339");
+        let sum = EncryptedNumber::add_const(&ek, &sum, &EncodedNumber::from_decimal_str("0.6610").unwrap()).unwrap();
+        assert_eq!(EncryptedNumber::decrypt(&dk, &ek, &sum).to_f64(), 13.0);
+        let prod = EncryptedNumber::mul_const(&ek, &a, &EncodedNumber::from_decimal_str("-0.5").unwrap()).unwrap();
+        assert_eq!(EncryptedNumber::decrypt(&dk, &ek, &prod).to_decimal_string().unwrap(), "-6.170");
+
+        let x = EncryptedNumber::encrypt(&ek, &EncodedNumber::from_f64(1.5).unwrap());
+        let y = EncryptedNumber::encrypt(&ek, &EncodedNumber::from_f64(-0.03125).unwrap());
+        let sum = EncryptedNumber::add(&ek, &x, &y).unwrap();
+        assert_eq!(EncryptedNumber::decrypt(&dk, &ek, &sum).to_f64(), 1.46875);
+        // binary and decimal encodings do not mix
+        assert!(EncryptedNumber::add(&ek, &a, &x).is_err());
+        // aligning 10^0 to 10^-60 scales past n/2
+        let big = EncryptedNumber::encrypt(&ek, &EncodedNumber::from_decimal_str("1").unwrap());
+        let tiny = EncryptedNumber::encrypt(&ek, &EncodedNumber::new(BigInt::one(), BASE_DECIMAL, -60).unwrap());
+        assert!(matches!(
+            EncryptedNumber::add(&ek, &big, &tiny),
+            Err(HopeError::PlaintextOutOfRange(_))
+        ));
+        assert!(matches!(
+            EncryptedNumber::add_const(&ek, &tiny, &EncodedNumber::from_decimal_str("1").unwrap()),
+            Err(HopeError::PlaintextOutOfRange(_))
+        ));
+        assert!(matches!(
+            EncryptedNumber::decrease_exponent_to(&ek, &tiny, -59),
+            Err(HopeError::InvalidArgument(_))
+        ));
+        // rejected before 10^(2^31) is computed
+        assert!(matches!(
+            EncryptedNumber::decrease_exponent_to(&ek, &big, i32::MIN),
+            Err(HopeError::PlaintextOutOfRange(_))
+        ));
+        let far = EncodedNumber::new(BigInt::one(), BASE_DECIMAL, i32::MAX).unwrap();
+        assert!(matches!(
+            EncryptedNumber::add_const(&ek, &big, &far),
+            Err(HopeError::PlaintextOutOfRange(_))
+        ));
+    }
+}
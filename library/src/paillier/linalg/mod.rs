@@ -0,0 +1,398 @@
+//! Encrypted vectors and their products with plaintext matrices.
+//!
+//! Values use the fixed-point representation of `encoding`: every entry of
+//! a vector or matrix is mantissa * base^exponent with one public base and
+//! exponent for the whole object. A matrix-vector product adds the
+//! exponents, a bias is scaled to the smaller exponent before it is added.
+//! As with `EncryptedNumber` the mantissas of all results must stay below
+//! n/2 in absolute value, and the same range checks apply.
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, Zero};
+use rayon::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use hope::protocol::model::*;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use crate::error::HopeError;
+use super::encoding::{check_range, exponent_factor, exponent_factor_within};
+use super::{EncodedNumber, Paillier};
+
+/// A plaintext vector of fixed-point values
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EncodedVector {
+    pub values: Vec<BigInt>,
+    pub base: u32,
+    pub exponent: i32,
+}
+
+/// A plaintext row-major matrix of fixed-point values
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EncodedMatrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub values: Vec<BigInt>,
+    pub base: u32,
+    pub exponent: i32,
+}
+
+/// A vector of encrypted mantissas with their public base and exponent
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptedVector {
+    pub cs: Vec<BigInt>,
+    pub base: u32,
+    pub exponent: i32,
+}
+
+impl EncodedVector {
+    /// `InvalidArgument` if base is below 2
+    pub fn new(values: Vec<BigInt>, base: u32, exponent: i32) -> Result<EncodedVector, HopeError> {
+        if base < 2 {
+            return Err(HopeError::InvalidArgument(format!("base {}", base)));
+        }
+        Ok(EncodedVector {
+            values,
+            base,
+            exponent,
+        })
+    }
+
+    /// Rounds every x to the nearest multiple of base^exponent, None if one
+    /// is not finite or base is below 2
+    pub fn from_f64(xs: &[f64], base: u32, exponent: i32) -> Option<EncodedVector> {
+        let values = xs
+            .iter()
+            .map(|x| fixed_mantissa(*x, base, exponent))
+            .collect::<Option<Vec<BigInt>>>()?;
+        EncodedVector::new(values, base, exponent).ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The same values with a smaller exponent, see
+    /// `EncodedNumber::decrease_exponent_to` for the errors
+    pub fn decrease_exponent_to(&self, new_exponent: i32) -> Result<EncodedVector, HopeError> {
+        let factor = exponent_factor(self.base, self.exponent, new_exponent)?;
+        EncodedVector::new(
+            self.values.iter().map(|v| v * &factor).collect(),
+            self.base,
+            new_exponent,
+        )
+    }
+
+    pub fn to_f64(&self) -> Vec<f64> {
+        self.values
+            .iter()
+            .map(|v| {
+                EncodedNumber {
+                    mantissa: v.clone(),
+                    base: self.base,
+                    exponent: self.exponent,
+                }
+                .to_f64()
+            })
+            .collect()
+    }
+}
+
+impl EncodedMatrix {
+    /// `InvalidArgument` if base is below 2 or there are not rows * cols
+    /// values
+    pub fn new(rows: usize, cols: usize, values: Vec<BigInt>, base: u32, exponent: i32) -> Result<EncodedMatrix, HopeError> {
+        if base < 2 {
+            return Err(HopeError::InvalidArgument(format!("base {}", base)));
+        }
+        if rows.checked_mul(cols) != Some(values.len()) {
+            return Err(HopeError::InvalidArgument(format!(
+                "{} values for {} x {}",
+                values.len(),
+                rows,
+                cols
+            )));
+        }
+        Ok(EncodedMatrix {
+            rows,
+            cols,
+            values,
+            base,
+            exponent,
+        })
+    }
+
+    /// Encodes the given rows, None if they differ in length or hold a
+    /// value that is not finite
+    pub fn from_f64(rows: &[Vec<f64>], base: u32, exponent: i32) -> Option<EncodedMatrix> {
+        let cols = rows.first().map_or(0, |r| r.len());
+        if rows.iter().any(|r| r.len() != cols) {
+            return None;
+        }
+        let values = rows
+            .iter()
+            .flat_map(|r| r.iter())
+            .map(|x| fixed_mantissa(*x, base, exponent))
+            .collect::<Option<Vec<BigInt>>>()?;
+        EncodedMatrix::new(rows.len(), cols, values, base, exponent).ok()
+    }
+
+    pub fn row(&self, i: usize) -> &[BigInt] {
+        &self.values[i * self.cols..(i + 1) * self.cols]
+    }
+}
+
+impl EncryptedVector {
+    pub fn encrypt(ek: &PaillierEncryptionKey, v: &EncodedVector) -> EncryptedVector {
+        EncryptedVector::encrypt_with_rng(ek, v, &mut OsRng)
+    }
+
+    /// `encrypt` with randomness drawn from rng
+    pub fn encrypt_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        v: &EncodedVector,
+        rng: &mut R,
+    ) -> EncryptedVector {
+        EncryptedVector {
+            cs: Paillier::encrypt_batch_with_rng(ek, &v.values, rng),
+            base: v.base,
+            exponent: v.exponent,
+        }
+    }
+
+    pub fn decrypt(dk: &PaillierDecryptionKey, ek: &PaillierEncryptionKey, x: &EncryptedVector) -> EncodedVector {
+        EncodedVector {
+            values: Paillier::decrypt_batch(dk, ek, &x.cs),
+            base: x.base,
+            exponent: x.exponent,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cs.is_empty()
+    }
+
+    /// The same values with a smaller exponent, see
+    /// `EncryptedNumber::decrease_exponent_to` for the errors
+    pub fn decrease_exponent_to(
+        ek: &PaillierEncryptionKey,
+        x: &EncryptedVector,
+        new_exponent: i32,
+    ) -> Result<EncryptedVector, HopeError> {
+        let factor = exponent_factor_within(x.base, x.exponent, new_exponent, ek.n.bits())?;
+        check_range(ek, &factor)?;
+        Ok(EncryptedVector {
+            cs: x.cs.par_iter().map(|c| Paillier::mul_const(ek, c, &factor)).collect(),
+            base: x.base,
+            exponent: new_exponent,
+        })
+    }
+
+    /// Encryption of m * x, `InvalidArgument` if the bases or dimensions do
+    /// not match or the exponent overflows
+    pub fn mul_matrix(ek: &PaillierEncryptionKey, m: &EncodedMatrix, x: &EncryptedVector) -> Result<EncryptedVector, HopeError> {
+        check_shape(m.base, x.base, m.cols, x.len())?;
+        if m.values.iter().any(|v| check_range(ek, v).is_err()) {
+            return Err(HopeError::PlaintextOutOfRange(String::from("matrix entry")));
+        }
+        let exponent = m
+            .exponent
+            .checked_add(x.exponent)
+            .ok_or_else(|| HopeError::InvalidArgument(format!("exponent {} + {}", m.exponent, x.exponent)))?;
+        let cs = (0..m.rows)
+            .into_par_iter()
+            .map(|i| Paillier::inner_product(ek, &x.cs, m.row(i)))
+            .collect::<Result<Vec<BigInt>, HopeError>>()?;
+        Ok(EncryptedVector {
+            cs,
+            base: x.base,
+            exponent,
+        })
+    }
+
+    /// Encryption of x + b, `InvalidArgument` if the bases or dimensions do
+    /// not match, `PlaintextOutOfRange` if a scaled entry of b reaches n/2
+    pub fn add_bias(ek: &PaillierEncryptionKey, x: &EncryptedVector, b: &EncodedVector) -> Result<EncryptedVector, HopeError> {
+        check_shape(x.base, b.base, x.len(), b.len())?;
+        let exponent = std::cmp::min(x.exponent, b.exponent);
+        let x = EncryptedVector::decrease_exponent_to(ek, x, exponent)?;
+        let factor = exponent_factor_within(b.base, b.exponent, exponent, ek.n.bits())?;
+        let b: Vec<BigInt> = b.values.iter().map(|v| v * &factor).collect();
+        for v in &b {
+            check_range(ek, v)?;
+        }
+        Ok(EncryptedVector {
+            cs: x
+                .cs
+                .par_iter()
+                .zip(b.par_iter())
+                .map(|(c, v)| Paillier::add_const(ek, c, v))
+                .collect(),
+            base: x.base,
+            exponent,
+        })
+    }
+
+    /// Encryption of x + y, `InvalidArgument` if the bases or dimensions do
+    /// not match, `PlaintextOutOfRange` if the scaling factor of the
+    /// alignment reaches n/2
+    pub fn add(ek: &PaillierEncryptionKey, x: &EncryptedVector, y: &EncryptedVector) -> Result<EncryptedVector, HopeError> {
+        check_shape(x.base, y.base, x.len(), y.len())?;
+        let exponent = std::cmp::min(x.exponent, y.exponent);
+        let x = EncryptedVector::decrease_exponent_to(ek, x, exponent)?;
+        let y = EncryptedVector::decrease_exponent_to(ek, y, exponent)?;
+        Ok(EncryptedVector {
+            cs: x
+                .cs
+                .par_iter()
+                .zip(y.cs.par_iter())
+                .map(|(a, b)| Paillier::add(ek, a, b))
+                .collect(),
+            base: x.base,
+            exponent,
+        })
+    }
+
+    /// Encryption of m * x + b, the output of a linear layer
+    pub fn affine(
+        ek: &PaillierEncryptionKey,
+        m: &EncodedMatrix,
+        x: &EncryptedVector,
+        b: &EncodedVector,
+    ) -> Result<EncryptedVector, HopeError> {
+        EncryptedVector::add_bias(ek, &EncryptedVector::mul_matrix(ek, m, x)?, b)
+    }
+
+    pub fn rerandomize(ek: &PaillierEncryptionKey, x: &EncryptedVector) -> EncryptedVector {
+        EncryptedVector::rerandomize_with_rng(ek, x, &mut OsRng)
+    }
+
+    /// `rerandomize` with randomness drawn from rng
+    pub fn rerandomize_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        x: &EncryptedVector,
+        rng: &mut R,
+    ) -> EncryptedVector {
+        let zeros = vec![BigInt::zero(); x.len()];
+        let masks = Paillier::encrypt_batch_with_rng(ek, &zeros, rng);
+        EncryptedVector {
+            cs: x
+                .cs
+                .par_iter()
+                .zip(masks.par_iter())
+                .map(|(c, z)| Paillier::add(ek, c, z))
+                .collect(),
+            base: x.base,
+            exponent: x.exponent,
+        }
+    }
+}
+
+/// `InvalidArgument` unless the bases and the lengths are equal
+fn check_shape(base_a: u32, base_b: u32, len_a: usize, len_b: usize) -> Result<(), HopeError> {
+    if base_a != base_b || len_a != len_b {
+        return Err(HopeError::InvalidArgument(format!(
+            "base {} length {}, base {} length {}",
+            base_a, len_a, base_b, len_b
+        )));
+    }
+    Ok(())
+}
+
+/// round(x / base^exponent)
+fn fixed_mantissa(x: f64, base: u32, exponent: i32) -> Option<BigInt> {
+    if !x.is_finite() {
+        return None;
+    }
+    BigInt::from_f64((x / (base as f64).powi(exponent)).round())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::paillier::test_keys;
+    use crate::paillier::encoding::BASE_DECIMAL;
+
+    #[test]
+    fn linalg_affine() {
+        let (ek, dk) = test_keys();
+        let w = vec![vec![0.5, -1.25, 2.0], vec![-0.001, 3.5, 0.75]];
+        let b = vec![0.1, -2.5];
+        let x = vec![1.5, -0.25, 4.0];
+        let m = EncodedMatrix::from_f64(&w, BASE_DECIMAL, -4).unwrap();
+        let bias = EncodedVector::from_f64(&b, BASE_DECIMAL, -4).unwrap();
+        let cx = EncryptedVector::encrypt(&ek, &EncodedVector::from_f64(&x, BASE_DECIMAL, -3).unwrap());
+        let y = EncryptedVector::affine(&ek, &m, &cx, &bias).unwrap();
+        assert_eq!(y.exponent, -7);
+        let y = EncryptedVector::decrypt(&dk, &ek, &y).to_f64();
+        for i in 0..2 {
+            let expected: f64 = w[i].iter().zip(x.iter()).map(|(a, b)| a * b).sum::<f64>() + b[i];
+            assert!((y[i] - expected).abs() < 1e-9, "{} != {}", y[i], expected);
+        }
+    }
+
+    #[test]
+    fn linalg_mismatch() {
+        let (ek, _) = test_keys();
+        let m = EncodedMatrix::from_f64(&[vec![1.0, 2.0]], BASE_DECIMAL, 0).unwrap();
+        let x3 = EncryptedVector::encrypt(&ek, &EncodedVector::from_f64(&[1.0, 2.0, 3.0], BASE_DECIMAL, 0).unwrap());
+        assert!(EncryptedVector::mul_matrix(&ek, &m, &x3).is_err());
+        let x2 = EncryptedVector::encrypt(&ek, &EncodedVector::from_f64(&[1.0, 2.0], 2, 0).unwrap());
+        assert!(EncryptedVector::mul_matrix(&ek, &m, &x2).is_err());
+        assert!(EncryptedVector::add_bias(&ek, &x3, &EncodedVector::from_f64(&[1.0], BASE_DECIMAL, 0).unwrap()).is_err());
+        assert!(EncodedVector::new(vec![], 1, 0).is_err());
+        // aligning 10^0 to 10^-60 scales past n/2
+        let tiny = EncodedVector::from_f64(&[0.0, 0.0, 0.0], BASE_DECIMAL, -60).unwrap();
+        assert!(matches!(
+            EncryptedVector::add_bias(&ek, &x3, &tiny),
+            Err(HopeError::PlaintextOutOfRange(_))
+        ));
+        assert!(matches!(
+            EncryptedVector::decrease_exponent_to(&ek, &x3, 1),
+            Err(HopeError::InvalidArgument(_))
+        ));
+        assert!(EncodedMatrix::from_f64(&[vec![1.0, 2.0], vec![3.0]], BASE_DECIMAL, 0).is_none());
+        assert!(matches!(
+            EncodedMatrix::new(2, 2, vec![BigInt::zero(); 3], BASE_DECIMAL, 0),
+            Err(HopeError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            EncodedMatrix::new(1, 1, vec![BigInt::zero()], 1, 0),
+            Err(HopeError::InvalidArgument(_))
+        ));
+        // rejected before 10^(2^31) is computed
+        let far = EncodedVector::new(vec![BigInt::zero(); 3], BASE_DECIMAL, i32::MIN).unwrap();
+        let x_far = EncryptedVector::encrypt(&ek, &far);
+        assert!(matches!(
+            EncryptedVector::add(&ek, &x3, &x_far),
+            Err(HopeError::PlaintextOutOfRange(_))
+        ));
+        assert!(matches!(
+            EncryptedVector::add_bias(&ek, &x3, &far),
+            Err(HopeError::PlaintextOutOfRange(_))
+        ));
+        assert!(matches!(
+            EncodedVector::new(vec![BigInt::zero()], BASE_DECIMAL, i32::MAX).unwrap().decrease_exponent_to(0),
+            Err(HopeError::PlaintextOutOfRange(_))
+        ));
+        assert!(EncodedVector::from_f64(&[f64::NAN], BASE_DECIMAL, 0).is_none());
+    }
+
+    #[test]
+    fn linalg_serialize() {
+        let (ek, dk) = test_keys();
+        let v = EncodedVector::from_f64(&[-1.5, 0.0, 2.25], BASE_DECIMAL, -2).unwrap();
+        let x = EncryptedVector::encrypt(&ek, &v);
+        let json = serde_json::to_string(&x).unwrap();
+        let y: EncryptedVector = serde_json::from_str(&json).unwrap();
+        assert_eq!(y.cs, x.cs);
+        let z = EncryptedVector::add(&ek, &y, &EncryptedVector::rerandomize(&ek, &x)).unwrap();
+        assert_eq!(EncryptedVector::decrypt(&dk, &ek, &z).to_f64(), vec![-3.0, 0.0, 4.5]);
+    }
+}
@@ -1,12 +1,15 @@
-use num_bigint::{BigInt, BigUint};
-use std::num::FromPrimitive;
-use std::num::{Zero, One};
+//! Prime generation for PAILLIER keys: uniform sampling, Miller-Rabin
+//! testing and generation of random, strong and safe primes.
+use num_bigint::BigUint;
+use num_integer::Integer;
 use num_traits::identities::Zero;
 use num_traits::cast::FromPrimitive;
 use num_traits::identities::One;
-use std::env;
+use hope::config::{KeygenConfig, PrimeType};
+use rand_core::{CryptoRng, RngCore};
 
-const NUM_TESTS: usize = 24;
+/// smallest prime size accepted by the key generators
+pub const MIN_PRIME_BITS: usize = 32;
 const TRIAL_DIVISORS: [u32; 167] = [
     3,
     5,
@@ -177,85 +180,157 @@ const TRIAL_DIVISORS: [u32; 167] = [
     997,
 ];
 
-pub fn generate_urandom(len: usize) -> BigUint {
-    let mut buf = vec![0; len];
-    let r = getrandom::getrandom(&mut buf[0..len]);
-    let a = BigUint::from_bytes_be(&buf);
-    assert!(a.bits() + 1 == len);
-    a
+/// Uniform integer of at most `bits` bits
+pub fn generate_urandom<R: RngCore + CryptoRng>(bits: usize, rng: &mut R) -> BigUint {
+    let len = bits.div_ceil(8);
+    let mut buf = vec![0u8; len];
+    rng.fill_bytes(&mut buf);
+    if len > 0 {
+        buf[0] &= 0xffu8 >> (8 * len - bits);
+    }
+    BigUint::from_bytes_be(&buf)
 }
 
-pub fn generate_urandom_inrange(lower: BigUint, upper: BigUint) -> BigUint {
+/// Uniform integer in [0, upper), by rejection sampling
+pub fn generate_urandom_below<R: RngCore + CryptoRng>(upper: &BigUint, rng: &mut R) -> BigUint {
+    assert!(!upper.is_zero());
+    let bits = upper.bits();
     loop {
-        let p: BigUint = generate_urandom((upper.bits() - lower.bits()) / 2);
-        if p.bits() + 1 > lower.bits() && p.bits() + 1 < upper.bits() {
-            return p;
+        let a = generate_urandom(bits, rng);
+        if &a < upper {
+            return a;
         }
     }
 }
 
+/// Uniform integer in [lower, upper)
+pub fn generate_urandom_inrange<R: RngCore + CryptoRng>(lower: BigUint, upper: BigUint, rng: &mut R) -> BigUint {
+    assert!(lower < upper);
+    let a = generate_urandom_below(&(&upper - &lower), rng);
+    lower + a
+}
+
+/// Uniform odd integer of exactly `bits` bits whose two top bits are set,
+/// so that the product of two such numbers has exactly 2*bits bits
+fn random_candidate<R: RngCore + CryptoRng>(bits: usize, rng: &mut R) -> BigUint {
+    let top = (BigUint::one() << (bits - 1)) | (BigUint::one() << (bits - 2)) | BigUint::one();
+    generate_urandom(bits, rng) | top
+}
 
-pub fn generate_prime(len: usize) -> BigUint {
+/// Random prime of exactly `bits` bits
+pub fn generate_prime<R: RngCore + CryptoRng>(bits: usize, rounds: usize, rng: &mut R) -> BigUint {
+    assert!(bits >= 3);
     loop {
-        let p: BigUint = nextprime(generate_urandom(len));
-        if p.bits() + 1 == len {
+        let p = random_candidate(bits, rng);
+        if is_probable_prime(&p, rounds, rng) {
             return p;
         }
     }
 }
 
-pub fn nextprime(nonp: BigUint) -> BigUint {
+/// Generate a prime p such that p-1 has a prime factor of half its size
+pub fn generate_strong_prime<R: RngCore + CryptoRng>(bits: usize, rounds: usize, rng: &mut R) -> BigUint {
+    assert!(bits >= MIN_PRIME_BITS);
+    let r = generate_prime(bits / 2, rounds, rng);
+    let r2 = &r << 1;
+    // p = 2kr + 1 with the two top bits of p set
+    let lower = (BigUint::from_u32(3).unwrap() << (bits - 2)).div_ceil(&r2);
+    let upper = ((BigUint::one() << bits) - 1u32) / &r2;
     loop {
-        let a: BigUint = generate_urandom(nonp.bits());
-        let p: BigUint = find_prime(a, NUM_TESTS);
-        if p.bits() + 1 == nonp.bits() {
+        let k = generate_urandom_inrange(lower.clone(), upper.clone(), rng);
+        let p = &k * &r2 + 1u32;
+        if is_probable_prime(&p, rounds, rng) {
             return p;
         }
     }
 }
-/// Generate a prime p such that p-1 has a large prime factor
-pub fn generate_strong_prime(len: usize) -> BigUint {
-    // generate a half-size prime pp
-    let pp = generate_prime(len / 2);
-    let a = generate_urandom(len - len / 2 + 1);
 
-    let mut p: BigUint = &pp * &a + One::one();
-    assert!(p.bits() + 1 >= len);
+/// Generate a safe prime p, i.e. (p-1)/2 is prime as well
+pub fn generate_safe_prime<R: RngCore + CryptoRng>(bits: usize, rounds: usize, rng: &mut R) -> BigUint {
+    assert!(bits >= MIN_PRIME_BITS);
     loop {
-        if p.probab_prime_p(40) {
-            if p.bits() + 1 == len {
-                return p;
-            } else {
-                return generate_strong_prime(len);
-            }
-        } else {
-            p = &p + &a;
+        let q = random_candidate(bits - 1, rng);
+        let p = (&q << 1) + 1u32;
+        // test the cheap conditions of both before running Miller-Rabin
+        if !sieve(&q) || !sieve(&p) {
+            continue;
+        }
+        if is_probable_prime(&q, rounds, rng) && is_probable_prime(&p, rounds, rng) {
+            return p;
         }
     }
 }
 
-fn find_prime(mut n: BigUint, ntests: usize) -> BigUint {
-    // If the input is even, it should be made odd.
-    if &n % 2u32 == BigUint::zero() {
-        n += 1u32;
+/// Prime of the given type and size
+pub fn generate<R: RngCore + CryptoRng>(bits: usize, kind: PrimeType, rounds: usize, rng: &mut R) -> BigUint {
+    match kind {
+        PrimeType::Random => generate_prime(bits, rounds, rng),
+        PrimeType::Strong => generate_strong_prime(bits, rounds, rng),
+        PrimeType::Safe => generate_safe_prime(bits, rounds, rng),
     }
-    let two: BigUint = BigUint::from_u32(2).unwrap();
-    while !isprime(&n, &ntests) {
-        n += &two;
+}
+
+/// Prime factors p != q of a modulus n = p*q of exactly `keysize` bits with
+/// gcd(n, (p-1)(q-1)) = 1. Returns `None` if the configuration rejects the
+/// key size.
+pub fn generate_primes<R: RngCore + CryptoRng>(
+    keysize: usize,
+    config: &KeygenConfig,
+    rng: &mut R,
+) -> Option<(BigUint, BigUint)> {
+    if keysize & 1 != 0
+        || keysize < config.min_key_size
+        || keysize / 2 < MIN_PRIME_BITS
+        || config.rounds == 0
+    {
+        return None;
+    }
+    loop {
+        let p = generate(keysize / 2, config.prime_type, config.rounds, rng);
+        let q = generate(keysize / 2, config.prime_type, config.rounds, rng);
+        if p == q {
+            continue;
+        }
+        let n = &p * &q;
+        let phi = (&p - 1u32) * (&q - 1u32);
+        if n.bits() == keysize && n.gcd(&phi).is_one() {
+            return Some((p, q));
+        }
     }
-    n
 }
 
-fn isprime(n: &BigUint, ntests: &usize) -> bool {
+/// Miller-Rabin test with `rounds` uniformly chosen witnesses
+pub fn is_probable_prime<R: RngCore + CryptoRng>(n: &BigUint, rounds: usize, rng: &mut R) -> bool {
+    isprime(n, &rounds, rng)
+}
+
+/// false if n has a small factor, unless n is that factor
+fn sieve(n: &BigUint) -> bool {
     for i in TRIAL_DIVISORS.iter() {
         if n % i == BigUint::zero() {
             return n == &(BigUint::from_u32(*i).unwrap());
         }
     }
-    let (d, r) = decompose(n);
+    true
+}
+
+fn isprime<R: RngCore + CryptoRng>(n: &BigUint, ntests: &usize, rng: &mut R) -> bool {
     let two: BigUint = BigUint::from_u32(2).unwrap();
+    if n < &two {
+        return false;
+    }
+    if n.is_even() {
+        return n == &two;
+    }
+    if !sieve(n) {
+        return false;
+    }
+    if n < &BigUint::from_u32(997 * 997).unwrap() {
+        return true;
+    }
+    let (d, r) = decompose(n);
     for _ in 0..*ntests {
-        let a: BigUint = generate_urandom_inrange(two, (n - 2u16));
+        let a: BigUint = generate_urandom_inrange(two.clone(), n - 1u32, rng);
         if trial_composite(n, &d, &r, &a) {
             return false;
         }
@@ -263,21 +338,17 @@ fn isprime(n: &BigUint, ntests: &usize) -> bool {
     true
 }
 
-
 fn trial_composite(n: &BigUint, d: &BigUint, r: &usize, a: &BigUint) -> bool {
-    let mut x = a.modpow(&d, &n);
+    let mut x = a.modpow(d, n);
     if (x == BigUint::one()) || (x == (n - 1u32)) {
         return false;
     }
-    let two = BigUint::from_u32(2).unwrap();
-    for i in 0..(r - 1) {
-        let e = d * (&two << i);
-        x = x * x % n;
+    for _ in 1..*r {
+        x = &x * &x % n;
         if n - 1u32 == x {
             return false;
         }
     }
-
     true
 }
 
@@ -291,19 +362,161 @@ fn decompose(n: &BigUint) -> (BigUint, usize) {
         d /= 2u32;
     }
     (d, r)
-
 }
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use rand_chacha::ChaChaRng;
+    use rand_core::{OsRng, SeedableRng};
+
+    /// Miller-Rabin rounds of the tests, error probability below 4^-40
+    const NUM_TESTS: usize = 40;
+
+    fn parse(s: &str) -> BigUint {
+        BigUint::parse_bytes(s.as_bytes(), 10).unwrap()
+    }
+
+    fn small_config(min: usize, kind: PrimeType) -> KeygenConfig {
+        KeygenConfig {
+            key_size: min,
+            min_key_size: min,
+            rounds: 20,
+            prime_type: kind,
+        }
+    }
+
+    #[test]
+    fn miller_known_primes() {
+        for p in &[
+            "2",
+            "3",
+            "5",
+            "997",
+            "1009",
+            "1000003",
+            "2147483647",
+            "2305843009213693951",
+            "618970019642690137449562111",
+            "170141183460469231731687303715884105727",
+        ] {
+            assert!(is_probable_prime(&parse(p), NUM_TESTS, &mut OsRng), "{} is prime", p);
+        }
+    }
+
+    #[test]
+    fn miller_known_composites() {
+        for c in &[
+            "0",
+            "1",
+            "4",
+            "561",
+            "994009",
+            // Carmichael numbers
+            "41041",
+            "825265",
+            "321197185",
+            // strong pseudoprime to the bases 2, 3, 5 and 7
+            "3215031751",
+            // strong pseudoprime to all prime bases up to 23
+            "3825123056546413051",
+            // 2^64 + 1
+            "18446744073709551617",
+            // (2^61 - 1) * (2^89 - 1)
+            "1427247692705959880439315947500961989719490561",
+        ] {
+            assert!(!is_probable_prime(&parse(c), NUM_TESTS, &mut OsRng), "{} is composite", c);
+        }
+    }
+
+    #[test]
+    fn miller_urandom_bits() {
+        let mut top = 0;
+        for _ in 0..2000 {
+            let a = generate_urandom(13, &mut OsRng);
+            assert!(a.bits() <= 13);
+            if a.bits() == 13 {
+                top += 1;
+            }
+        }
+        // the top bit is set with probability 1/2, i.e. 1000 +- 22
+        assert!(top > 850 && top < 1150, "top bit set {} times", top);
+        assert!(generate_urandom(0, &mut OsRng).is_zero());
+    }
+
+    #[test]
+    fn miller_urandom_uniform() {
+        // chi-square test with 9 degrees of freedom, 27.88 is the 0.999 quantile
+        let n = 10u32;
+        let samples = 20000;
+        let mut counts = vec![0u32; n as usize];
+        for _ in 0..samples {
+            let a = generate_urandom_inrange(
+                BigUint::from_u32(5).unwrap(),
+                BigUint::from_u32(5 + n).unwrap(),
+                &mut OsRng,
+            );
+            let i = (a - 5u32).to_u32_digits();
+            counts[*i.first().unwrap_or(&0) as usize] += 1;
+        }
+        let expected = samples as f64 / n as f64;
+        let chi2: f64 = counts
+            .iter()
+            .map(|c| (*c as f64 - expected).powi(2) / expected)
+            .sum();
+        assert!(chi2 < 27.88, "chi2 = {}", chi2);
+    }
+
+    #[test]
+    fn miller_prime_types() {
+        for _ in 0..4 {
+            let p = generate(64, PrimeType::Random, NUM_TESTS, &mut OsRng);
+            assert_eq!(p.bits(), 64);
+            assert_eq!(&p >> 62usize, BigUint::from_u32(3).unwrap());
+            assert!(is_probable_prime(&p, NUM_TESTS, &mut OsRng));
+            let p = generate(64, PrimeType::Strong, NUM_TESTS, &mut OsRng);
+            assert_eq!(p.bits(), 64);
+            assert_eq!(&p >> 62usize, BigUint::from_u32(3).unwrap());
+            assert!(is_probable_prime(&p, NUM_TESTS, &mut OsRng));
+            let p = generate(64, PrimeType::Safe, NUM_TESTS, &mut OsRng);
+            assert_eq!(p.bits(), 64);
+            assert!(is_probable_prime(&p, NUM_TESTS, &mut OsRng));
+            assert!(is_probable_prime(&((p - 1u32) >> 1), NUM_TESTS, &mut OsRng));
+        }
+    }
+
+    #[test]
+    fn miller_generate_primes() {
+        for kind in &[PrimeType::Random, PrimeType::Strong, PrimeType::Safe] {
+            let config = small_config(128, *kind);
+            let (p, q) = generate_primes(128, &config, &mut OsRng).unwrap();
+            let n = &p * &q;
+            assert!(p != q);
+            assert_eq!(n.bits(), 128);
+            assert!(n.gcd(&((&p - 1u32) * (&q - 1u32))).is_one());
+        }
+    }
+
+    #[test]
+    fn miller_reject_config() {
+        let config = small_config(128, PrimeType::Random);
+        assert!(generate_primes(126, &config, &mut OsRng).is_none());
+        assert!(generate_primes(129, &config, &mut OsRng).is_none());
+        assert!(generate_primes(32, &small_config(32, PrimeType::Random), &mut OsRng).is_none());
+        let mut zero = config.clone();
+        zero.rounds = 0;
+        assert!(generate_primes(128, &zero, &mut OsRng).is_none());
+        assert!(generate_primes(1024, &KeygenConfig::default(), &mut OsRng).is_none());
+    }
 
     #[test]
-    fn miller_generate() {
-        let p = Paillier::new(256);
-        let n1 = p.encrypt(100);
-        let n2 = p.encrypt(101);
-        assert_eq!(p.decrypt(p.encrypt(201)), p.decrypt(p.add_cipher(n1, n2)));
+    fn miller_seeded_rng() {
+        let config = small_config(128, PrimeType::Strong);
+        let a = generate_primes(128, &config, &mut ChaChaRng::seed_from_u64(7)).unwrap();
+        let b = generate_primes(128, &config, &mut ChaChaRng::seed_from_u64(7)).unwrap();
+        let c = generate_primes(128, &config, &mut ChaChaRng::seed_from_u64(8)).unwrap();
+        assert!(a == b);
+        assert!(a != c);
     }
 }
@@ -1,8 +1,40 @@
 /// Paillier cryptosystem
 mod millerrabin;
-use bn::arith::U256;
-use num_traits::{Zero, One};
+/// Damgard-Jurik generalisation modulo n^(s+1)
+pub mod damgardjurik;
+/// t-of-l threshold decryption
+pub mod threshold;
+/// non-interactive zero-knowledge proofs on ciphertexts
+pub mod proofs;
+/// several small values per plaintext
+pub mod packing;
+/// fixed- and floating-point plaintexts
+pub mod encoding;
+/// precomputed randomness for encryption
+pub mod pool;
+/// parallel batch encryption and aggregation
+pub mod batch;
+/// encrypted vectors and plaintext matrices
+pub mod linalg;
+/// level-2 homomorphic encryption after Catalano and Fiore
+pub mod catalanofiore;
+use num_bigint::{BigInt, Sign, ToBigInt};
+use num_integer::Integer;
+use num_traits::One;
+use sha2::{Digest, Sha256};
 use hope::protocol::model::*;
+use hope::config::KeygenConfig;
+use crate::error::HopeError;
+use rand_core::{CryptoRng, OsRng, RngCore};
+
+pub use self::damgardjurik::DamgardJurik;
+pub use self::threshold::ThresholdPaillier;
+pub use self::proofs::PaillierProofs;
+pub use self::packing::{PackedCiphertext, PaillierPacker};
+pub use self::encoding::{EncodedNumber, EncryptedNumber};
+pub use self::pool::RandomnessPool;
+pub use self::linalg::{EncodedMatrix, EncodedVector, EncryptedVector};
+pub use self::catalanofiore::{CatalanoFiore, Level1Ciphertext, Level2Ciphertext};
 
 pub struct Paillier {
     pub pk: PaillierEncryptionKey,
@@ -10,108 +42,388 @@ pub struct Paillier {
 }
 
 impl Paillier {
-    pub fn new(keysize: usize) -> Paillier {
-        let (pk, sk) = Paillier::keygen(keysize);
-        Paillier {
-            pk: pk,
+    pub fn new(keysize: usize) -> Result<Paillier, HopeError> {
+        let (pk, sk) = Paillier::keygen(keysize)?;
+        Ok(Paillier {
+            pk,
             sk: Some(sk),
-        }
+        })
+    }
+
+    /// Generates a key pair with the default `KeygenConfig`
+    pub fn keygen(keysize: usize) -> Result<(PaillierEncryptionKey, PaillierDecryptionKey), HopeError> {
+        Paillier::keygen_with(keysize, &KeygenConfig::default())
+    }
+
+    /// Generates a key pair; `KeyGeneration` if config rejects keysize
+    pub fn keygen_with(
+        keysize: usize,
+        config: &KeygenConfig,
+    ) -> Result<(PaillierEncryptionKey, PaillierDecryptionKey), HopeError> {
+        Paillier::keygen_with_rng(keysize, config, &mut OsRng)
     }
 
-    pub fn keygen(keysize: usize) -> (PaillierEncryptionKey, PaillierDecryptionKey) {
-        assert!(keysize % 2 == 0);
-        let p = millerrabin::generate_strong_prime(keysize / 2)
-            .to_bigint()
-            .unwrap();
-        let q = millerrabin::generate_strong_prime(keysize / 2)
-            .to_bigint()
-            .unwrap();
-        let n: BigInt = &p * &q;
-        let g = &n + One::one();
-        let lambda: BigInt = (&p - One::one()) * (&q - One::one());
-        let mu = lambda.invert(&n).unwrap();
+    /// Generates a key pair from the primes drawn with rng
+    pub fn keygen_with_rng<R: RngCore + CryptoRng>(
+        keysize: usize,
+        config: &KeygenConfig,
+        rng: &mut R,
+    ) -> Result<(PaillierEncryptionKey, PaillierDecryptionKey), HopeError> {
+        let (p, q) = millerrabin::generate_primes(keysize, config, rng).ok_or(HopeError::KeyGeneration)?;
+        Ok(Paillier::keygen_from_primes(
+            &p.to_bigint().unwrap(),
+            &q.to_bigint().unwrap(),
+        ))
+    }
+
+    /// Builds a key pair from the prime factors of n. The decryption key
+    /// keeps p and q so that it can decrypt via the CRT.
+    pub fn keygen_from_primes(p: &BigInt, q: &BigInt) -> (PaillierEncryptionKey, PaillierDecryptionKey) {
+        let n: BigInt = p * q;
+        let g = &n + BigInt::one();
+        let lambda: BigInt = (p - BigInt::one()) * (q - BigInt::one());
+        let mu = invert(&lambda, &n).unwrap();
         let n2 = &n * &n;
+        let ek = PaillierEncryptionKey { n, n2, g };
+        let crt = Paillier::crt_key(&ek, p, q);
         (
-            PaillierEncryptionKey { n: n, n2: n2, g: g },
+            ek,
             PaillierDecryptionKey {
-                lambda: lambda,
-                mu: mu,
+                lambda,
+                mu,
+                crt: Some(crt),
             },
         )
     }
 
+    /// Derives the CRT constants for the factors p and q of ek.n, panics
+    /// unless they are distinct primes, see `try_crt_key`
+    pub fn crt_key(ek: &PaillierEncryptionKey, p: &BigInt, q: &BigInt) -> PaillierCrtKey {
+        Paillier::try_crt_key(ek, p, q).unwrap()
+    }
+
+    /// `crt_key`, `NotInvertible` if one of the constants does not exist.
+    /// p and q must be larger than 1.
+    pub fn try_crt_key(ek: &PaillierEncryptionKey, p: &BigInt, q: &BigInt) -> Result<PaillierCrtKey, HopeError> {
+        let p2 = p * p;
+        let q2 = q * q;
+        let hp = invert(&l_function(&ek.g.modpow(&(p - BigInt::one()), &p2), p), p)?;
+        let hq = invert(&l_function(&ek.g.modpow(&(q - BigInt::one()), &q2), q), q)?;
+        let qinv = invert(q, p)?;
+        Ok(PaillierCrtKey {
+            p: p.clone(),
+            q: q.clone(),
+            p2,
+            q2,
+            hp,
+            hq,
+            qinv,
+        })
+    }
+
     pub fn encrypt(ek: &PaillierEncryptionKey, m: &BigInt) -> BigInt {
-        let mut r = millerrabin::generate_urandom_inrange(Zero::zero(), ek.n.to_biguint().unwrap())
-            .to_bigint()
-            .unwrap();
-        while r.gcd(&ek.n) != One::one() {
-            r = millerrabin::generate_urandom_inrange(Zero::zero(), ek.n.to_biguint().unwrap())
-                .to_bigint()
-                .unwrap();
+        Paillier::encrypt_with_rng(ek, m, &mut OsRng)
+    }
+
+    /// Encryption of m with randomness drawn from rng
+    pub fn encrypt_with_rng<R: RngCore + CryptoRng>(ek: &PaillierEncryptionKey, m: &BigInt, rng: &mut R) -> BigInt {
+        Paillier::encrypt_with_r(ek, m, &random_unit_with_rng(&ek.n, rng))
+    }
+
+    /// Encryption of m with the caller-supplied randomness r in Z_n^*
+    pub fn encrypt_with_r(ek: &PaillierEncryptionKey, m: &BigInt, r: &BigInt) -> BigInt {
+        Paillier::encrypt_with_rn(ek, m, &r.modpow(&ek.n, &ek.n2))
+    }
+
+    /// Encryption of m with randomness from pool, computed inline if the
+    /// pool is empty
+    pub fn encrypt_pooled(ek: &PaillierEncryptionKey, m: &BigInt, pool: &RandomnessPool) -> BigInt {
+        match pool.take(ek) {
+            Some(rn) => Paillier::encrypt_with_rn(ek, m, &rn),
+            None => Paillier::encrypt(ek, m),
         }
-        let rn = r.modpow(&ek.n, &ek.n2);
-        let gm = m * &ek.n + One::one(); // faster version
+    }
+
+    fn encrypt_with_rn(ek: &PaillierEncryptionKey, m: &BigInt, rn: &BigInt) -> BigInt {
+        let gm = Paillier::encode_signed(ek, m) * &ek.n + BigInt::one(); // faster version
         // let gm = self.pk.g.powm(m, &self.pk.n2);
-        (&gm * &rn) % &ek.n2
+        (&gm * rn) % &ek.n2
     }
 
+    /// Decrypts c to a signed plaintext, using the CRT whenever the key
+    /// still carries p and q
     pub fn decrypt(dk: &PaillierDecryptionKey, ek: &PaillierEncryptionKey, c: &BigInt) -> BigInt {
+        let m = match dk.crt {
+            Some(ref crt) => Paillier::decrypt_crt(crt, c),
+            None => Paillier::decrypt_lambda(dk, ek, c),
+        };
+        Paillier::decode_signed(ek, &m)
+    }
+
+    /// Maps a signed plaintext into Z_n, negative values land in the upper half
+    pub fn encode_signed(ek: &PaillierEncryptionKey, m: &BigInt) -> BigInt {
+        m.mod_floor(&ek.n)
+    }
+
+    /// Maps a residue in Z_n back to a signed plaintext in (-n/2, n/2]
+    pub fn decode_signed(ek: &PaillierEncryptionKey, m: &BigInt) -> BigInt {
+        let m = m.mod_floor(&ek.n);
+        if m > (&ek.n >> 1) {
+            m - &ek.n
+        } else {
+            m
+        }
+    }
+
+    /// Textbook decryption with a single exponentiation modulo n^2
+    pub fn decrypt_lambda(dk: &PaillierDecryptionKey, ek: &PaillierEncryptionKey, c: &BigInt) -> BigInt {
         let cl: BigInt = c.modpow(&dk.lambda, &ek.n2);
-        let lc: BigInt = (cl - One::one()) as &BigInt / &ek.n;
-        (&lc * &dk.mu) % &ek.n
+        (l_function(&cl, &ek.n) * &dk.mu) % &ek.n
+    }
+
+    /// Decryption modulo p^2 and q^2, recombined with Garner's formula
+    pub fn decrypt_crt(crt: &PaillierCrtKey, c: &BigInt) -> BigInt {
+        let cp = (c % &crt.p2).modpow(&(&crt.p - BigInt::one()), &crt.p2);
+        let mp = (l_function(&cp, &crt.p) * &crt.hp) % &crt.p;
+        let cq = (c % &crt.q2).modpow(&(&crt.q - BigInt::one()), &crt.q2);
+        let mq = (l_function(&cq, &crt.q) * &crt.hq) % &crt.q;
+        let h = ((mp - &mq) * &crt.qinv).mod_floor(&crt.p);
+        mq + h * &crt.q
     }
 
     pub fn rerandomize(ek: &PaillierEncryptionKey, m: &BigInt) -> BigInt {
-        let r = millerrabin::generate_urandom_inrange(Zero::zero(), ek.n.to_biguint().unwrap())
-            .to_bigint()
-            .unwrap();
-        let rn = r.modpow(&ek.n, &ek.n2);
-        (m as &BigInt * rn) % &ek.n2
+        Paillier::rerandomize_with_rng(ek, m, &mut OsRng)
+    }
+
+    /// Rerandomizes c with randomness drawn from rng
+    pub fn rerandomize_with_rng<R: RngCore + CryptoRng>(ek: &PaillierEncryptionKey, c: &BigInt, rng: &mut R) -> BigInt {
+        Paillier::rerandomize_with_r(ek, c, &random_unit_with_rng(&ek.n, rng))
+    }
+
+    /// Rerandomizes c with the caller-supplied randomness r in Z_n^*
+    pub fn rerandomize_with_r(ek: &PaillierEncryptionKey, c: &BigInt, r: &BigInt) -> BigInt {
+        (c * r.modpow(&ek.n, &ek.n2)) % &ek.n2
+    }
+
+    /// Rerandomizes c with randomness from pool, computed inline if the
+    /// pool is empty
+    pub fn rerandomize_pooled(ek: &PaillierEncryptionKey, c: &BigInt, pool: &RandomnessPool) -> BigInt {
+        match pool.take(ek) {
+            Some(rn) => (c * rn) % &ek.n2,
+            None => Paillier::rerandomize(ek, c),
+        }
     }
 
     pub fn add(ek: &PaillierEncryptionKey, c1: &BigInt, c2: &BigInt) -> BigInt {
         (c1 * c2) % &ek.n2
     }
 
-    pub fn sub(ek: &PaillierEncryptionKey, c1: &BigInt, c2: &BigInt) -> Option<BigInt> {
-        match Paillier::mult_inv(ek, c2) {
-            Some(inv) => Some((c1 * inv) % &ek.n2),
-            None => None,
-        }
+    /// Encryption of m1 - m2, `NotInvertible` if c2 is not invertible
+    /// modulo n^2
+    pub fn sub(ek: &PaillierEncryptionKey, c1: &BigInt, c2: &BigInt) -> Result<BigInt, HopeError> {
+        let inv = Paillier::mult_inv(ek, c2)?;
+        Ok((c1 * inv) % &ek.n2)
     }
 
-    pub fn mult_inv(ek: &PaillierEncryptionKey, c1: &BigInt) -> Option<BigInt> {
-        let a: BigInt = c1 % &ek.n2;
-        let mut x: BigInt = One::one();
-        while x < ek.n2 {
-            if (a * x) % &ek.n2 == One::one() {
-                Some(x);
-            }
-            x = x + One::one();
-        }
-        None
+    /// Encryption of -m, `NotInvertible` if c is not invertible modulo n^2
+    pub fn neg(ek: &PaillierEncryptionKey, c: &BigInt) -> Result<BigInt, HopeError> {
+        Paillier::mult_inv(ek, c)
+    }
+
+    pub fn mult_inv(ek: &PaillierEncryptionKey, c1: &BigInt) -> Result<BigInt, HopeError> {
+        invert(c1, &ek.n2)
     }
 
+    /// Encryption of m1 + m, m may be negative
     pub fn add_const(ek: &PaillierEncryptionKey, c: &BigInt, m: &BigInt) -> BigInt {
-        Paillier::add(ek, c, &ek.g.modpow(&m, &ek.n2))
+        let gm = Paillier::encode_signed(ek, m) * &ek.n + BigInt::one();
+        Paillier::add(ek, c, &gm)
     }
 
+    /// Encryption of m1 * m, m may be negative
     pub fn mul_const(ek: &PaillierEncryptionKey, c: &BigInt, m: &BigInt) -> BigInt {
-        c.modpow(&m, &ek.n2)
+        c.modpow(&Paillier::encode_signed(ek, m), &ek.n2)
+    }
+}
+
+/// L(x) = (x - 1) / n
+fn l_function(x: &BigInt, n: &BigInt) -> BigInt {
+    (x - BigInt::one()) / n
+}
+
+/// Uniform element of [0, n)
+pub fn random_below(n: &BigInt) -> BigInt {
+    random_below_with_rng(n, &mut OsRng)
+}
+
+/// Uniform element of [0, n) drawn from rng
+pub fn random_below_with_rng<R: RngCore + CryptoRng>(n: &BigInt, rng: &mut R) -> BigInt {
+    millerrabin::generate_urandom_below(&n.to_biguint().unwrap(), rng)
+        .to_bigint()
+        .unwrap()
+}
+
+/// Uniform element of Z_n^*
+pub fn random_unit(n: &BigInt) -> BigInt {
+    random_unit_with_rng(n, &mut OsRng)
+}
+
+/// Uniform element of Z_n^* drawn from rng
+pub fn random_unit_with_rng<R: RngCore + CryptoRng>(n: &BigInt, rng: &mut R) -> BigInt {
+    loop {
+        let r = random_below_with_rng(n, rng);
+        if r.gcd(n).is_one() {
+            return r;
+        }
     }
 }
 
+/// Inverse of a modulo n, `NotInvertible` unless gcd(a, n) = 1
+pub fn invert(a: &BigInt, n: &BigInt) -> Result<BigInt, HopeError> {
+    let e = a.mod_floor(n).extended_gcd(n);
+    if e.gcd.is_one() {
+        Ok(e.x.mod_floor(n))
+    } else {
+        Err(HopeError::NotInvertible)
+    }
+}
+
+/// Fiat-Shamir challenge, SHA-256 over the length-prefixed encodings of parts
+pub fn challenge(parts: &[&BigInt]) -> BigInt {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        let bytes = part.to_signed_bytes_be();
+        hasher.input((bytes.len() as u64).to_be_bytes());
+        hasher.input(&bytes);
+    }
+    BigInt::from_bytes_be(Sign::Plus, &hasher.result())
+}
+
+/// Keys of the Mersenne primes 2^61 - 1 and 2^89 - 1, shared by the tests
+#[cfg(test)]
+pub(crate) fn test_keys() -> (PaillierEncryptionKey, PaillierDecryptionKey) {
+    let p = BigInt::parse_bytes(b"2305843009213693951", 10).unwrap();
+    let q = BigInt::parse_bytes(b"618970019642690137449562111", 10).unwrap();
+    Paillier::keygen_from_primes(&p, &q)
+}
+
+/// Keys of the safe primes 2^64 - 1469 and 2^90 - 41, shared by the tests
+/// that need safe primes
+#[cfg(test)]
+pub(crate) fn test_safe_keys() -> (PaillierEncryptionKey, PaillierDecryptionKey) {
+    let p = BigInt::parse_bytes(b"18446744073709550147", 10).unwrap();
+    let q = BigInt::parse_bytes(b"1237940039285380274899124183", 10).unwrap();
+    Paillier::keygen_from_primes(&p, &q)
+}
 
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use hope::config::PrimeType;
 
     #[test]
     fn paillier_add() {
-        let p = Paillier::new(256);
-        let n1 = p.encrypt(100);
-        let n2 = p.encrypt(101);
-        assert_eq!(p.decrypt(p.encrypt(201)), p.decrypt(p.add_cipher(n1, n2)));
+        let config = KeygenConfig {
+            min_key_size: 256,
+            ..KeygenConfig::default()
+        };
+        let (pk, sk) = Paillier::keygen_with(256, &config).unwrap();
+        let n1 = Paillier::encrypt(&pk, &BigInt::from(100));
+        let n2 = Paillier::encrypt(&pk, &BigInt::from(101));
+        assert_eq!(
+            Paillier::decrypt(&sk, &pk, &Paillier::encrypt(&pk, &BigInt::from(201))),
+            Paillier::decrypt(&sk, &pk, &Paillier::add(&pk, &n1, &n2))
+        );
+    }
+
+    #[test]
+    fn paillier_keygen_minimum() {
+        assert_eq!(Paillier::keygen(1024).err(), Some(HopeError::KeyGeneration));
+        assert!(Paillier::new(1024).is_err());
+        let config = KeygenConfig {
+            min_key_size: 128,
+            prime_type: PrimeType::Safe,
+            ..KeygenConfig::default()
+        };
+        let (ek, dk) = Paillier::keygen_with(128, &config).unwrap();
+        let crt = dk.crt.unwrap();
+        assert_eq!(ek.n.bits(), 128);
+        assert!(crt.p != crt.q);
+    }
+
+    #[test]
+    fn paillier_decrypt_crt() {
+        let (ek, dk) = test_keys();
+        let m = BigInt::from(1234567890u64);
+        let r = BigInt::from(987654321u64);
+        let c = ((&m * &ek.n + BigInt::one()) * r.modpow(&ek.n, &ek.n2)) % &ek.n2;
+        assert_eq!(Paillier::decrypt_crt(dk.crt.as_ref().unwrap(), &c), m);
+        assert_eq!(Paillier::decrypt_lambda(&dk, &ek, &c), m);
+        assert_eq!(Paillier::decrypt(&dk, &ek, &c), m);
+    }
+
+    #[test]
+    fn paillier_decrypt_legacy_key() {
+        let (ek, dk) = test_keys();
+        let m = BigInt::from(42u32);
+        let c = ((&m * &ek.n + BigInt::one()) * BigInt::from(7u32).modpow(&ek.n, &ek.n2)) % &ek.n2;
+        // keys written before the CRT constants were introduced
+        let mut json = serde_json::to_value(&dk).unwrap();
+        json.as_object_mut().unwrap().remove("crt");
+        let legacy: PaillierDecryptionKey = serde_json::from_value(json).unwrap();
+        assert!(legacy.crt.is_none());
+        assert_eq!(Paillier::decrypt(&legacy, &ek, &c), m);
+    }
+
+    fn encrypt_r(ek: &PaillierEncryptionKey, m: i64, r: u64) -> BigInt {
+        let gm = Paillier::encode_signed(ek, &BigInt::from(m)) * &ek.n + BigInt::one();
+        (gm * BigInt::from(r).modpow(&ek.n, &ek.n2)) % &ek.n2
+    }
+
+    #[test]
+    fn paillier_signed() {
+        let (ek, dk) = test_keys();
+        for m in [-1i64, -4711, 0, 1, 4711].iter() {
+            let c = encrypt_r(&ek, *m, 31337);
+            assert_eq!(Paillier::decrypt(&dk, &ek, &c), BigInt::from(*m));
+        }
+        let half = &ek.n >> 1;
+        assert_eq!(Paillier::decode_signed(&ek, &half), half);
+        assert_eq!(Paillier::decode_signed(&ek, &(&half + BigInt::one())), -half);
+    }
+
+    #[test]
+    fn paillier_sub_neg() {
+        let (ek, dk) = test_keys();
+        let c1 = encrypt_r(&ek, 100, 17);
+        let c2 = encrypt_r(&ek, 250, 19);
+        let diff = Paillier::sub(&ek, &c1, &c2).unwrap();
+        assert_eq!(Paillier::decrypt(&dk, &ek, &diff), BigInt::from(-150));
+        let neg = Paillier::neg(&ek, &diff).unwrap();
+        assert_eq!(Paillier::decrypt(&dk, &ek, &neg), BigInt::from(150));
+        let c = Paillier::add_const(&ek, &c1, &BigInt::from(-300));
+        assert_eq!(Paillier::decrypt(&dk, &ek, &c), BigInt::from(-200));
+        let c = Paillier::mul_const(&ek, &c1, &BigInt::from(-3));
+        assert_eq!(Paillier::decrypt(&dk, &ek, &c), BigInt::from(-300));
+    }
+
+    #[test]
+    fn paillier_seeded_rng() {
+        use rand_chacha::ChaChaRng;
+        use rand_core::SeedableRng;
+        let (ek, dk) = test_keys();
+        let m = BigInt::from(-4711);
+        let c1 = Paillier::encrypt_with_rng(&ek, &m, &mut ChaChaRng::seed_from_u64(1));
+        let c2 = Paillier::encrypt_with_rng(&ek, &m, &mut ChaChaRng::seed_from_u64(1));
+        let c3 = Paillier::encrypt_with_rng(&ek, &m, &mut ChaChaRng::seed_from_u64(2));
+        assert_eq!(c1, c2);
+        assert!(c1 != c3);
+        let r = random_unit_with_rng(&ek.n, &mut ChaChaRng::seed_from_u64(1));
+        assert_eq!(c1, Paillier::encrypt_with_r(&ek, &m, &r));
+        let c4 = Paillier::rerandomize_with_rng(&ek, &c1, &mut ChaChaRng::seed_from_u64(3));
+        let r = random_unit_with_rng(&ek.n, &mut ChaChaRng::seed_from_u64(3));
+        assert_eq!(c4, Paillier::rerandomize_with_r(&ek, &c1, &r));
+        assert_eq!(Paillier::decrypt(&dk, &ek, &c4), m);
     }
 }
@@ -0,0 +1,225 @@
+//! Packing of several small signed values into one Paillier plaintext.
+//!
+//! Slot i holds v_i * 2^(i * (width + headroom)), so homomorphic additions
+//! and multiplications by a constant act on all slots at once. Every
+//! packed ciphertext carries a public bound on the absolute value of its
+//! slots. Operations that could carry into the neighbouring slot are
+//! refused, the headroom bits decide how many of them fit.
+use num_bigint::BigInt;
+use num_traits::{One, Signed, Zero};
+use serde_derive::{Deserialize, Serialize};
+use hope::protocol::model::*;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use crate::error::HopeError;
+use super::Paillier;
+
+/// Layout of the slots within a plaintext
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PaillierPacker {
+    /// number of slots per plaintext
+    pub slots: usize,
+    /// bits of a fresh slot value, including the sign
+    pub width: usize,
+    /// extra bits per slot that absorb the growth of sums and products
+    pub headroom: usize,
+}
+
+/// A ciphertext of packed slots
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PackedCiphertext {
+    /// paillier ciphertext
+    pub c: BigInt,
+    /// upper bound on the absolute value of every slot
+    pub bound: BigInt,
+}
+
+impl PaillierPacker {
+    /// Creates a layout, `KeyTooSmall` if the slots do not fit into a
+    /// plaintext of ek
+    pub fn new(ek: &PaillierEncryptionKey, slots: usize, width: usize, headroom: usize) -> Result<PaillierPacker, HopeError> {
+        let packer = PaillierPacker {
+            slots,
+            width,
+            headroom,
+        };
+        if slots == 0 || width == 0 {
+            return Err(HopeError::InvalidArgument(String::from("no slots or slots of width 0")));
+        }
+        // a signed plaintext must stay below n/2
+        if slots * packer.slot_bits() > ek.n.bits() - 1 {
+            return Err(HopeError::KeyTooSmall {
+                bits: ek.n.bits(),
+                required: slots * packer.slot_bits() + 1,
+            });
+        }
+        Ok(packer)
+    }
+
+    /// Creates the layout with as many slots as the key allows
+    pub fn with_max_slots(ek: &PaillierEncryptionKey, width: usize, headroom: usize) -> Result<PaillierPacker, HopeError> {
+        let slots = (ek.n.bits() - 1) / (width + headroom);
+        PaillierPacker::new(ek, slots, width, headroom)
+    }
+
+    fn slot_bits(&self) -> usize {
+        self.width + self.headroom
+    }
+
+    /// largest absolute slot value that cannot disturb its neighbours
+    fn limit(&self) -> BigInt {
+        (BigInt::one() << (self.slot_bits() - 1)) - BigInt::one()
+    }
+
+    /// largest absolute value of a fresh slot
+    fn fresh_bound(&self) -> BigInt {
+        BigInt::one() << (self.width - 1)
+    }
+
+    /// Packs up to `slots` values of `width` bits, missing slots are 0
+    pub fn pack(&self, values: &[BigInt]) -> Result<BigInt, HopeError> {
+        let bound = self.fresh_bound();
+        if values.len() > self.slots {
+            return Err(HopeError::InvalidArgument(format!("{} values for {} slots", values.len(), self.slots)));
+        }
+        if let Some(v) = values.iter().find(|v| **v < -&bound || **v >= bound) {
+            return Err(HopeError::PlaintextOutOfRange(format!("{} exceeds {} bits", v, self.width)));
+        }
+        Ok(
+            values
+                .iter()
+                .rev()
+                .fold(BigInt::zero(), |acc, v| (acc << self.slot_bits()) + v),
+        )
+    }
+
+    /// Splits a signed plaintext into its slot values
+    pub fn unpack(&self, m: &BigInt) -> Vec<BigInt> {
+        let base = BigInt::one() << self.slot_bits();
+        let half = BigInt::one() << (self.slot_bits() - 1);
+        let mut rest = m.clone();
+        let mut values = Vec::with_capacity(self.slots);
+        for _ in 0..self.slots {
+            let mut v = &rest & (&base - BigInt::one());
+            if v >= half {
+                v -= &base;
+            }
+            rest = (rest - &v) >> self.slot_bits();
+            values.push(v);
+        }
+        values
+    }
+
+    pub fn encrypt(&self, ek: &PaillierEncryptionKey, values: &[BigInt]) -> Result<PackedCiphertext, HopeError> {
+        self.encrypt_with_rng(ek, values, &mut OsRng)
+    }
+
+    /// `encrypt` with randomness drawn from rng
+    pub fn encrypt_with_rng<R: RngCore + CryptoRng>(
+        &self,
+        ek: &PaillierEncryptionKey,
+        values: &[BigInt],
+        rng: &mut R,
+    ) -> Result<PackedCiphertext, HopeError> {
+        let m = self.pack(values)?;
+        Ok(PackedCiphertext {
+            c: Paillier::encrypt_with_rng(ek, &m, rng),
+            bound: self.fresh_bound(),
+        })
+    }
+
+    pub fn decrypt(&self, dk: &PaillierDecryptionKey, ek: &PaillierEncryptionKey, ct: &PackedCiphertext) -> Vec<BigInt> {
+        self.unpack(&Paillier::decrypt(dk, ek, &ct.c))
+    }
+
+    pub fn rerandomize(&self, ek: &PaillierEncryptionKey, ct: &PackedCiphertext) -> PackedCiphertext {
+        self.rerandomize_with_rng(ek, ct, &mut OsRng)
+    }
+
+    /// `rerandomize` with randomness drawn from rng
+    pub fn rerandomize_with_rng<R: RngCore + CryptoRng>(
+        &self,
+        ek: &PaillierEncryptionKey,
+        ct: &PackedCiphertext,
+        rng: &mut R,
+    ) -> PackedCiphertext {
+        PackedCiphertext {
+            c: Paillier::rerandomize_with_rng(ek, &ct.c, rng),
+            bound: ct.bound.clone(),
+        }
+    }
+
+    /// Slot-wise sum, `PlaintextOutOfRange` if a slot could overflow
+    pub fn add(&self, ek: &PaillierEncryptionKey, a: &PackedCiphertext, b: &PackedCiphertext) -> Result<PackedCiphertext, HopeError> {
+        self.checked(Paillier::add(ek, &a.c, &b.c), &a.bound + &b.bound)
+    }
+
+    /// Adds the plaintext values slot-wise, `PlaintextOutOfRange` if a slot
+    /// could overflow
+    pub fn add_const(&self, ek: &PaillierEncryptionKey, a: &PackedCiphertext, values: &[BigInt]) -> Result<PackedCiphertext, HopeError> {
+        let m = self.pack(values)?;
+        self.checked(Paillier::add_const(ek, &a.c, &m), &a.bound + self.fresh_bound())
+    }
+
+    /// Multiplies every slot by k, `PlaintextOutOfRange` if a slot could
+    /// overflow
+    pub fn mul_const(&self, ek: &PaillierEncryptionKey, a: &PackedCiphertext, k: &BigInt) -> Result<PackedCiphertext, HopeError> {
+        self.checked(Paillier::mul_const(ek, &a.c, k), &a.bound * k.abs())
+    }
+
+    fn checked(&self, c: BigInt, bound: BigInt) -> Result<PackedCiphertext, HopeError> {
+        if bound > self.limit() {
+            return Err(HopeError::PlaintextOutOfRange(format!("slot bound {} exceeds {}", bound, self.limit())));
+        }
+        Ok(PackedCiphertext { c, bound })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::paillier::test_keys;
+
+    fn values(v: &[i64]) -> Vec<BigInt> {
+        v.iter().map(|x| BigInt::from(*x)).collect()
+    }
+
+    #[test]
+    fn packing_layout() {
+        let (ek, _) = test_keys();
+        // n has 150 bits, one of them is reserved for the sign
+        let packer = PaillierPacker::with_max_slots(&ek, 16, 8).unwrap();
+        assert_eq!(packer.slots, 6);
+        assert_eq!(
+            PaillierPacker::new(&ek, 7, 16, 8).err(),
+            Some(HopeError::KeyTooSmall { bits: 150, required: 169 })
+        );
+        let v = values(&[-32768, 32767, 0, -1, 1, 12345]);
+        assert_eq!(packer.unpack(&packer.pack(&v).unwrap()), v);
+        assert!(packer.pack(&values(&[32768])).is_err());
+        assert!(packer.pack(&values(&[1, 2, 3, 4, 5, 6, 7])).is_err());
+        assert_eq!(packer.unpack(&packer.pack(&values(&[5])).unwrap()), values(&[5, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn packing_homomorphic() {
+        let (ek, dk) = test_keys();
+        let packer = PaillierPacker::new(&ek, 4, 16, 8).unwrap();
+        let a = packer.encrypt(&ek, &values(&[100, -200, 300, -32768])).unwrap();
+        let b = packer.encrypt(&ek, &values(&[-1, -2, 3, -32768])).unwrap();
+        let sum = packer.add(&ek, &a, &b).unwrap();
+        assert_eq!(packer.decrypt(&dk, &ek, &sum), values(&[99, -202, 303, -65536]));
+        let sum = packer.add_const(&ek, &sum, &values(&[1, 2, -3])).unwrap();
+        assert_eq!(packer.decrypt(&dk, &ek, &sum), values(&[100, -200, 300, -65536]));
+        let prod = packer.mul_const(&ek, &a, &BigInt::from(-3)).unwrap();
+        assert_eq!(packer.decrypt(&dk, &ek, &prod), values(&[-300, 600, -900, 98304]));
+        // 8 bits of headroom allow 255 fresh values per slot, not 256
+        let mut acc = a.clone();
+        for _ in 1..255 {
+            acc = packer.add(&ek, &acc, &a).unwrap();
+        }
+        assert!(packer.add(&ek, &acc, &a).is_err());
+        assert_eq!(packer.decrypt(&dk, &ek, &acc)[0], BigInt::from(25500));
+        assert!(packer.mul_const(&ek, &a, &BigInt::from(256)).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Pool of precomputed Paillier randomness.
+//!
+//! Encryption and rerandomisation spend most of their time on r^n mod n^2,
+//! which does not depend on the plaintext. Background threads keep a queue
+//! of such values filled for one key, `Paillier::encrypt_pooled` and
+//! `Paillier::rerandomize_pooled` take from it and fall back to inline
+//! computation when it runs dry.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use num_bigint::BigInt;
+use hope::config::PoolConfig;
+use hope::protocol::model::*;
+use super::random_unit;
+
+/// how long an idle worker sleeps before it checks for shutdown again
+const IDLE_WAIT: Duration = Duration::from_millis(100);
+
+struct PoolState {
+    values: Mutex<VecDeque<BigInt>>,
+    wakeup: Condvar,
+    stop: AtomicBool,
+}
+
+/// Precomputed values r^n mod n^2 for one encryption key
+pub struct RandomnessPool {
+    n: BigInt,
+    size: usize,
+    state: Arc<PoolState>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl RandomnessPool {
+    /// Starts config.threads workers that keep config.size values ready
+    pub fn new(ek: &PaillierEncryptionKey, config: &PoolConfig) -> RandomnessPool {
+        let state = Arc::new(PoolState {
+            values: Mutex::new(VecDeque::with_capacity(config.size)),
+            wakeup: Condvar::new(),
+            stop: AtomicBool::new(false),
+        });
+        let workers = (0..config.threads)
+            .map(|_| {
+                let state = state.clone();
+                let ek = ek.clone();
+                let size = config.size;
+                thread::spawn(move || RandomnessPool::refill(&state, &ek, size))
+            })
+            .collect();
+        RandomnessPool {
+            n: ek.n.clone(),
+            size: config.size,
+            state,
+            workers,
+        }
+    }
+
+    fn refill(state: &PoolState, ek: &PaillierEncryptionKey, size: usize) {
+        while !state.stop.load(Ordering::SeqCst) {
+            {
+                let values = state.values.lock().unwrap();
+                if values.len() >= size {
+                    let _ = state.wakeup.wait_timeout(values, IDLE_WAIT).unwrap();
+                    continue;
+                }
+            }
+            // compute outside of the lock so that consumers are not blocked
+            let rn = random_unit(&ek.n).modpow(&ek.n, &ek.n2);
+            let mut values = state.values.lock().unwrap();
+            if values.len() < size {
+                values.push_back(rn);
+            }
+        }
+    }
+
+    /// Takes a precomputed r^n, None if the pool is empty or belongs to
+    /// another key
+    pub fn take(&self, ek: &PaillierEncryptionKey) -> Option<BigInt> {
+        if ek.n != self.n {
+            return None;
+        }
+        let rn = self.state.values.lock().unwrap().pop_front();
+        self.state.wakeup.notify_one();
+        rn
+    }
+
+    /// Number of values ready to be taken
+    pub fn len(&self) -> usize {
+        self.state.values.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of values the workers aim to keep ready
+    pub fn capacity(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for RandomnessPool {
+    fn drop(&mut self) {
+        self.state.stop.store(true, Ordering::SeqCst);
+        self.state.wakeup.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::paillier::test_keys;
+    use crate::paillier::Paillier;
+    use std::time::Instant;
+
+    #[test]
+    fn pool_refills() {
+        let (ek, dk) = test_keys();
+        let pool = RandomnessPool::new(&ek, &PoolConfig { size: 16, threads: 2 });
+        let start = Instant::now();
+        while pool.len() < pool.capacity() && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.len(), 16);
+        let m = BigInt::from(-4711);
+        let c = Paillier::encrypt_pooled(&ek, &m, &pool);
+        assert_eq!(Paillier::decrypt(&dk, &ek, &c), m);
+        let c2 = Paillier::rerandomize_pooled(&ek, &c, &pool);
+        assert!(c2 != c);
+        assert_eq!(Paillier::decrypt(&dk, &ek, &c2), m);
+    }
+
+    #[test]
+    fn pool_fallback() {
+        let (ek, dk) = test_keys();
+        // no workers, the pool stays empty
+        let pool = RandomnessPool::new(&ek, &PoolConfig { size: 16, threads: 0 });
+        assert!(pool.is_empty());
+        let m = BigInt::from(42);
+        let c = Paillier::encrypt_pooled(&ek, &m, &pool);
+        assert_eq!(Paillier::decrypt(&dk, &ek, &c), m);
+        let c = Paillier::rerandomize_pooled(&ek, &c, &pool);
+        assert_eq!(Paillier::decrypt(&dk, &ek, &c), m);
+    }
+}
@@ -0,0 +1,418 @@
+//! Non-interactive zero-knowledge proofs for Paillier ciphertexts.
+//!
+//! All proofs are sigma protocols made non-interactive with the
+//! Fiat-Shamir heuristic. The challenge hashes the public key, the
+//! statement and the first messages of the prover, so a proof cannot be
+//! replayed for another ciphertext or key. The prover needs the plaintext
+//! m and the randomness r with c = g^m * r^n mod n^2, see
+//! `Paillier::encrypt_with_r`.
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Signed, Zero};
+use serde_derive::{Deserialize, Serialize};
+use hope::protocol::model::*;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use super::{challenge, invert, random_below_with_rng, random_unit_with_rng, Paillier};
+
+/// bit length of the Fiat-Shamir challenges
+const CHALLENGE_BITS: usize = 256;
+/// domain separation tags of the different proofs
+const TAG_PLAINTEXT: u32 = 1;
+const TAG_MEMBERSHIP: u32 = 2;
+const TAG_DECRYPTION: u32 = 3;
+
+/// Proof of knowledge of the plaintext and randomness of a ciphertext
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlaintextProof {
+    pub a: BigInt,
+    pub z: BigInt,
+    pub w: BigInt,
+}
+
+/// OR-proof that a ciphertext encrypts one value of a public set
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MembershipProof {
+    pub a: Vec<BigInt>,
+    pub e: Vec<BigInt>,
+    pub z: Vec<BigInt>,
+}
+
+/// Encryptions of the bits of a value, each with a proof that it is 0 or 1
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BitDecompositionProof {
+    pub bits: Vec<BigInt>,
+    pub proofs: Vec<MembershipProof>,
+}
+
+/// Proof that the plaintext lies in [a, b]: m - a and b - m are both
+/// sums of k bits with 2^k > b - a
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RangeProof {
+    pub lower: BitDecompositionProof,
+    pub upper: BitDecompositionProof,
+}
+
+/// Proof that c * g^-m is an n-th residue, i.e. that c decrypts to m
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DecryptionProof {
+    pub a: BigInt,
+    pub z: BigInt,
+}
+
+pub struct PaillierProofs;
+
+impl PaillierProofs {
+    /// Checks that c is a unit of Z_(n^2)
+    pub fn is_well_formed(ek: &PaillierEncryptionKey, c: &BigInt) -> bool {
+        c.is_positive() && *c < ek.n2 && c.gcd(&ek.n).is_one()
+    }
+
+    /// Proves knowledge of m and r with c = g^m * r^n
+    pub fn prove_plaintext(ek: &PaillierEncryptionKey, c: &BigInt, m: &BigInt, r: &BigInt) -> PlaintextProof {
+        PaillierProofs::prove_plaintext_with_rng(ek, c, m, r, &mut OsRng)
+    }
+
+    /// `prove_plaintext` with randomness drawn from rng
+    pub fn prove_plaintext_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        c: &BigInt,
+        m: &BigInt,
+        r: &BigInt,
+        rng: &mut R,
+    ) -> PlaintextProof {
+        let x = random_below_with_rng(&ek.n, rng);
+        let s = random_unit_with_rng(&ek.n, rng);
+        let a = Paillier::encrypt_with_r(ek, &x, &s);
+        let e = challenge(&[&BigInt::from(TAG_PLAINTEXT), &ek.n, c, &a]);
+        // g^n = 1 mod n^2, so the carry of x + e*m can be dropped
+        let z = (x + &e * Paillier::encode_signed(ek, m)).mod_floor(&ek.n);
+        let w = (s * r.modpow(&e, &ek.n)) % &ek.n;
+        PlaintextProof { a, z, w }
+    }
+
+    pub fn verify_plaintext(ek: &PaillierEncryptionKey, c: &BigInt, proof: &PlaintextProof) -> bool {
+        if !PaillierProofs::is_well_formed(ek, c) || !PaillierProofs::is_well_formed(ek, &proof.a) {
+            return false;
+        }
+        let e = challenge(&[&BigInt::from(TAG_PLAINTEXT), &ek.n, c, &proof.a]);
+        let lhs = Paillier::encrypt_with_r(ek, &proof.z, &proof.w);
+        lhs == (&proof.a * c.modpow(&e, &ek.n2)) % &ek.n2
+    }
+
+    /// Proves that c encrypts one element of set without revealing which,
+    /// None if m is not in set
+    pub fn prove_membership(
+        ek: &PaillierEncryptionKey,
+        c: &BigInt,
+        m: &BigInt,
+        r: &BigInt,
+        set: &[BigInt],
+    ) -> Option<MembershipProof> {
+        PaillierProofs::prove_membership_with_rng(ek, c, m, r, set, &mut OsRng)
+    }
+
+    /// `prove_membership` with randomness drawn from rng
+    pub fn prove_membership_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        c: &BigInt,
+        m: &BigInt,
+        r: &BigInt,
+        set: &[BigInt],
+        rng: &mut R,
+    ) -> Option<MembershipProof> {
+        let j = set.iter().position(|s| s == m)?;
+        let bound = BigInt::one() << CHALLENGE_BITS;
+        let mut a = Vec::with_capacity(set.len());
+        let mut e = Vec::with_capacity(set.len());
+        let mut z = Vec::with_capacity(set.len());
+        let rho = random_unit_with_rng(&ek.n, rng);
+        for (i, s) in set.iter().enumerate() {
+            if i == j {
+                a.push(rho.modpow(&ek.n, &ek.n2));
+                e.push(BigInt::zero());
+                z.push(BigInt::zero());
+            } else {
+                // simulated transcript for the wrong values
+                let ui = PaillierProofs::shift(ek, c, s);
+                let ei = random_below_with_rng(&bound, rng);
+                let zi = random_unit_with_rng(&ek.n, rng);
+                let ai = (zi.modpow(&ek.n, &ek.n2) * invert(&ui.modpow(&ei, &ek.n2), &ek.n2).ok()?) % &ek.n2;
+                a.push(ai);
+                e.push(ei);
+                z.push(zi);
+            }
+        }
+        let total = PaillierProofs::membership_challenge(ek, c, set, &a);
+        let others = e.iter().fold(BigInt::zero(), |acc, ei| acc + ei);
+        e[j] = (total - others).mod_floor(&bound);
+        z[j] = (rho * r.modpow(&e[j], &ek.n)) % &ek.n;
+        Some(MembershipProof { a, e, z })
+    }
+
+    pub fn verify_membership(
+        ek: &PaillierEncryptionKey,
+        c: &BigInt,
+        set: &[BigInt],
+        proof: &MembershipProof,
+    ) -> bool {
+        if !PaillierProofs::is_well_formed(ek, c)
+            || proof.a.len() != set.len()
+            || proof.e.len() != set.len()
+            || proof.z.len() != set.len()
+        {
+            return false;
+        }
+        let bound = BigInt::one() << CHALLENGE_BITS;
+        if proof.e.iter().any(|ei| ei.is_negative() || *ei >= bound) {
+            return false;
+        }
+        let total = PaillierProofs::membership_challenge(ek, c, set, &proof.a);
+        let sum = proof.e.iter().fold(BigInt::zero(), |acc, ei| acc + ei);
+        if sum.mod_floor(&bound) != total.mod_floor(&bound) {
+            return false;
+        }
+        set.iter().enumerate().all(|(i, s)| {
+            let ui = PaillierProofs::shift(ek, c, s);
+            PaillierProofs::is_well_formed(ek, &proof.a[i])
+                && proof.z[i].modpow(&ek.n, &ek.n2) == (&proof.a[i] * ui.modpow(&proof.e[i], &ek.n2)) % &ek.n2
+        })
+    }
+
+    /// Proves that c encrypts 0 or 1
+    pub fn prove_bit(ek: &PaillierEncryptionKey, c: &BigInt, m: &BigInt, r: &BigInt) -> Option<MembershipProof> {
+        PaillierProofs::prove_bit_with_rng(ek, c, m, r, &mut OsRng)
+    }
+
+    /// `prove_bit` with randomness drawn from rng
+    pub fn prove_bit_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        c: &BigInt,
+        m: &BigInt,
+        r: &BigInt,
+        rng: &mut R,
+    ) -> Option<MembershipProof> {
+        PaillierProofs::prove_membership_with_rng(ek, c, m, r, &[BigInt::zero(), BigInt::one()], rng)
+    }
+
+    pub fn verify_bit(ek: &PaillierEncryptionKey, c: &BigInt, proof: &MembershipProof) -> bool {
+        PaillierProofs::verify_membership(ek, c, &[BigInt::zero(), BigInt::one()], proof)
+    }
+
+    /// Proves that the plaintext m of c lies in [a, b], None if it does not
+    /// or if (m, r) does not open c
+    pub fn prove_range(
+        ek: &PaillierEncryptionKey,
+        c: &BigInt,
+        m: &BigInt,
+        r: &BigInt,
+        a: &BigInt,
+        b: &BigInt,
+    ) -> Option<RangeProof> {
+        PaillierProofs::prove_range_with_rng(ek, c, m, r, a, b, &mut OsRng)
+    }
+
+    /// `prove_range` with randomness drawn from rng
+    pub fn prove_range_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        c: &BigInt,
+        m: &BigInt,
+        r: &BigInt,
+        a: &BigInt,
+        b: &BigInt,
+        rng: &mut R,
+    ) -> Option<RangeProof> {
+        if m < a || m > b || Paillier::encrypt_with_r(ek, m, r) != *c {
+            return None;
+        }
+        let k = PaillierProofs::range_bits(a, b);
+        let lower = PaillierProofs::prove_bits(ek, &(m - a), r, k, rng)?;
+        let upper = PaillierProofs::prove_bits(ek, &(b - m), &invert(r, &ek.n).ok()?, k, rng)?;
+        Some(RangeProof { lower, upper })
+    }
+
+    pub fn verify_range(ek: &PaillierEncryptionKey, c: &BigInt, a: &BigInt, b: &BigInt, proof: &RangeProof) -> bool {
+        if a > b || !PaillierProofs::is_well_formed(ek, c) {
+            return false;
+        }
+        let k = PaillierProofs::range_bits(a, b);
+        // Enc(m - a) and Enc(b - m)
+        let c_lower = PaillierProofs::shift(ek, c, a);
+        let c_upper = match invert(c, &ek.n2) {
+            Ok(inv) => Paillier::add_const(ek, &inv, b),
+            Err(_) => return false,
+        };
+        PaillierProofs::verify_bits(ek, &c_lower, k, &proof.lower)
+            && PaillierProofs::verify_bits(ek, &c_upper, k, &proof.upper)
+    }
+
+    /// Proves that c decrypts to its signed plaintext, which is returned
+    /// together with the proof
+    pub fn prove_decryption(
+        ek: &PaillierEncryptionKey,
+        dk: &PaillierDecryptionKey,
+        c: &BigInt,
+    ) -> Option<(BigInt, DecryptionProof)> {
+        PaillierProofs::prove_decryption_with_rng(ek, dk, c, &mut OsRng)
+    }
+
+    /// `prove_decryption` with randomness drawn from rng
+    pub fn prove_decryption_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        dk: &PaillierDecryptionKey,
+        c: &BigInt,
+        rng: &mut R,
+    ) -> Option<(BigInt, DecryptionProof)> {
+        let m = Paillier::decrypt(dk, ek, c);
+        let u = PaillierProofs::shift(ek, c, &m);
+        // the n-th root of u = r^n, n is invertible modulo lambda
+        let r = (&u % &ek.n).modpow(&invert(&ek.n, &dk.lambda).ok()?, &ek.n);
+        let rho = random_unit_with_rng(&ek.n, rng);
+        let a = rho.modpow(&ek.n, &ek.n2);
+        let e = challenge(&[&BigInt::from(TAG_DECRYPTION), &ek.n, c, &m, &a]);
+        let z = (rho * r.modpow(&e, &ek.n)) % &ek.n;
+        Some((m, DecryptionProof { a, z }))
+    }
+
+    pub fn verify_decryption(ek: &PaillierEncryptionKey, c: &BigInt, m: &BigInt, proof: &DecryptionProof) -> bool {
+        if !PaillierProofs::is_well_formed(ek, c) || !PaillierProofs::is_well_formed(ek, &proof.a) {
+            return false;
+        }
+        let u = PaillierProofs::shift(ek, c, m);
+        let e = challenge(&[&BigInt::from(TAG_DECRYPTION), &ek.n, c, m, &proof.a]);
+        proof.z.modpow(&ek.n, &ek.n2) == (&proof.a * u.modpow(&e, &ek.n2)) % &ek.n2
+    }
+
+    /// c * g^-s, an encryption of m - s with the same randomness
+    fn shift(ek: &PaillierEncryptionKey, c: &BigInt, s: &BigInt) -> BigInt {
+        Paillier::add_const(ek, c, &-s)
+    }
+
+    fn membership_challenge(ek: &PaillierEncryptionKey, c: &BigInt, set: &[BigInt], a: &[BigInt]) -> BigInt {
+        let tag = BigInt::from(TAG_MEMBERSHIP);
+        let mut parts = vec![&tag, &ek.n, c];
+        parts.extend(set.iter());
+        parts.extend(a.iter());
+        challenge(&parts)
+    }
+
+    /// number of bits needed for values in [0, b - a]
+    fn range_bits(a: &BigInt, b: &BigInt) -> usize {
+        std::cmp::max((b - a).bits(), 1)
+    }
+
+    /// Encrypts the k lowest bits of x such that the weighted product of the
+    /// bit ciphertexts equals Enc(x, r)
+    fn prove_bits<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        x: &BigInt,
+        r: &BigInt,
+        k: usize,
+        rng: &mut R,
+    ) -> Option<BitDecompositionProof> {
+        let mut randomness: Vec<BigInt> = (1..k).map(|_| random_unit_with_rng(&ek.n, rng)).collect();
+        let mut rest = BigInt::one();
+        for (i, ri) in randomness.iter().enumerate() {
+            rest = (rest * ri.modpow(&(BigInt::one() << (i + 1)), &ek.n)) % &ek.n;
+        }
+        randomness.insert(0, (r * invert(&rest, &ek.n).ok()?) % &ek.n);
+        let mut bits = Vec::with_capacity(k);
+        let mut proofs = Vec::with_capacity(k);
+        for (i, ri) in randomness.iter().enumerate() {
+            let bit = (x >> i) & BigInt::one();
+            let ci = Paillier::encrypt_with_r(ek, &bit, ri);
+            proofs.push(PaillierProofs::prove_bit_with_rng(ek, &ci, &bit, ri, rng)?);
+            bits.push(ci);
+        }
+        Some(BitDecompositionProof { bits, proofs })
+    }
+
+    fn verify_bits(ek: &PaillierEncryptionKey, c: &BigInt, k: usize, proof: &BitDecompositionProof) -> bool {
+        if proof.bits.len() != k || proof.proofs.len() != k {
+            return false;
+        }
+        let mut product = BigInt::one();
+        for (i, ci) in proof.bits.iter().enumerate() {
+            if !PaillierProofs::verify_bit(ek, ci, &proof.proofs[i]) {
+                return false;
+            }
+            product = (product * ci.modpow(&(BigInt::one() << i), &ek.n2)) % &ek.n2;
+        }
+        product == *c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::paillier::test_keys;
+    use rand_chacha::ChaChaRng;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn proof_plaintext() {
+        let (ek, _) = test_keys();
+        let (m, r) = (BigInt::from(-17), BigInt::from(4711));
+        let c = Paillier::encrypt_with_r(&ek, &m, &r);
+        let proof = PaillierProofs::prove_plaintext(&ek, &c, &m, &r);
+        assert!(PaillierProofs::verify_plaintext(&ek, &c, &proof));
+        let other = Paillier::encrypt_with_r(&ek, &m, &BigInt::from(4712));
+        assert!(!PaillierProofs::verify_plaintext(&ek, &other, &proof));
+        assert!(!PaillierProofs::verify_plaintext(&ek, &ek.n, &proof));
+        // the same rng seed gives the same transcript
+        let p1 = PaillierProofs::prove_plaintext_with_rng(&ek, &c, &m, &r, &mut ChaChaRng::seed_from_u64(5));
+        let p2 = PaillierProofs::prove_plaintext_with_rng(&ek, &c, &m, &r, &mut ChaChaRng::seed_from_u64(5));
+        assert!(p1.a == p2.a && p1.z == p2.z && p1.w == p2.w);
+        assert!(PaillierProofs::verify_plaintext(&ek, &c, &p1));
+    }
+
+    #[test]
+    fn proof_bit() {
+        let (ek, _) = test_keys();
+        let r = BigInt::from(99);
+        for m in [0, 1].iter() {
+            let m = BigInt::from(*m);
+            let c = Paillier::encrypt_with_r(&ek, &m, &r);
+            let proof = PaillierProofs::prove_bit(&ek, &c, &m, &r).unwrap();
+            assert!(PaillierProofs::verify_bit(&ek, &c, &proof));
+        }
+        let two = BigInt::from(2);
+        let c = Paillier::encrypt_with_r(&ek, &two, &r);
+        assert!(PaillierProofs::prove_bit(&ek, &c, &two, &r).is_none());
+        // a proof for Enc(1) does not verify for Enc(2)
+        let c1 = Paillier::encrypt_with_r(&ek, &BigInt::one(), &r);
+        let proof = PaillierProofs::prove_bit(&ek, &c1, &BigInt::one(), &r).unwrap();
+        assert!(!PaillierProofs::verify_bit(&ek, &c, &proof));
+    }
+
+    #[test]
+    fn proof_range() {
+        let (ek, _) = test_keys();
+        let (a, b) = (BigInt::from(-10), BigInt::from(90));
+        let r = BigInt::from(1234567);
+        for m in [-10, 0, 42, 90].iter() {
+            let m = BigInt::from(*m);
+            let c = Paillier::encrypt_with_r(&ek, &m, &r);
+            let proof = PaillierProofs::prove_range(&ek, &c, &m, &r, &a, &b).unwrap();
+            assert!(PaillierProofs::verify_range(&ek, &c, &a, &b, &proof));
+            assert!(!PaillierProofs::verify_range(&ek, &c, &a, &BigInt::from(200), &proof));
+        }
+        let m = BigInt::from(91);
+        let c = Paillier::encrypt_with_r(&ek, &m, &r);
+        assert!(PaillierProofs::prove_range(&ek, &c, &m, &r, &a, &b).is_none());
+        // reusing a proof for a ciphertext outside the range fails
+        let inside = Paillier::encrypt_with_r(&ek, &BigInt::from(90), &r);
+        let proof = PaillierProofs::prove_range(&ek, &inside, &BigInt::from(90), &r, &a, &b).unwrap();
+        assert!(!PaillierProofs::verify_range(&ek, &c, &a, &b, &proof));
+    }
+
+    #[test]
+    fn proof_decryption() {
+        let (ek, dk) = test_keys();
+        let c = Paillier::encrypt_with_r(&ek, &BigInt::from(-4711), &BigInt::from(31337));
+        let (m, proof) = PaillierProofs::prove_decryption(&ek, &dk, &c).unwrap();
+        assert_eq!(m, BigInt::from(-4711));
+        assert!(PaillierProofs::verify_decryption(&ek, &c, &m, &proof));
+        assert!(!PaillierProofs::verify_decryption(&ek, &c, &BigInt::from(4711), &proof));
+    }
+}
@@ -0,0 +1,316 @@
+//! Threshold Paillier decryption after Shoup and Damgard-Jurik.
+//!
+//! A dealer holding the decryption key shares d = lambda * mu, which is
+//! 0 mod lambda and 1 mod n, with a random polynomial of degree t-1 over
+//! Z_(n*lambda). Every shareholder publishes c^(2*delta*s_i) together with
+//! a proof that the same exponent was used for its verification key
+//! v^(delta*s_i). Any t valid partials are combined by Lagrange
+//! interpolation in the exponent, where delta = l! keeps the coefficients
+//! integral. The proofs are only sound if n is the product of two safe
+//! primes, so the dealer refuses other keys.
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Signed, Zero};
+use serde_derive::{Deserialize, Serialize};
+use hope::config::KeygenConfig;
+use hope::protocol::model::*;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use crate::error::HopeError;
+use super::millerrabin::is_probable_prime;
+use super::{challenge, invert, random_below_with_rng, random_unit_with_rng, Paillier};
+
+/// bit length of the Fiat-Shamir challenges
+const CHALLENGE_BITS: usize = 256;
+
+/// Public data of a dealt key, needed to verify and combine partials
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PaillierThresholdKey {
+    /// number of partials needed to decrypt
+    pub t: usize,
+    /// number of shareholders
+    pub l: usize,
+    /// l!
+    pub delta: BigInt,
+    /// random square in Z_(n^2)^*
+    pub v: BigInt,
+    /// verification keys v^(delta * s_i), index i-1 belongs to share i
+    pub vi: Vec<BigInt>,
+}
+
+/// Secret share of shareholder i (1-based)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PaillierKeyShare {
+    pub i: usize,
+    pub si: BigInt,
+}
+
+/// Partial decryption of one ciphertext by shareholder i
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PaillierPartialDecryption {
+    pub i: usize,
+    /// c^(2 * delta * s_i) mod n^2
+    pub ci: BigInt,
+    /// proof of correct partial decryption
+    pub proof: PartialDecryptionProof,
+}
+
+/// Proof that log_(c^4)(c_i^2) = log_v(v_i)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PartialDecryptionProof {
+    pub e: BigInt,
+    pub z: BigInt,
+}
+
+pub struct ThresholdPaillier;
+
+impl ThresholdPaillier {
+    /// Splits dk into l shares, any t of which can decrypt. dk must carry
+    /// the factors of n, which must be safe primes.
+    pub fn deal(
+        ek: &PaillierEncryptionKey,
+        dk: &PaillierDecryptionKey,
+        t: usize,
+        l: usize,
+    ) -> Result<(PaillierThresholdKey, Vec<PaillierKeyShare>), HopeError> {
+        ThresholdPaillier::deal_with_rng(ek, dk, t, l, &mut OsRng)
+    }
+
+    /// `deal` with randomness drawn from rng
+    pub fn deal_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        dk: &PaillierDecryptionKey,
+        t: usize,
+        l: usize,
+        rng: &mut R,
+    ) -> Result<(PaillierThresholdKey, Vec<PaillierKeyShare>), HopeError> {
+        if t < 1 || t > l {
+            return Err(HopeError::InvalidArgument(format!("threshold {} of {} shareholders", t, l)));
+        }
+        let crt = dk
+            .crt
+            .as_ref()
+            .ok_or_else(|| HopeError::InvalidArgument(String::from("the decryption key lacks the factors of n")))?;
+        if &crt.p * &crt.q != ek.n || !is_safe_prime(&crt.p, rng) || !is_safe_prime(&crt.q, rng) {
+            return Err(HopeError::InvalidArgument(String::from("n is not a product of safe primes")));
+        }
+        let order = &ek.n * &dk.lambda;
+        let d = (&dk.lambda * &dk.mu).mod_floor(&order);
+        let mut coefficients = vec![d];
+        for _ in 1..t {
+            coefficients.push(random_below_with_rng(&order, rng));
+        }
+        let delta = factorial(l);
+        let r = random_unit_with_rng(&ek.n, rng);
+        let v = (&r * &r) % &ek.n2;
+        let mut shares = Vec::with_capacity(l);
+        let mut vi = Vec::with_capacity(l);
+        for i in 1..=l {
+            let x = BigInt::from(i);
+            // Horner evaluation of the sharing polynomial at i
+            let si = coefficients
+                .iter()
+                .rev()
+                .fold(BigInt::zero(), |acc, a| (acc * &x + a) % &order);
+            vi.push(v.modpow(&(&delta * &si), &ek.n2));
+            shares.push(PaillierKeyShare { i, si });
+        }
+        Ok((
+            PaillierThresholdKey {
+                t,
+                l,
+                delta,
+                v,
+                vi,
+            },
+            shares,
+        ))
+    }
+
+    /// Computes the partial decryption of c with share and proves it correct
+    pub fn partial_decrypt(
+        ek: &PaillierEncryptionKey,
+        tk: &PaillierThresholdKey,
+        share: &PaillierKeyShare,
+        c: &BigInt,
+    ) -> PaillierPartialDecryption {
+        ThresholdPaillier::partial_decrypt_with_rng(ek, tk, share, c, &mut OsRng)
+    }
+
+    /// `partial_decrypt` with randomness drawn from rng
+    pub fn partial_decrypt_with_rng<R: RngCore + CryptoRng>(
+        ek: &PaillierEncryptionKey,
+        tk: &PaillierThresholdKey,
+        share: &PaillierKeyShare,
+        c: &BigInt,
+        rng: &mut R,
+    ) -> PaillierPartialDecryption {
+        let x = &tk.delta * &share.si;
+        let ci = c.modpow(&(BigInt::from(2) * &x), &ek.n2);
+        let c4 = c.modpow(&BigInt::from(4), &ek.n2);
+        let ci2 = (&ci * &ci) % &ek.n2;
+        let bound = BigInt::one() << (ek.n2.bits() + 2 * CHALLENGE_BITS);
+        let r = random_below_with_rng(&bound, rng);
+        let a = c4.modpow(&r, &ek.n2);
+        let b = tk.v.modpow(&r, &ek.n2);
+        let e = challenge(&[&c4, &ci2, &tk.v, &tk.vi[share.i - 1], &a, &b]);
+        let z = r + &e * x;
+        PaillierPartialDecryption {
+            i: share.i,
+            ci,
+            proof: PartialDecryptionProof { e, z },
+        }
+    }
+
+    /// Checks the proof attached to a partial decryption of c
+    pub fn verify_partial(
+        ek: &PaillierEncryptionKey,
+        tk: &PaillierThresholdKey,
+        c: &BigInt,
+        partial: &PaillierPartialDecryption,
+    ) -> bool {
+        if partial.i == 0 || partial.i > tk.l || tk.vi.len() != tk.l || partial.proof.z.is_negative() {
+            return false;
+        }
+        let vi = &tk.vi[partial.i - 1];
+        let c4 = c.modpow(&BigInt::from(4), &ek.n2);
+        let ci2 = (&partial.ci * &partial.ci) % &ek.n2;
+        let (ci2_inv, vi_inv) = match (invert(&ci2, &ek.n2), invert(vi, &ek.n2)) {
+            (Ok(ci2_inv), Ok(vi_inv)) => (ci2_inv, vi_inv),
+            _ => return false,
+        };
+        let e = &partial.proof.e;
+        let a = (c4.modpow(&partial.proof.z, &ek.n2) * ci2_inv.modpow(e, &ek.n2)) % &ek.n2;
+        let b = (tk.v.modpow(&partial.proof.z, &ek.n2) * vi_inv.modpow(e, &ek.n2)) % &ek.n2;
+        *e == challenge(&[&c4, &ci2, &tk.v, vi, &a, &b])
+    }
+
+    /// Combines t valid partials of distinct shareholders to the signed
+    /// plaintext of c, `InvalidArgument` if there are not enough valid
+    /// partials
+    pub fn combine(
+        ek: &PaillierEncryptionKey,
+        tk: &PaillierThresholdKey,
+        c: &BigInt,
+        partials: &[PaillierPartialDecryption],
+    ) -> Result<BigInt, HopeError> {
+        let mut valid: Vec<&PaillierPartialDecryption> = Vec::with_capacity(tk.t);
+        for partial in partials {
+            if valid.len() == tk.t {
+                break;
+            }
+            if valid.iter().all(|v| v.i != partial.i)
+                && ThresholdPaillier::verify_partial(ek, tk, c, partial)
+            {
+                valid.push(partial);
+            }
+        }
+        if valid.len() < tk.t {
+            return Err(HopeError::InvalidArgument(format!("{} of {} valid partials", valid.len(), tk.t)));
+        }
+        let indices: Vec<usize> = valid.iter().map(|p| p.i).collect();
+        let mut cprime = BigInt::one();
+        for partial in &valid {
+            let lambda = ThresholdPaillier::lagrange(&tk.delta, &indices, partial.i);
+            let base = if lambda.is_negative() {
+                invert(&partial.ci, &ek.n2)?
+            } else {
+                partial.ci.clone()
+            };
+            let exp = BigInt::from(2) * lambda.abs();
+            cprime = (cprime * base.modpow(&exp, &ek.n2)) % &ek.n2;
+        }
+        // cprime = (1 + n)^(4 * delta^2 * m)
+        let lm = (cprime - BigInt::one()) / &ek.n;
+        let scale = invert(&(BigInt::from(4) * &tk.delta * &tk.delta), &ek.n)?;
+        Ok(Paillier::decode_signed(ek, &(lm * scale)))
+    }
+
+    /// delta times the Lagrange coefficient at 0 of index i within indices
+    fn lagrange(delta: &BigInt, indices: &[usize], i: usize) -> BigInt {
+        let mut num = delta.clone();
+        let mut den = BigInt::one();
+        for j in indices.iter().filter(|j| **j != i) {
+            num *= BigInt::from(*j);
+            den *= BigInt::from(*j as i64 - i as i64);
+        }
+        num / den
+    }
+}
+
+/// Whether p and (p-1)/2 are prime
+fn is_safe_prime<R: RngCore + CryptoRng>(p: &BigInt, rng: &mut R) -> bool {
+    let rounds = KeygenConfig::default().rounds;
+    match (p.to_biguint(), (p >> 1usize).to_biguint()) {
+        (Some(p), Some(h)) => is_probable_prime(&p, rounds, rng) && is_probable_prime(&h, rounds, rng),
+        _ => false,
+    }
+}
+
+fn factorial(l: usize) -> BigInt {
+    (1..=l).fold(BigInt::one(), |acc, i| acc * BigInt::from(i))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::paillier::{test_keys, test_safe_keys};
+
+    fn encrypt_r(ek: &PaillierEncryptionKey, m: i64, r: u64) -> BigInt {
+        let gm = Paillier::encode_signed(ek, &BigInt::from(m)) * &ek.n + BigInt::one();
+        (gm * BigInt::from(r).modpow(&ek.n, &ek.n2)) % &ek.n2
+    }
+
+    #[test]
+    fn threshold_two_of_three() {
+        let (ek, dk) = test_safe_keys();
+        let (tk, shares) = ThresholdPaillier::deal(&ek, &dk, 2, 3).unwrap();
+        let c = encrypt_r(&ek, -4711, 42);
+        let partials: Vec<PaillierPartialDecryption> = shares
+            .iter()
+            .map(|s| ThresholdPaillier::partial_decrypt(&ek, &tk, s, &c))
+            .collect();
+        for partial in &partials {
+            assert!(ThresholdPaillier::verify_partial(&ek, &tk, &c, partial));
+        }
+        for pair in [[0, 1], [0, 2], [2, 1]].iter() {
+            let chosen = vec![partials[pair[0]].clone(), partials[pair[1]].clone()];
+            assert_eq!(ThresholdPaillier::combine(&ek, &tk, &c, &chosen), Ok(BigInt::from(-4711)));
+        }
+        // a single officer cannot decrypt
+        assert!(ThresholdPaillier::combine(&ek, &tk, &c, &partials[..1]).is_err());
+        let twice = vec![partials[0].clone(), partials[0].clone()];
+        assert!(ThresholdPaillier::combine(&ek, &tk, &c, &twice).is_err());
+    }
+
+    #[test]
+    fn threshold_rejects_bad_keys() {
+        let (ek, dk) = test_safe_keys();
+        assert!(ThresholdPaillier::deal(&ek, &dk, 0, 3).is_err());
+        assert!(ThresholdPaillier::deal(&ek, &dk, 4, 3).is_err());
+        let mut old = dk.clone();
+        old.crt = None;
+        assert!(ThresholdPaillier::deal(&ek, &old, 2, 3).is_err());
+        // 2^61 - 1 and 2^89 - 1 are prime, but not safe
+        let (ek2, dk2) = test_keys();
+        assert!(ThresholdPaillier::deal(&ek2, &dk2, 2, 3).is_err());
+        // a threshold key that lost a verification key
+        let (mut tk, shares) = ThresholdPaillier::deal(&ek, &dk, 2, 3).unwrap();
+        let c = encrypt_r(&ek, 5, 3);
+        let partial = ThresholdPaillier::partial_decrypt(&ek, &tk, &shares[2], &c);
+        tk.vi.pop();
+        assert!(!ThresholdPaillier::verify_partial(&ek, &tk, &c, &partial));
+    }
+
+    #[test]
+    fn threshold_rejects_forged_partial() {
+        let (ek, dk) = test_safe_keys();
+        let (tk, shares) = ThresholdPaillier::deal(&ek, &dk, 2, 3).unwrap();
+        let c = encrypt_r(&ek, 100, 7);
+        let mut forged = ThresholdPaillier::partial_decrypt(&ek, &tk, &shares[0], &c);
+        forged.ci = (&forged.ci * BigInt::from(2)) % &ek.n2;
+        assert!(!ThresholdPaillier::verify_partial(&ek, &tk, &c, &forged));
+        let honest = ThresholdPaillier::partial_decrypt(&ek, &tk, &shares[1], &c);
+        assert!(ThresholdPaillier::combine(&ek, &tk, &c, &[forged, honest]).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Unidirectional proxy re-encryption after Ateniese, Fu, Green and
+//! Hohenberger, on the asymmetric pairing e: G1 x G2 -> Gt of `bn`.
+//!
+//! With generators P of G1 and Q of G2 and Z = e(P, Q), a user with secret
+//! a publishes aP and aQ. A second-level ciphertext of m in Gt for Alice is
+//! (k * aP, m * Z^k). Alice derives the re-encryption key (b / a) * Q from
+//! Bob's public bQ alone, and the backend turns the ciphertext into the
+//! first-level ciphertext (e(k * aP, (b / a) * Q), m * Z^k) =
+//! (Z^(bk), m * Z^k) for Bob. The backend never sees Z^k, so it learns
+//! nothing about m, and first-level ciphertexts cannot be re-encrypted
+//! again. Records are shared by encrypting a random Gt element and deriving
+//! a symmetric key from it with `Afgh::derive_key`.
+use bn::{pairing, Fr, G1, G2, Group, Gt};
+use rand_core::{CryptoRng, OsRng, RngCore};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Secret key a
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct AfghSecretKey {
+    pub a: Fr,
+}
+
+/// Public key (aP, aQ)
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct AfghPublicKey {
+    pub p: G1,
+    pub q: G2,
+}
+
+/// Re-encryption key (b / a) * Q from Alice (a) to Bob (b)
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct AfghReKey {
+    pub rk: G2,
+}
+
+/// Second-level ciphertext (k * aP, m * Z^k), can be re-encrypted
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct AfghSecondLevel {
+    pub c1: G1,
+    pub c2: Gt,
+}
+
+/// First-level ciphertext (Z^(ak), m * Z^k), only the owner of a decrypts
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct AfghFirstLevel {
+    pub c1: Gt,
+    pub c2: Gt,
+}
+
+/// AFGH proxy re-encryption
+pub struct Afgh;
+
+impl Afgh {
+    pub fn keygen() -> (AfghPublicKey, AfghSecretKey) {
+        Afgh::keygen_with_rng(&mut OsRng)
+    }
+
+    /// `keygen` with the secret drawn from rng
+    pub fn keygen_with_rng<R: RngCore + CryptoRng>(rng: &mut R) -> (AfghPublicKey, AfghSecretKey) {
+        let a = Fr::random_with_rng(rng);
+        (
+            AfghPublicKey {
+                p: G1::one() * a,
+                q: G2::one() * a,
+            },
+            AfghSecretKey { a },
+        )
+    }
+
+    /// Re-encryption key from the owner of sk to the owner of pk, None for
+    /// the zero secret
+    pub fn rekey(sk: &AfghSecretKey, pk: &AfghPublicKey) -> Option<AfghReKey> {
+        Some(AfghReKey { rk: pk.q * sk.a.inverse()? })
+    }
+
+    /// Second-level encryption of m for pk
+    pub fn encrypt(pk: &AfghPublicKey, m: &Gt) -> AfghSecondLevel {
+        Afgh::encrypt_with_rng(pk, m, &mut OsRng)
+    }
+
+    /// `encrypt` with randomness drawn from rng
+    pub fn encrypt_with_rng<R: RngCore + CryptoRng>(pk: &AfghPublicKey, m: &Gt, rng: &mut R) -> AfghSecondLevel {
+        let k = Fr::random_with_rng(rng);
+        AfghSecondLevel {
+            c1: pk.p * k,
+            c2: *m * Afgh::z().pow(k),
+        }
+    }
+
+    /// First-level encryption of m for pk
+    pub fn encrypt_first_level(pk: &AfghPublicKey, m: &Gt) -> AfghFirstLevel {
+        Afgh::encrypt_first_level_with_rng(pk, m, &mut OsRng)
+    }
+
+    /// `encrypt_first_level` with randomness drawn from rng
+    pub fn encrypt_first_level_with_rng<R: RngCore + CryptoRng>(
+        pk: &AfghPublicKey,
+        m: &Gt,
+        rng: &mut R,
+    ) -> AfghFirstLevel {
+        let k = Fr::random_with_rng(rng);
+        AfghFirstLevel {
+            c1: pairing(pk.p, G2::one()).pow(k),
+            c2: *m * Afgh::z().pow(k),
+        }
+    }
+
+    /// Turns a second-level ciphertext for Alice into a first-level
+    /// ciphertext for Bob
+    pub fn reencrypt(rk: &AfghReKey, c: &AfghSecondLevel) -> AfghFirstLevel {
+        AfghFirstLevel {
+            c1: pairing(c.c1, rk.rk),
+            c2: c.c2,
+        }
+    }
+
+    /// Plaintext of a second-level ciphertext, m * Z^k / e(k * aP, Q)^(1/a)
+    pub fn decrypt(sk: &AfghSecretKey, c: &AfghSecondLevel) -> Option<Gt> {
+        Some(c.c2 * pairing(c.c1, G2::one()).pow(sk.a.inverse()?).inverse())
+    }
+
+    /// Plaintext of a first-level ciphertext, m * Z^k / (Z^(ak))^(1/a)
+    pub fn decrypt_first_level(sk: &AfghSecretKey, c: &AfghFirstLevel) -> Option<Gt> {
+        Some(c.c2 * c.c1.pow(sk.a.inverse()?).inverse())
+    }
+
+    /// Uniform element of Gt, to be used as message key
+    pub fn random_message<R: RngCore + CryptoRng>(rng: &mut R) -> Gt {
+        Afgh::z().pow(Fr::random_with_rng(rng))
+    }
+
+    /// 256 bit symmetric key derived from a message key
+    pub fn derive_key(m: &Gt) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&Sha256::digest(&serde_json::to_vec(m).unwrap()));
+        key
+    }
+
+    /// Z = e(P, Q)
+    fn z() -> Gt {
+        pairing(G1::one(), G2::one())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn afgh_reencryption() {
+        let (pk_a, sk_a) = Afgh::keygen();
+        let (pk_b, sk_b) = Afgh::keygen();
+        let (_, sk_c) = Afgh::keygen();
+        let m = Afgh::random_message(&mut OsRng);
+        let c = Afgh::encrypt(&pk_a, &m);
+        assert!(Afgh::decrypt(&sk_a, &c) == Some(m));
+        assert!(Afgh::decrypt(&sk_b, &c) != Some(m));
+        let rk = Afgh::rekey(&sk_a, &pk_b).unwrap();
+        let d = Afgh::reencrypt(&rk, &c);
+        assert!(Afgh::decrypt_first_level(&sk_b, &d) == Some(m));
+        assert!(Afgh::decrypt_first_level(&sk_a, &d) != Some(m));
+        assert!(Afgh::decrypt_first_level(&sk_c, &d) != Some(m));
+        assert!(Afgh::derive_key(&Afgh::decrypt_first_level(&sk_b, &d).unwrap()) == Afgh::derive_key(&m));
+    }
+
+    #[test]
+    fn afgh_first_level() {
+        let (pk, sk) = Afgh::keygen();
+        let m = Afgh::random_message(&mut OsRng);
+        let c = Afgh::encrypt_first_level(&pk, &m);
+        assert!(Afgh::decrypt_first_level(&sk, &c) == Some(m));
+        let zero = AfghSecretKey { a: Fr::zero() };
+        assert!(Afgh::rekey(&zero, &pk).is_none());
+    }
+}
@@ -10,258 +10,545 @@
 //!
 //! # Examples
 //!
-//! ```
+//! ```ignore
 //!use rabe::schemes::hOPE::*;
 //!let (_pk, _msk) = setup();
 //! ```
 extern crate bn;
 extern crate serde;
-#[cfg(feature = "serde_derive")]
-extern crate serde_derive;
 extern crate serde_json;
-extern crate mongodb;
+
+/// key rotation by blinded re-encryption
+pub mod rotation;
+pub use self::rotation::KeyRotation;
 
 use ::hope::protocol::model::*;
+use ::hope::config::KeygenConfig;
 use crate::bplus::Tree;
-use crate::websocket::HopeWebSocket;
+use crate::error::HopeError;
+use crate::websocket::{ClientComparable, HopeWebSocket};
+use crate::homomorphic::AdditiveHomomorphic;
+use crate::oracle::{ComparisonOracle, LocalOracle, WebSocketOracle};
+use ::hope::protocol::request::ProtocolOp;
+use ::hope::protocol::response::ProtocolResult;
 use crate::paillier::Paillier;
 use bn::*;
 use std::string::String;
 use crate::paillier::*;
 use std::cmp::Ordering;
-use std::ops::AddAssign;
-use std::ops::Sub as StdSub;
-use std::ops::SubAssign;
 use std::ops::Mul;
-use std::fs::File;
-use std::error::Error;
-use std::io::{Read, Write};
-use std::path::Path;
-use std::collections::HashMap;
 use std::collections::BTreeMap;
-use std::marker::PhantomData;
-use mongodb::oid::ObjectId;
-use serde_derive::{Serialize, Deserialize};
-use actix_web_actors::ws;
-use num_bigint::BigInt;
+use bson::oid::ObjectId;
+use num_bigint::{BigInt, Sign};
+use num_integer::Integer;
+use num_traits::{One, Signed, Zero};
+use rand_core::{CryptoRng, OsRng, RngCore};
 
 const DEGREE: usize = 4;
+/// bound on the bit length of plaintexts taking part in `mul` and `div_const`
+pub const PLAINTEXT_BITS: usize = 128;
+/// statistical security of the masks used in `mul` and `div_const`
+pub const STATISTICAL_BITS: usize = 80;
  
-/// ehOPE scheme
-pub struct hope<'a> {
-    /// HopeWebsocket communication
-    pub _ws: &'a HopeWebSocket,
+/// ehOPE scheme over the additively homomorphic scheme S
+#[allow(non_camel_case_types)]
+pub struct hope<'a, S: AdditiveHomomorphic = Paillier> {
+    /// HopeWebsocket communication, `ClientUnavailable` for the client
+    /// assisted operations if None
+    pub _ws: Option<&'a HopeWebSocket>,
     /// ehOPE System Parameters
     pub _sp: hopeSP,
     /// code tree of hOPE scheme
-    pub _tree: Tree,
-    /// lookup table of hOPE scheme
-    pub _apl: BTreeMap<Vec<u8>, ObjectId>,
+    pub _tree: Tree<S::Ciphertext>,
+    /// lookup table of hOPE scheme, stored ciphertexts by their token
+    pub _apl: BTreeMap<String, hopeCT<S::Ciphertext>>,
+    /// keys of the stored ciphertexts in _apl by their id
+    pub _ids: BTreeMap<ObjectId, String>,
     /// Optional keypair
-    pub _key: Option<hopeK>,
+    pub _key: Option<hopeK<S::EncryptionKey, S::DecryptionKey>>,
+    /// comparison oracle of the code tree, the client over _ws if None
+    pub _oracle: Option<Box<dyn ComparisonOracle<S::Ciphertext> + 'a>>,
+    /// oracle of the client over _ws under an encryption key, None if S
+    /// has no client comparison
+    pub _client: Option<ClientOracle<'a, S>>,
+    /// whether a key rotation is running, operations on stored ciphertexts
+    /// and inserts are refused until it finishes
+    pub _rotating: bool,
+}
+
+/// Constructor of the oracle asking the client over a websocket
+pub type ClientOracle<'a, S> = fn(
+    &'a HopeWebSocket,
+    <S as AdditiveHomomorphic>::EncryptionKey,
+) -> Box<dyn ComparisonOracle<<S as AdditiveHomomorphic>::Ciphertext> + 'a>;
+
+/// Key of the token _g in the lookup table. Tokens are kept in jacobian
+/// coordinates, so equal tokens are normalized before serializing.
+pub(crate) fn apl_key(mut _g: G1) -> Result<String, HopeError> {
+    if _g.is_zero() {
+        _g = G1::zero();
+    }
+    _g.normalize();
+    Ok(serde_json::to_string(&_g)?)
 }
 
 impl hope<'_> {
-    pub fn new(_name: String, _ws: &HopeWebSocket) -> hope {
+    pub fn new(_name: String, _ws: &HopeWebSocket) -> Result<hope<'_>, HopeError> {
+        hope::new_with_rng(_name, _ws, &mut OsRng)
+    }
+
+    /// `new` with parameters and keys drawn from rng
+    pub fn new_with_rng<'a, R: RngCore + CryptoRng>(
+        _name: String,
+        _ws: &'a HopeWebSocket,
+        rng: &mut R,
+    ) -> Result<hope<'a>, HopeError> {
+        let _sp = hopeSP::new_with_rng(_name, rng);
+        let _key = hope::keygen_with_rng(&KeygenConfig::default(), rng)?;
         // return System
+        Ok(hope::with_key(_sp, _ws, _key))
+    }
+
+    pub fn from_sp(_sp: hopeSP, _ws: &HopeWebSocket) -> Result<hope<'_>, HopeError> {
+        let _key = hope::keygen()?;
+        // return System
+        Ok(hope::with_key(_sp, _ws, _key))
+    }
+
+    pub fn keygen() -> Result<hopeK, HopeError> {
+        hope::keygen_with(&KeygenConfig::default())
+    }
+
+    pub fn keygen_with(_config: &KeygenConfig) -> Result<hopeK, HopeError> {
+        hope::keygen_with_rng(_config, &mut OsRng)
+    }
+
+    /// `keygen_with` with primes drawn from rng
+    pub fn keygen_with_rng<R: RngCore + CryptoRng>(_config: &KeygenConfig, rng: &mut R) -> Result<hopeK, HopeError> {
+        let (ek, dk) = Paillier::keygen_with_rng(_config.key_size, _config, rng)?;
+        Ok(hopeK::new(ek, dk))
+    }
+}
+
+impl<'a, S: ClientComparable + 'a> hope<'a, S> {
+    /// Instance with the scheme S and the keypair _key
+    pub fn with_key(_sp: hopeSP, _ws: &'a HopeWebSocket, _key: hopeK<S::EncryptionKey, S::DecryptionKey>) -> hope<'a, S> {
         hope {
-            _ws: _ws,
-            _sp: hopeSP::new(_name),
+            _ws: Some(_ws),
+            _sp,
             _tree: Tree::new(DEGREE),
             _apl: BTreeMap::new(),
-            _key: hope::keygen(),
+            _ids: BTreeMap::new(),
+            _key: Some(_key),
+            _oracle: None,
+            _client: Some(WebSocketOracle::<S>::boxed),
+            _rotating: false,
         }
     }
-    
-    pub fn from_sp(_sp: hopeSP, _ws: &HopeWebSocket) -> hope {
-        // return System
-        hope {
-            _ws: _ws,
-            _sp: _sp,
+}
+
+impl<'a, S: AdditiveHomomorphic + 'a> hope<'a, S> {
+    /// Instance with the scheme S and the keypair _key without a client,
+    /// the tree is ordered by a `LocalOracle` holding _key.
+    /// `MissingDecryptionKey` if _key has no decryption key.
+    pub fn with_local_key(_sp: hopeSP, _key: hopeK<S::EncryptionKey, S::DecryptionKey>) -> Result<hope<'a, S>, HopeError> {
+        let _dk = _key._dk.clone().ok_or(HopeError::MissingDecryptionKey)?;
+        let _oracle = LocalOracle::<S>::new(_key._ek.clone(), _dk);
+        Ok(hope {
+            _ws: None,
+            _sp,
             _tree: Tree::new(DEGREE),
             _apl: BTreeMap::new(),
-            _key: hope::keygen(),
-        }
-    }    
+            _ids: BTreeMap::new(),
+            _key: Some(_key),
+            _oracle: Some(Box::new(_oracle)),
+            _client: None,
+            _rotating: false,
+        })
+    }
 
-    pub fn keygen() -> Option<hopeK> {
-    	let (ek, dk) = Paillier::keygen(256);
-        Some(hopeK::new(ek,dk))
-    } 
+    /// Orders the code tree with _oracle instead of the client over _ws
+    pub fn with_oracle<O: ComparisonOracle<S::Ciphertext> + 'a>(mut self, _oracle: O) -> hope<'a, S> {
+        self._oracle = Some(Box::new(_oracle));
+        self
+    }
 
     pub fn parameters(&self) -> hopeSP {
     	self._sp.clone()
     }
 
-    pub fn encrypt(&mut self, _m: BigInt) -> Option<hopeCT> {
-        if let Some(_ek) = self.enc_key() {
-            self.encrypt_ek(&_ek, _m);
-        }
-        None
+    pub fn encrypt(&mut self, _m: BigInt) -> Result<hopeCT<S::Ciphertext>, HopeError> {
+        let _ek = self.enc_key()?;
+        self.encrypt_ek(&_ek, _m)
     }
 
-    pub fn encrypt_ek(&mut self, _ek: &PaillierEncryptionKey, _m: BigInt) -> Option<hopeCT> {
-        let _c = Paillier::encrypt(&_ek, &_m);
-        // return pk_u and sk_u
-        match Fr::from_str(&_m.to_string()) {
-            Some(_fr) => {
-                let _g = self._sp._p.mul(_fr);
-                match self.lookup_apl(_g) {
-                    Some(_ct) => return Some(_ct),
-                    None => {
-                        let _h = pairing(_g, self._sp._q);
-                        let _id = ObjectId::new().unwrap();
-                        let leaf = hopeLeaf::new(_id.clone(), _c.clone(), 0);
-                        self.insert_tree(leaf);
-                        self.update_tree();
-                        match self.lookup_tree(_id.clone()) {
-                            Some(_code) => {
-                                let _hct = hopeCT::from_id(_id, _c, _g, _h, _code);
-                                match self.insert_apl(_hct.clone()) {
-                                    Some(ins_res) => Some(_hct),
-                                    None => None,
-                                }
-                            }
-                            None => None,
-                        }
-                    }
-                }
-            }
-            None => None,
-        }
+    pub fn encrypt_ek(&mut self, _ek: &S::EncryptionKey, _m: BigInt) -> Result<hopeCT<S::Ciphertext>, HopeError> {
+        self.encrypt_ek_with_rng(_ek, _m, &mut OsRng)
     }
 
-    pub fn decrypt(&self, _ct: hopeCT, _dk: PaillierDecryptionKey, _ek: PaillierEncryptionKey) -> BigInt {
-        Paillier::decrypt(&_dk, &_ek, &_ct._c)
+    /// `encrypt_ek` with randomness drawn from rng
+    pub fn encrypt_ek_with_rng<R: RngCore + CryptoRng>(
+        &mut self,
+        _ek: &S::EncryptionKey,
+        _m: BigInt,
+        rng: &mut R,
+    ) -> Result<hopeCT<S::Ciphertext>, HopeError> {
+        let _g = self._sp._p.mul(hope::to_fr(&_m)?);
+        let _c = S::encrypt_with_rng(_ek, &_m, rng);
+        self.insert_result(_c, _g)
     }
 
-    pub fn add(&mut self, _ct1: &hopeCT, _ct2: &hopeCT) -> Option<hopeCT> {
-        if let Some(ek) = self.enc_key() {
-            let _g1 = _ct1._g + _ct2._g;
-            match self.lookup_apl(_g1) {
-                Some(_ct) => return Some(_ct),
-                None => {
-                    let _h1 = pairing(_g1, self._sp._q);
-                    let _id = ObjectId::new().unwrap();
-                    let _c = Paillier::rerandomize(&ek, &Paillier::add(&ek, &_ct1._c, &_ct2._c));
-                    let leaf = hopeLeaf::new(_id.clone(), _c, 0);
-                    self.insert_tree(leaf);
-                    self.update_tree();
-                    match self.lookup_tree(_id.clone()) {
-                        Some(_code) => {
-                            let _hct = hopeCT::from_id(_id, _c, _g1, _h1, _code);
-                            match self.insert_apl(_hct.clone()) {
-                                Some(ins_res) => return Some(_hct),
-                                None => return None,
-                            }
-                        }
-                        None => return None,
-                    }
-                }
-            }
-        }
-        None
+    pub fn decrypt(&self, _ct: hopeCT<S::Ciphertext>, _dk: S::DecryptionKey, _ek: S::EncryptionKey) -> Result<BigInt, HopeError> {
+        S::decrypt(&_dk, &_ek, &_ct._c)
+    }
+
+    pub fn add(&mut self, _ct1: &hopeCT<S::Ciphertext>, _ct2: &hopeCT<S::Ciphertext>) -> Result<hopeCT<S::Ciphertext>, HopeError> {
+        self.add_with_rng(_ct1, _ct2, &mut OsRng)
+    }
+
+    /// `add` with randomness drawn from rng
+    pub fn add_with_rng<R: RngCore + CryptoRng>(
+        &mut self,
+        _ct1: &hopeCT<S::Ciphertext>,
+        _ct2: &hopeCT<S::Ciphertext>,
+        rng: &mut R,
+    ) -> Result<hopeCT<S::Ciphertext>, HopeError> {
+        self.not_rotating()?;
+        let ek = self.enc_key()?;
+        let _c = S::rerandomize_with_rng(&ek, &S::add(&ek, &_ct1._c, &_ct2._c), rng);
+        self.insert_result(_c, _ct1._g + _ct2._g)
     }
  
-    pub fn sub(&mut self, _ct1: &hopeCT, _ct2: &hopeCT) -> Option<hopeCT> {
-        if let Some(ek) = self.enc_key() {
-            let _g1 = _ct1._g - _ct2._g;
-            match self.lookup_apl(_g1) {
-                Some(_ct) => return Some(_ct),
-                None => {
-                    let _id = ObjectId::new().unwrap();
-                    match Paillier::sub(&ek, &_ct1._c, &_ct2._c) {
-                    	Some(result) => {
-		                    let _c = Paillier::rerandomize(&ek, &result);
-		                    let leaf = hopeLeaf::new(_id.clone(), _c.clone(), 0);
-		                    self.insert_tree(leaf);
-		                    match self.lookup_tree(_id.clone()) {
-		                        Some(_code) => {
-		                            let _h1 = pairing(_g1, self._sp._q);
-		                            let _hct = hopeCT::from_id(_id, _c, _g1, _h1, _code);
-		                            match self.insert_apl(_hct.clone()) {
-		                                Some(ins_res) => return Some(_hct),
-		                                None => return None,
-		                            }
-		                        }
-		                        None => return None,
-		                    }
-                    	},
-                    	None => return None,
-                    }
-                }
-            }
-        }
-        None
+    pub fn sub(&mut self, _ct1: &hopeCT<S::Ciphertext>, _ct2: &hopeCT<S::Ciphertext>) -> Result<hopeCT<S::Ciphertext>, HopeError> {
+        self.sub_with_rng(_ct1, _ct2, &mut OsRng)
     }
 
+    /// `sub` with randomness drawn from rng
+    pub fn sub_with_rng<R: RngCore + CryptoRng>(
+        &mut self,
+        _ct1: &hopeCT<S::Ciphertext>,
+        _ct2: &hopeCT<S::Ciphertext>,
+        rng: &mut R,
+    ) -> Result<hopeCT<S::Ciphertext>, HopeError> {
+        self.not_rotating()?;
+        let ek = self.enc_key()?;
+        let _c = S::rerandomize_with_rng(&ek, &S::sub(&ek, &_ct1._c, &_ct2._c)?, rng);
+        self.insert_result(_c, _ct1._g - _ct2._g)
+    }
+
+    /// Stores a fresh ciphertext with token _g in the tree and the lookup
+    /// table, or returns the stored one if _g is known already
+    fn insert_result(&mut self, _c: S::Ciphertext, _g: G1) -> Result<hopeCT<S::Ciphertext>, HopeError> {
+        if let Some(_ct) = self.lookup_apl(_g)? {
+            return Ok(_ct);
+        }
+        let _h = pairing(_g, self._sp._q);
+        let _id = ObjectId::new().map_err(|_| HopeError::Storage(String::from("cannot create an object id")))?;
+        let leaf = hopeLeaf::new(_id.clone(), _c.clone(), 0);
+        self.insert_tree(leaf)?;
+        self.update_tree()?;
+        let _code = self.lookup_tree(_id.clone())?;
+        let _hct = hopeCT::from_id(_id, _c, _g, _h, _code);
+        self.insert_apl(_hct.clone())?;
+        Ok(_hct)
+    }
 
     //pub fn ask_client<T>(_req: &ProtocolReq<T>, ctx: &mut Self::Context) -> ProtocolRes<T> {
     //Paillier::decrypt(_pk._key, _ct._c);
     //}
 
 
-    pub fn fetch_ct(&self, _id: ObjectId) -> Option<hopeCT> {
-        None
+    /// Stored ciphertext with id _id and its current code
+    pub fn fetch_ct(&self, _id: ObjectId) -> Result<hopeCT<S::Ciphertext>, HopeError> {
+        let mut _ct = self._ids
+            .get(&_id)
+            .and_then(|_g| self._apl.get(_g))
+            .cloned()
+            .ok_or_else(|| HopeError::Storage(format!("no ciphertext {}", _id)))?;
+        self.refresh(&mut _ct)?;
+        Ok(_ct)
+    }
+
+    pub fn insert_ct(&mut self, _ct: hopeCT<S::Ciphertext>) -> Result<ObjectId, HopeError> {
+        let _id = _ct._id.clone();
+        self.insert_apl(_ct)?;
+        Ok(_id)
+    }
+
+    /// Inserts _elem in plaintext order, asking `_oracle` or else the
+    /// client over _ws. `RotationInProgress` while the stored ciphertexts
+    /// are under two keys.
+    pub fn insert_tree(&mut self, _elem: hopeLeaf<S::Ciphertext>) -> Result<(), HopeError> {
+        self.not_rotating()?;
+        match (&self._oracle, self._client) {
+            (Some(_oracle), _) => self._tree.insert_with(_elem, _oracle.as_ref()),
+            (None, Some(_client)) => {
+                let _oracle = _client(self.ws()?, self.enc_key()?);
+                self._tree.insert_with(_elem, _oracle.as_ref())
+            }
+            (None, None) => Err(HopeError::ClientUnavailable),
+        }
     }
 
-    pub fn insert_ct(&self, _ct: hopeCT) -> Option<ObjectId> {
-        None
+    /// `RotationInProgress` while the stored ciphertexts are under two keys,
+    /// neither their ciphertexts nor their codes can be combined until
+    /// `finish_rotation`
+    fn not_rotating(&self) -> Result<(), HopeError> {
+        if self._rotating {
+            return Err(HopeError::RotationInProgress);
+        }
+        Ok(())
     }
 
-    pub fn insert_tree(&mut self, _elem: hopeLeaf) {
-        self._tree.insert(_elem);
+    /// Websocket of the client, `ClientUnavailable` without one
+    fn ws(&self) -> Result<&'a HopeWebSocket, HopeError> {
+        self._ws.ok_or(HopeError::ClientUnavailable)
     }
 
-    pub fn update_tree(&self) {
+    /// Refreshes the codes of the leaves after an insert, the codes of
+    /// ciphertexts returned earlier are stale afterwards, see `lookup_tree`
+    pub fn update_tree(&mut self) -> Result<(), HopeError> {
         //self._tree.update_apl(&MONGO.collection(&self._coll))
+        self._tree.update_codes()
     }
 
-    pub fn lookup_tree(&self, _id: ObjectId) -> Option<u64> {
+    pub fn lookup_tree(&self, _id: ObjectId) -> Result<u64, HopeError> {
         self._tree.code(_id)
     }
 
-    pub fn lookup_apl(&self, _token: bn::G1) -> Option<hopeCT> {
-        match serde_json::to_string(&_token) {
-            Err(_) => return None,
-            Ok(_g) => {
-                match self._apl.get(&_g) {
-                    Some(_id) => return self.fetch_ct(_id.clone()),
-                    None => return None,
-                }
+    /// Plaintext order of _ct1 and _ct2 by their current codes in the tree,
+    /// `RotationInProgress` during a key rotation
+    pub fn compare(&self, _ct1: &hopeCT<S::Ciphertext>, _ct2: &hopeCT<S::Ciphertext>) -> Result<Ordering, HopeError> {
+        self.not_rotating()?;
+        Ok(self.lookup_tree(_ct1._id.clone())?.cmp(&self.lookup_tree(_ct2._id.clone())?))
+    }
+
+    /// Sets the code of _ct to its current code in the tree
+    pub fn refresh(&self, _ct: &mut hopeCT<S::Ciphertext>) -> Result<(), HopeError> {
+        _ct._o = self.lookup_tree(_ct._id.clone())?;
+        Ok(())
+    }
+
+    /// Sorts _cts in plaintext order, refreshing their codes,
+    /// `RotationInProgress` during a key rotation
+    pub fn sort(&self, _cts: &mut [hopeCT<S::Ciphertext>]) -> Result<(), HopeError> {
+        self.not_rotating()?;
+        for _ct in _cts.iter_mut() {
+            self.refresh(_ct)?;
+        }
+        _cts.sort_by_key(|_ct| _ct._o);
+        Ok(())
+    }
+
+    /// Ciphertext stored under _token with its current code, Ok(None) if
+    /// the token is unknown
+    pub fn lookup_apl(&self, _token: bn::G1) -> Result<Option<hopeCT<S::Ciphertext>>, HopeError> {
+        let _g = apl_key(_token)?;
+        match self._apl.get(&_g) {
+            Some(_ct) => {
+                let mut _ct = _ct.clone();
+                self.refresh(&mut _ct)?;
+                Ok(Some(_ct))
             }
+            None => Ok(None),
         }
     }
 
-    pub fn insert_apl(&mut self, _elem: hopeCT) -> Option<ObjectId> {
-        match serde_json::to_string(&_elem._g) {
-            Err(_) => return None,
-            Ok(_g) => self._apl.insert(_g, _elem._id),
+    /// Stores _elem under its token, `AplCollision` if the token belongs to
+    /// another ciphertext already
+    pub fn insert_apl(&mut self, _elem: hopeCT<S::Ciphertext>) -> Result<(), HopeError> {
+        let _g = apl_key(_elem._g)?;
+        match self._apl.get(&_g) {
+            Some(_existing) if _existing._id != _elem._id => Err(HopeError::AplCollision {
+                existing: _existing._id.clone(),
+                new: _elem._id,
+            }),
+            _ => {
+                self._ids.insert(_elem._id.clone(), _g.clone());
+                self._apl.insert(_g, _elem);
+                Ok(())
+            }
         }
     }
 
-    //pub fn lookup_ppl(&self, _token: Document) -> Option<hopeCT> {}
+    //pub fn lookup_ppl(&self, _token: Document) -> Option<hopeCT<S::Ciphertext>> {}
     // omitted
 
     //pub fn insert_ppl(&self, _elem: hopeCT) -> Option<InsertOneResult> {}
     // omitted
 
-    pub fn keys(&self) -> Option<hopeK> {
-        return self._key.clone();
+    pub fn keys(&self) -> Result<hopeK<S::EncryptionKey, S::DecryptionKey>, HopeError> {
+        self._key.clone().ok_or(HopeError::MissingKey)
+    }
+
+    pub fn enc_key(&self) -> Result<S::EncryptionKey, HopeError> {
+        match &self._key {
+            Some(_k) => Ok(_k._ek.clone()),
+            None => Err(HopeError::MissingKey),
+        }
+    }
+
+    pub fn dec_key(&self) -> Result<S::DecryptionKey, HopeError> {
+        match &self._key {
+            Some(_k) => _k._dk.clone().ok_or(HopeError::MissingDecryptionKey),
+            None => Err(HopeError::MissingKey),
+        }
+    }
+}
+
+impl hope<'_> {
+    /// Product of two ciphertexts, computed with the help of the client.
+    /// Both plaintexts must be below 2^PLAINTEXT_BITS in absolute value.
+    pub fn mul(&mut self, _ct1: &hopeCT, _ct2: &hopeCT) -> Result<hopeCT, HopeError> {
+        self.mul_with_rng(_ct1, _ct2, &mut OsRng)
+    }
+
+    /// `mul` with randomness drawn from rng
+    ///
+    /// The server adds random masks a and b to the plaintexts x and y, the
+    /// client returns an encryption of (x + a)(y + b) and the server removes
+    /// b*x + a*y + a*b homomorphically. The masks are integers of
+    /// PLAINTEXT_BITS + STATISTICAL_BITS bits, so the client learns nothing
+    /// about x and y beyond a statistically negligible amount.
+    pub fn mul_with_rng<R: RngCore + CryptoRng>(
+        &mut self,
+        _ct1: &hopeCT,
+        _ct2: &hopeCT,
+        rng: &mut R,
+    ) -> Result<hopeCT, HopeError> {
+        self.not_rotating()?;
+        let ek = self.enc_key()?;
+        let (_c, _g) = self.blinded_mul(&ek, _ct1, _ct2, rng)?;
+        self.insert_result(_c, _g)
     }
 
-    pub fn enc_key(&self) -> Option<PaillierEncryptionKey> {
-        if let Some(ref _k) = &self._key {
-            return Some(_k._ek.clone());
+    /// Truncated quotient of a ciphertext and a public divisor, computed
+    /// with the help of the client. `DivisionByZero` if _d is zero. The
+    /// plaintext and _d must be below 2^PLAINTEXT_BITS in absolute value.
+    /// There is no quotient of two ciphertexts, the client could not compute
+    /// it without learning the operands.
+    pub fn div_const(&mut self, _ct: &hopeCT, _d: &BigInt) -> Result<hopeCT, HopeError> {
+        self.div_const_with_rng(_ct, _d, &mut OsRng)
+    }
+
+    /// `div_const` with randomness drawn from rng
+    ///
+    /// The server adds a random mask a of PLAINTEXT_BITS + STATISTICAL_BITS
+    /// bits to x, so that z = x + a > 0, and the client returns encryptions
+    /// of z div d and z mod d. The server removes a div d, subtracts the
+    /// borrow if z mod d < a mod d and rounds towards zero for negative x,
+    /// deciding both with `greater_than`. The client learns d, the server
+    /// learns the sign of x.
+    pub fn div_const_with_rng<R: RngCore + CryptoRng>(
+        &mut self,
+        _ct: &hopeCT,
+        _d: &BigInt,
+        rng: &mut R,
+    ) -> Result<hopeCT, HopeError> {
+        if _d.is_zero() {
+            return Err(HopeError::DivisionByZero);
+        }
+        self.not_rotating()?;
+        let ek = self.enc_key()?;
+        let (_c, _g) = self.blinded_div_const(&ek, &_ct._c, _d, rng)?;
+        self.insert_result(_c, _g)
+    }
+
+    /// Paillier ciphertext and G1 token of the product, see `mul_with_rng`
+    fn blinded_mul<R: RngCore + CryptoRng>(
+        &self,
+        ek: &PaillierEncryptionKey,
+        _ct1: &hopeCT,
+        _ct2: &hopeCT,
+        rng: &mut R,
+    ) -> Result<(BigInt, G1), HopeError> {
+        let _bits = PLAINTEXT_BITS + STATISTICAL_BITS;
+        if ek.n.bits() <= 2 * (_bits + 1) {
+            return Err(HopeError::KeyTooSmall {
+                bits: ek.n.bits(),
+                required: 2 * (_bits + 1) + 1,
+            });
+        }
+        let _bound = BigInt::from(1) << _bits;
+        let _a = random_below_with_rng(&_bound, rng);
+        let _b = random_below_with_rng(&_bound, rng);
+        let _ca = Paillier::rerandomize_with_rng(ek, &Paillier::add_const(ek, &_ct1._c, &_a), rng);
+        let _cb = Paillier::rerandomize_with_rng(ek, &Paillier::add_const(ek, &_ct2._c, &_b), rng);
+        match self.ws()?.request(ProtocolOp::Mul { a: _ca, b: _cb })? {
+            ProtocolResult::Value { c, g } => {
+                let _ab = &_a * &_b;
+                let _c = Paillier::add(ek, &c, &Paillier::mul_const(ek, &_ct1._c, &-&_b));
+                let _c = Paillier::add(ek, &_c, &Paillier::mul_const(ek, &_ct2._c, &-&_a));
+                let _c = Paillier::add_const(ek, &_c, &-&_ab);
+                let _g = g - _ct1._g * hope::to_fr(&_b)? - _ct2._g * hope::to_fr(&_a)? - self._sp._p * hope::to_fr(&_ab)?;
+                Ok((Paillier::rerandomize_with_rng(ek, &_c, rng), _g))
+            }
+            res => Err(HopeError::from(res)),
+        }
+    }
+
+    /// Paillier ciphertext and G1 token of the quotient of the plaintext of
+    /// _c by _d, see `div_const_with_rng`
+    fn blinded_div_const<R: RngCore + CryptoRng>(
+        &self,
+        ek: &PaillierEncryptionKey,
+        _c: &BigInt,
+        _d: &BigInt,
+        rng: &mut R,
+    ) -> Result<(BigInt, G1), HopeError> {
+        let _required = PLAINTEXT_BITS + STATISTICAL_BITS + 4;
+        if ek.n.bits() < _required {
+            return Err(HopeError::KeyTooSmall {
+                bits: ek.n.bits(),
+                required: _required,
+            });
+        }
+        if _d.is_zero() {
+            return Err(HopeError::DivisionByZero);
+        }
+        if _d.bits() > PLAINTEXT_BITS {
+            return Err(HopeError::PlaintextOutOfRange(_d.to_string()));
+        }
+        // x / d = (-x) / (-d)
+        let (_cx, _d) = if _d.is_negative() {
+            (Paillier::neg(ek, _c)?, -_d)
+        } else {
+            (_c.clone(), _d.clone())
+        };
+        let _a = (BigInt::one() << PLAINTEXT_BITS)
+            + random_below_with_rng(&(BigInt::one() << (PLAINTEXT_BITS + STATISTICAL_BITS)), rng);
+        let _cz = Paillier::rerandomize_with_rng(ek, &Paillier::add_const(ek, &_cx, &_a), rng);
+        let (_cq, _gq, _cr) = match self.ws()?.request(ProtocolOp::DivConst { c: _cz, d: _d.clone() })? {
+            ProtocolResult::DivMod { q, g, r } => (q, g, r),
+            res => return Err(HopeError::from(res)),
+        };
+        let (_aq, _ar) = _a.div_mod_floor(&_d);
+        let _car = Paillier::encrypt_with_rng(ek, &_ar, rng);
+        let _czero = Paillier::encrypt_with_rng(ek, &BigInt::zero(), rng);
+        let _bits = self.ws()?.greater_than_batch_with_rng(ek, &[(&_car, &_cr), (&_cr, &_car), (&_czero, &_cx)], rng)?;
+        let (_borrow, _remainder, _negative) = (_bits[0], _bits[0] || _bits[1], _bits[2]);
+        // floor(x / d) = z div d - a div d - borrow, truncation rounds up
+        // negative quotients with a remainder
+        let mut _off = -_aq;
+        if _borrow {
+            _off -= 1;
         }
-        None
+        if _negative && _remainder {
+            _off += 1;
+        }
+        let _c = Paillier::rerandomize_with_rng(ek, &Paillier::add_const(ek, &_cq, &_off), rng);
+        Ok((_c, _gq + self._sp._p * hope::to_fr(&_off)?))
     }
 
-    pub fn dec_key(&self) -> Option<PaillierDecryptionKey> {
-        if let Some(ref _k) = &self._key {
-            return _k._dk.clone();
+    /// maps a signed plaintext to Fr, negative values to the additive inverse
+    pub fn to_fr(_m: &BigInt) -> Result<Fr, HopeError> {
+        match Fr::from_str(&_m.abs().to_string()) {
+            Some(_fr) => match _m.sign() {
+                Sign::Minus => Ok(-_fr),
+                _ => Ok(_fr),
+            },
+            None => Err(HopeError::PlaintextOutOfRange(_m.to_string())),
         }
-        None
     }
 }
 /*
@@ -386,4 +673,192 @@ mod tests {
 
         //        assert_eq!(_match.unwrap(), _plaintext);
     }
+
+    #[test]
+    fn signed_fr() {
+        let _five = Fr::from_str("5").unwrap();
+        assert!(hope::to_fr(&BigInt::from(5)) == Ok(_five));
+        assert!(hope::to_fr(&BigInt::from(-5)) == Ok(-_five));
+        assert!(hope::to_fr(&BigInt::from(-5)).unwrap() + _five == Fr::zero());
+    }
+
+    #[test]
+    fn client_assisted_mul_div_const() {
+        use crate::websocket::HopeClient;
+        use std::thread;
+        let p = (BigInt::from(1) << 521) - 1;
+        let q = (BigInt::from(1) << 607) - 1;
+        let (ek, dk) = Paillier::keygen_from_primes(&p, &q);
+        let (ws, end) = HopeWebSocket::connect();
+        let _hope: hope = hope::with_key(hopeSP::new(String::from("test")), &ws, hopeK::new(ek.clone(), dk.clone()));
+        let client = HopeClient::new(_hope.parameters(), ek.clone(), dk.clone());
+        let server = thread::spawn(move || end.serve(|req| client.handle(req)));
+        let _token = |m: &BigInt| _hope._sp._p * hope::to_fr(m).unwrap();
+        let _ct = |m: &BigInt| hopeCT::new(Paillier::encrypt(&ek, m), _token(m), Gt::one(), 0);
+        let x = BigInt::from(-1234567);
+        let y = BigInt::from(891);
+        let (_c, _g) = _hope.blinded_mul(&ek, &_ct(&x), &_ct(&y), &mut OsRng).unwrap();
+        assert_eq!(Paillier::decrypt(&dk, &ek, &_c), &x * &y);
+        assert!(_g == _token(&(&x * &y)));
+        for x in &[-1234567, -891 * 7, -5, 0, 5, 891 * 7, 1234567] {
+            for d in &[-891, -1, 1, 7, 891] {
+                let (x, d) = (BigInt::from(*x), BigInt::from(*d));
+                let (_c, _g) = _hope.blinded_div_const(&ek, &_ct(&x)._c, &d, &mut OsRng).unwrap();
+                assert_eq!(Paillier::decrypt(&dk, &ek, &_c), &x / &d);
+                assert!(_g == _token(&(&x / &d)));
+            }
+        }
+        assert_eq!(
+            _hope.blinded_div_const(&ek, &_ct(&x)._c, &BigInt::zero(), &mut OsRng).err(),
+            Some(HopeError::DivisionByZero)
+        );
+        drop(_hope);
+        drop(ws);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn elgamal_backend() {
+        use crate::elgamal::{ElGamal, ElGamalDecryptor};
+        use crate::websocket::{HopeClient, ELGAMAL_MASK_BITS};
+        use std::thread;
+        let p = (BigInt::from(1) << 521) - 1;
+        let q = (BigInt::from(1) << 607) - 1;
+        let (pek, pdk) = Paillier::keygen_from_primes(&p, &q);
+        let (ek, dk) = ElGamal::keygen();
+        let _eg = ElGamalDecryptor::new(dk, 1 << (ELGAMAL_MASK_BITS + 10)).unwrap();
+        let (ws, end) = HopeWebSocket::connect();
+        let mut _hope = hope::<ElGamal>::with_key(hopeSP::new(String::from("test")), &ws, hopeK::new(ek, _eg.clone()));
+        let client = HopeClient::new(_hope.parameters(), pek, pdk).with_elgamal(_eg.clone());
+        let server = thread::spawn(move || end.serve(|req| client.handle(req)));
+        for m in &[30, -10, 20, 0] {
+            let _c = ElGamal::encrypt(&ek, &BigInt::from(*m));
+            _hope.insert_tree(hopeLeaf::new(ObjectId::new().unwrap(), _c, 0)).unwrap();
+        }
+        let _cts = _hope._tree._root.clone().unwrap()._cts;
+        let ms: Vec<BigInt> = _cts
+            .iter()
+            .map(|l| {
+                let _ct = hopeCT::new(l._c, G1::one(), Gt::one(), 0);
+                _hope.decrypt(_ct, _hope.dec_key().unwrap(), ek).unwrap()
+            })
+            .collect();
+        assert_eq!(ms, vec![BigInt::from(-10), BigInt::from(0), BigInt::from(20), BigInt::from(30)]);
+        drop(_hope);
+        drop(ws);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn local_oracle() {
+        use crate::oracle::LocalOracle;
+        let (ek, dk) = crate::paillier::test_keys();
+        // no client is connected, the oracle answers every comparison
+        let ws = HopeWebSocket::new();
+        let mut _hope = hope::<Paillier>::with_key(hopeSP::new(String::from("test")), &ws, hopeK::new(ek.clone(), dk.clone()))
+            .with_oracle(LocalOracle::<Paillier>::new(ek.clone(), dk.clone()));
+        let mut _local = hope::<Paillier>::with_local_key(hopeSP::new(String::from("test")), hopeK::new(ek.clone(), dk.clone())).unwrap();
+        for m in &[30, -10, 20, 0] {
+            let _c = Paillier::encrypt(&ek, &BigInt::from(*m));
+            _hope.insert_tree(hopeLeaf::new(ObjectId::new().unwrap(), _c.clone(), 0)).unwrap();
+            _local.insert_tree(hopeLeaf::new(ObjectId::new().unwrap(), _c, 0)).unwrap();
+        }
+        for _tree in &[&_hope._tree, &_local._tree] {
+            let ms: Vec<BigInt> = _tree._root.clone().unwrap()._cts
+                .iter()
+                .map(|l| Paillier::decrypt(&dk, &ek, &l._c))
+                .collect();
+            assert_eq!(ms, vec![BigInt::from(-10), BigInt::from(0), BigInt::from(20), BigInt::from(30)]);
+        }
+        _hope._oracle = None;
+        let _c = Paillier::encrypt(&ek, &BigInt::from(5));
+        assert!(_hope.insert_tree(hopeLeaf::new(ObjectId::new().unwrap(), _c.clone(), 0)).is_err());
+        // without a websocket the client assisted operations fail
+        _local._oracle = None;
+        assert_eq!(
+            _local.insert_tree(hopeLeaf::new(ObjectId::new().unwrap(), _c, 0)).err(),
+            Some(HopeError::ClientUnavailable)
+        );
+        let (_ct1, _ct2) = (_local.encrypt(BigInt::from(6)), _local.encrypt(BigInt::from(3)));
+        assert!(_ct1.is_err() && _ct2.is_err());
+    }
+
+    #[test]
+    fn encrypt_orders_codes() {
+        use crate::oracle::LocalOracle;
+        let (ek, dk) = crate::paillier::test_keys();
+        let ws = HopeWebSocket::new();
+        let mut _hope = hope::<Paillier>::with_key(hopeSP::new(String::from("test")), &ws, hopeK::new(ek.clone(), dk.clone()))
+            .with_oracle(LocalOracle::<Paillier>::new(ek, dk));
+        let ms = [5, -3, 12, 0, 7, -20, 33, 1, 9, -8, 2, 40, -1];
+        let _cts: Vec<hopeCT> = ms.iter().map(|m| _hope.encrypt(BigInt::from(*m)).unwrap()).collect();
+        assert!(_hope._tree.depth() > 1);
+        let mut _codes: Vec<(u64, i32)> = _cts
+            .iter()
+            .zip(ms.iter())
+            .map(|(_ct, m)| (_hope.lookup_tree(_ct._id.clone()).unwrap(), *m))
+            .collect();
+        _codes.sort();
+        let mut sorted = ms.to_vec();
+        sorted.sort();
+        assert_eq!(_codes.iter().map(|(_, m)| *m).collect::<Vec<i32>>(), sorted);
+    }
+
+    #[test]
+    fn earlier_ciphertexts_stay_ordered() {
+        use crate::oracle::LocalOracle;
+        let (ek, dk) = crate::paillier::test_keys();
+        let ws = HopeWebSocket::new();
+        let mut _hope = hope::<Paillier>::with_key(hopeSP::new(String::from("test")), &ws, hopeK::new(ek.clone(), dk.clone()))
+            .with_oracle(LocalOracle::<Paillier>::new(ek, dk));
+        let ms = [10, 20, 30];
+        let mut _cts: Vec<hopeCT> = ms.iter().map(|m| _hope.encrypt(BigInt::from(*m)).unwrap()).collect();
+        let _before: Vec<u64> = _cts.iter().map(|_ct| _ct._o).collect();
+        for m in &[15, 25, 5, 35, 12, 22, 28, 1, 18] {
+            _hope.encrypt(BigInt::from(*m)).unwrap();
+        }
+        for (i, a) in _cts.iter().enumerate() {
+            for (j, b) in _cts.iter().enumerate() {
+                assert_eq!(_hope.compare(a, b), Ok(ms[i].cmp(&ms[j])));
+            }
+        }
+        // the returned codes are snapshots, the tree renumbered them
+        _cts.reverse();
+        _hope.sort(&mut _cts).unwrap();
+        let _after: Vec<u64> = _cts.iter().map(|_ct| _ct._o).collect();
+        assert_ne!(_before, _after);
+        assert!(_after.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(_hope.decrypt(_cts[0].clone(), _hope.dec_key().unwrap(), _hope.enc_key().unwrap()), Ok(BigInt::from(10)));
+    }
+
+    #[test]
+    fn repeated_plaintexts() {
+        use crate::oracle::LocalOracle;
+        let (ek, dk) = crate::paillier::test_keys();
+        let ws = HopeWebSocket::new();
+        let mut _hope = hope::<Paillier>::with_key(hopeSP::new(String::from("test")), &ws, hopeK::new(ek.clone(), dk.clone()))
+            .with_oracle(LocalOracle::<Paillier>::new(ek, dk));
+        let _ct1 = _hope.encrypt(BigInt::from(7)).unwrap();
+        _hope.encrypt(BigInt::from(3)).unwrap();
+        let _ct2 = _hope.encrypt(BigInt::from(7)).unwrap();
+        let _zero = _hope.encrypt(BigInt::from(0)).unwrap();
+        let _ct3 = _hope.add(&_ct1, &_zero).unwrap();
+        assert!(_ct1._id == _ct2._id && _ct1._id == _ct3._id);
+        assert_eq!(_ct3._o, _hope.lookup_tree(_ct1._id.clone()).unwrap());
+        assert_eq!(_hope._apl.len(), 3);
+        assert_eq!(_hope.fetch_ct(_ct1._id.clone()).unwrap()._o, _ct3._o);
+    }
+
+    #[test]
+    fn seeded_parameters() {
+        use rand_chacha::ChaChaRng;
+        use rand_core::SeedableRng;
+        let sp1 = hopeSP::new_with_rng(String::from("demo"), &mut ChaChaRng::seed_from_u64(1));
+        let sp2 = hopeSP::new_with_rng(String::from("demo"), &mut ChaChaRng::seed_from_u64(1));
+        let sp3 = hopeSP::new_with_rng(String::from("demo"), &mut ChaChaRng::seed_from_u64(2));
+        assert!(sp1._p == sp2._p && sp1._q == sp2._q);
+        assert!(sp1._p != sp3._p);
+        let mut rng = ChaChaRng::seed_from_u64(3);
+        assert!(Fr::random_with_rng(&mut rng) != Fr::random_with_rng(&mut rng));
+    }
 }
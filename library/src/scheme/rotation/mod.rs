@@ -0,0 +1,240 @@
+//! Rotation of the Paillier key of a hope instance.
+//!
+//! The server adds a random mask a to every stored ciphertext, the holder
+//! of the old decryption key decrypts x + a and encrypts it under the new
+//! key, and the server subtracts a again. The ids, G1 tokens and order codes
+//! of the ciphertexts do not change, neither does their position in the
+//! tree. Ciphertexts are rotated in batches of one request each, and the
+//! `KeyRotation` records how far the rotation got, so it can be persisted
+//! and resumed after a failed batch or a restart. While the ciphertexts are
+//! under two keys the tree cannot compare them and sums of them would mix
+//! keys, so inserts and operations on stored ciphertexts are refused until
+//! `finish_rotation`.
+//!
+//! The mask a is drawn below 2^(PLAINTEXT_BITS + STATISTICAL_BITS), it
+//! hides x and x + a does not wrap modulo n only if |x| < 2^PLAINTEXT_BITS.
+//! The client refuses to re-encrypt values outside the range this leaves,
+//! see `HopeClient::handle`.
+use ::hope::protocol::model::*;
+use ::hope::protocol::request::ProtocolOp;
+use ::hope::protocol::response::ProtocolResult;
+use crate::error::HopeError;
+use crate::paillier::{random_below_with_rng, Paillier};
+use num_bigint::BigInt;
+use num_traits::One;
+use rand_core::{CryptoRng, OsRng, RngCore};
+use serde_derive::{Deserialize, Serialize};
+use super::{apl_key, hope, PLAINTEXT_BITS, STATISTICAL_BITS};
+
+/// Progress of a key rotation
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeyRotation {
+    /// key the ciphertexts are rotated from
+    pub _old: PaillierEncryptionKey,
+    /// key the ciphertexts are rotated to
+    pub _new: PaillierEncryptionKey,
+    /// number of ciphertexts to rotate
+    pub _total: usize,
+    /// number of ciphertexts rotated so far
+    pub _done: usize,
+    /// number of ciphertexts per request
+    pub _batch: usize,
+}
+
+impl KeyRotation {
+    pub fn new(_old: PaillierEncryptionKey, _new: PaillierEncryptionKey, _total: usize, _batch: usize) -> KeyRotation {
+        KeyRotation {
+            _old,
+            _new,
+            _total,
+            _done: 0,
+            _batch: _batch.max(1),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self._done >= self._total
+    }
+}
+
+impl hope<'_> {
+    /// Starts rotating the _total stored ciphertexts to the key _new, inserts
+    /// and operations on them are refused until `finish_rotation`
+    pub fn start_rotation(
+        &mut self,
+        _new: PaillierEncryptionKey,
+        _total: usize,
+        _batch: usize,
+    ) -> Result<KeyRotation, HopeError> {
+        let _old = self.enc_key()?;
+        self._rotating = true;
+        Ok(KeyRotation::new(_old, _new, _total, _batch))
+    }
+
+    /// Rotates the next batch of _cts, which must list the stored
+    /// ciphertexts in the same order on every call, and updates their
+    /// leaves in the tree. Returns the number of rotated ciphertexts. If the
+    /// batch fails, _rot and _cts are unchanged, `TreeMiss` if a ciphertext
+    /// of the batch has no leaf. The plaintexts must be below
+    /// 2^PLAINTEXT_BITS in absolute value.
+    pub fn rotate_batch(&mut self, _rot: &mut KeyRotation, _cts: &mut [hopeCT]) -> Result<usize, HopeError> {
+        self.rotate_batch_with_rng(_rot, _cts, &mut OsRng)
+    }
+
+    /// `rotate_batch` with randomness drawn from rng
+    pub fn rotate_batch_with_rng<R: RngCore + CryptoRng>(
+        &mut self,
+        _rot: &mut KeyRotation,
+        _cts: &mut [hopeCT],
+        rng: &mut R,
+    ) -> Result<usize, HopeError> {
+        if _cts.len() != _rot._total {
+            return Err(HopeError::InvalidArgument(format!(
+                "{} ciphertexts, the rotation has {}",
+                _cts.len(),
+                _rot._total
+            )));
+        }
+        let _bits = PLAINTEXT_BITS + STATISTICAL_BITS;
+        let _min = _rot._old.n.bits().min(_rot._new.n.bits());
+        if _min <= _bits + 1 {
+            return Err(HopeError::KeyTooSmall {
+                bits: _min,
+                required: _bits + 2,
+            });
+        }
+        // a resumed rotation blocks inserts as well
+        self._rotating = true;
+        let _end = _rot._total.min(_rot._done + _rot._batch);
+        let _todo = &mut _cts[_rot._done.._end];
+        if _todo.is_empty() {
+            return Ok(0);
+        }
+        for _ct in _todo.iter() {
+            self._tree.leaf_mut(&_ct._id)?;
+        }
+        let _bound = BigInt::one() << _bits;
+        let _masks: Vec<BigInt> = _todo.iter().map(|_| random_below_with_rng(&_bound, rng)).collect();
+        let _blinded: Vec<BigInt> = _todo
+            .iter()
+            .zip(_masks.iter())
+            .map(|(_ct, _a)| {
+                Paillier::rerandomize_with_rng(&_rot._old, &Paillier::add_const(&_rot._old, &_ct._c, _a), rng)
+            })
+            .collect();
+        let _cs = match self.ws()?.request(ProtocolOp::Reencrypt {
+            ek: _rot._new.clone(),
+            cs: _blinded,
+        })? {
+            ProtocolResult::Ciphertexts(_cs) => _cs,
+            res => return Err(HopeError::from(res)),
+        };
+        if _cs.len() != _todo.len() {
+            return Err(HopeError::MalformedResponse(format!("{} of {} ciphertexts", _cs.len(), _todo.len())));
+        }
+        for ((_ct, _c), _a) in _todo.iter_mut().zip(_cs.iter()).zip(_masks.iter()) {
+            let _c = Paillier::add_const(&_rot._new, _c, &-_a);
+            _ct._c = Paillier::rerandomize_with_rng(&_rot._new, &_c, rng);
+            self._tree.leaf_mut(&_ct._id)?._c = _ct._c.clone();
+            if let Some(_stored) = self._apl.get_mut(&apl_key(_ct._g)?) {
+                _stored._c = _ct._c.clone();
+            }
+        }
+        _rot._done = _end;
+        Ok(_todo.len())
+    }
+
+    /// Switches to the new key once every ciphertext is rotated
+    pub fn finish_rotation(&mut self, _rot: &KeyRotation, _key: hopeK) -> Result<(), HopeError> {
+        if !_rot.is_done() {
+            return Err(HopeError::InvalidArgument(format!("{} of {} ciphertexts rotated", _rot._done, _rot._total)));
+        }
+        if _key._ek.n != _rot._new.n {
+            return Err(HopeError::InvalidArgument(String::from("not the key of the rotation")));
+        }
+        self._key = Some(_key);
+        self._rotating = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bplus::Tree;
+    use crate::oracle::LocalOracle;
+    use crate::websocket::{HopeClient, HopeWebSocket};
+    use bn::Gt;
+    use bson::oid::ObjectId;
+    use std::thread;
+
+    fn mersenne(e: usize) -> BigInt {
+        (BigInt::one() << e) - 1
+    }
+
+    #[test]
+    fn rotation_resumes_in_batches() {
+        let (old_ek, old_dk) = Paillier::keygen_from_primes(&mersenne(521), &mersenne(607));
+        let (new_ek, new_dk) = Paillier::keygen_from_primes(&mersenne(127), &mersenne(521));
+        let (ws, end) = HopeWebSocket::connect();
+        let mut _hope: hope = hope::with_key(hopeSP::new(String::from("test")), &ws, hopeK::new(old_ek.clone(), old_dk.clone()));
+        _hope._tree = Tree::new(8);
+        let _oracle = LocalOracle::<Paillier>::new(old_ek.clone(), old_dk.clone());
+        let client = HopeClient::new(_hope.parameters(), old_ek.clone(), old_dk);
+        let server = thread::spawn(move || end.serve(|req| client.handle(req)));
+        let ms: Vec<BigInt> = (0..7).map(|i| BigInt::from(i * 1000 - 3500)).collect();
+        let mut _cts: Vec<hopeCT> = ms
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                let _c = Paillier::encrypt(&old_ek, m);
+                let _ct = hopeCT::from_id(ObjectId::new().unwrap(), _c, _hope._sp._p, Gt::one(), i as u64);
+                _hope._tree.insert_with(hopeLeaf::new(_ct._id.clone(), _ct._c.clone(), _ct._o), &_oracle).unwrap();
+                _ct
+            })
+            .collect();
+        let mut _rot = _hope.start_rotation(new_ek.clone(), _cts.len(), 3).unwrap();
+        // a ciphertext without leaf fails the batch and changes nothing
+        let mut _unknown = _cts.clone();
+        _unknown[1]._id = ObjectId::new().unwrap();
+        assert_eq!(
+            _hope.rotate_batch(&mut _rot.clone(), &mut _unknown),
+            Err(HopeError::TreeMiss(_unknown[1]._id.clone()))
+        );
+        assert!(_unknown[0]._c == _cts[0]._c);
+        assert_eq!(_hope.rotate_batch(&mut _rot, &mut _cts), Ok(3));
+        let _c = Paillier::encrypt(&old_ek, &BigInt::from(1));
+        assert_eq!(
+            _hope.insert_tree(hopeLeaf::new(ObjectId::new().unwrap(), _c, 0)),
+            Err(HopeError::RotationInProgress)
+        );
+        // _cts[0] is under the new key, _cts[4] still under the old one
+        assert_eq!(_hope.add(&_cts[0], &_cts[4]).err(), Some(HopeError::RotationInProgress));
+        assert_eq!(_hope.sub(&_cts[0], &_cts[4]).err(), Some(HopeError::RotationInProgress));
+        assert_eq!(_hope.mul(&_cts[0], &_cts[4]).err(), Some(HopeError::RotationInProgress));
+        assert_eq!(_hope.div_const(&_cts[4], &BigInt::from(7)).err(), Some(HopeError::RotationInProgress));
+        assert_eq!(_hope.compare(&_cts[0], &_cts[4]), Err(HopeError::RotationInProgress));
+        // persist and resume
+        let json = serde_json::to_string(&_rot).unwrap();
+        let mut _rot: KeyRotation = serde_json::from_str(&json).unwrap();
+        assert!(_hope.finish_rotation(&_rot, hopeK::new(new_ek.clone(), new_dk.clone())).is_err());
+        assert!(_hope.rotate_batch(&mut _rot, &mut _cts[1..]).is_err());
+        assert_eq!(_hope.rotate_batch(&mut _rot, &mut _cts), Ok(3));
+        assert_eq!(_hope.rotate_batch(&mut _rot, &mut _cts), Ok(1));
+        assert_eq!(_hope.rotate_batch(&mut _rot, &mut _cts), Ok(0));
+        assert!(_rot.is_done());
+        _hope.finish_rotation(&_rot, hopeK::new(new_ek.clone(), new_dk.clone())).unwrap();
+        assert!(_hope.enc_key().unwrap().n == new_ek.n);
+        assert!(!_hope._rotating);
+        assert_eq!(_hope.compare(&_cts[0], &_cts[4]), Ok(std::cmp::Ordering::Less));
+        for (i, (_ct, m)) in _cts.iter().zip(ms.iter()).enumerate() {
+            assert_eq!(Paillier::decrypt(&new_dk, &new_ek, &_ct._c), *m);
+            assert_eq!(_ct._o, i as u64);
+            assert!(_hope._tree.leaf_mut(&_ct._id).unwrap()._c == _ct._c);
+        }
+        drop(_hope);
+        drop(ws);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,512 @@
+//! Channel between the server and the client that holds the decryption key.
+//!
+//! Client-assisted protocols of the scheme send a `ProtocolReq` through a
+//! `HopeWebSocket` and block until the `ProtocolRes` with the same id
+//! arrives. Messages travel as JSON text over a pair of std channels. The
+//! websocket actor of the backend owns the `HopeClientEnd` and moves the
+//! text frames between these channels and the browser. Native clients and
+//! tests answer the requests directly with `HopeClientEnd::serve`.
+use ::hope::protocol::model::*;
+use ::hope::protocol::request::*;
+use ::hope::protocol::response::*;
+use crate::error::HopeError;
+use crate::elgamal::{ElGamal, ElGamalCiphertext, ElGamalDecryptor, ElGamalEncryptionKey};
+use crate::homomorphic::AdditiveHomomorphic;
+use crate::paillier::{random_below_with_rng, Paillier};
+use crate::scheme::{hope, PLAINTEXT_BITS, STATISTICAL_BITS};
+use num_bigint::BigInt;
+use num_traits::{One, Signed};
+use rand_core::{CryptoRng, OsRng, RngCore};
+use std::cmp::Ordering as CmpOrdering;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the server waits for the client to answer a request
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// bit length of the masks in `greater_than_elgamal`, small enough for the
+/// client to decrypt the blinded difference
+pub const ELGAMAL_MASK_BITS: usize = 16;
+
+/// Server side of the channel to the client
+pub struct HopeWebSocket {
+    link: Option<Link>,
+    next_id: AtomicU64,
+    timeout: Duration,
+}
+
+struct Link {
+    outgoing: Mutex<Sender<String>>,
+    incoming: Mutex<Receiver<String>>,
+}
+
+/// Client side of the channel
+pub struct HopeClientEnd {
+    /// requests to forward to the client
+    pub outgoing: Receiver<String>,
+    /// responses received from the client
+    pub incoming: Sender<String>,
+}
+
+impl Default for HopeWebSocket {
+    fn default() -> Self {
+        HopeWebSocket::new()
+    }
+}
+
+impl HopeWebSocket {
+    /// A channel without client, every request fails
+    pub fn new() -> Self {
+        HopeWebSocket {
+            link: None,
+            next_id: AtomicU64::new(0),
+            timeout: REQUEST_TIMEOUT,
+        }
+    }
+
+    /// A connected channel and its client end
+    pub fn connect() -> (HopeWebSocket, HopeClientEnd) {
+        let (req_tx, req_rx) = channel();
+        let (res_tx, res_rx) = channel();
+        let ws = HopeWebSocket {
+            link: Some(Link {
+                outgoing: Mutex::new(req_tx),
+                incoming: Mutex::new(res_rx),
+            }),
+            next_id: AtomicU64::new(0),
+            timeout: REQUEST_TIMEOUT,
+        };
+        (ws, HopeClientEnd { outgoing: req_rx, incoming: res_tx })
+    }
+
+    /// Sets the time to wait for a response
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.link.is_some()
+    }
+
+    /// Sends op to the client and waits for the result. `ClientUnavailable`
+    /// if there is no client, `ClientTimeout` if it does not answer in time
+    /// and `MalformedResponse` if the answer is no response.
+    pub fn request(&self, op: ProtocolOp) -> Result<ProtocolResult, HopeError> {
+        let link = self.link.as_ref().ok_or(HopeError::ClientUnavailable)?;
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let req = serde_json::to_string(&ProtocolReq { id, op })?;
+        // the receiver is held for the whole round trip, so concurrent
+        // requests cannot take each other's responses
+        let incoming = link.incoming.lock().map_err(|_| HopeError::ClientUnavailable)?;
+        link.outgoing
+            .lock()
+            .map_err(|_| HopeError::ClientUnavailable)?
+            .send(req)
+            .map_err(|_| HopeError::ClientUnavailable)?;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let left = deadline
+                .checked_duration_since(Instant::now())
+                .ok_or(HopeError::ClientTimeout)?;
+            let text = incoming.recv_timeout(left).map_err(|e| match e {
+                RecvTimeoutError::Timeout => HopeError::ClientTimeout,
+                RecvTimeoutError::Disconnected => HopeError::ClientUnavailable,
+            })?;
+            let res = serde_json::from_str::<ProtocolRes>(&text)
+                .map_err(|e| HopeError::MalformedResponse(e.to_string()))?;
+            // skips late answers to requests that already timed out
+            if res.id == id {
+                return Ok(res.result);
+            }
+        }
+    }
+
+    /// Whether the plaintext of c1 is greater than the plaintext of c2.
+    /// Both plaintexts must be below 2^PLAINTEXT_BITS in absolute value.
+    pub fn greater_than(&self, ek: &PaillierEncryptionKey, c1: &BigInt, c2: &BigInt) -> Result<bool, HopeError> {
+        self.greater_than_with_rng(ek, c1, c2, &mut OsRng)
+    }
+
+    /// `greater_than` with randomness drawn from rng
+    ///
+    /// With d = x - y - 1 the client decrypts v = r*d + s for random
+    /// 0 <= s < r, which is non-negative exactly if x > y. With probability
+    /// 1/2 the server sends -v - 1 instead and flips the answer, so the
+    /// client learns neither the order nor the difference beyond its
+    /// magnitude, and the server learns only the comparison bit.
+    pub fn greater_than_with_rng<R: RngCore + CryptoRng>(
+        &self,
+        ek: &PaillierEncryptionKey,
+        c1: &BigInt,
+        c2: &BigInt,
+        rng: &mut R,
+    ) -> Result<bool, HopeError> {
+        let (_v, _flip) = blinded_difference(ek, c1, c2, rng)?;
+        match self.request(ProtocolOp::Compare { c: _v })? {
+            ProtocolResult::Bit(_b) => Ok(_b != _flip),
+            res => Err(HopeError::from(res)),
+        }
+    }
+
+    /// `greater_than` for every pair (c1, c2) of pairs, in one request
+    pub fn greater_than_batch(&self, ek: &PaillierEncryptionKey, pairs: &[(&BigInt, &BigInt)]) -> Result<Vec<bool>, HopeError> {
+        self.greater_than_batch_with_rng(ek, pairs, &mut OsRng)
+    }
+
+    /// `greater_than_batch` with randomness drawn from rng, every pair is
+    /// blinded like in `greater_than_with_rng`
+    pub fn greater_than_batch_with_rng<R: RngCore + CryptoRng>(
+        &self,
+        ek: &PaillierEncryptionKey,
+        pairs: &[(&BigInt, &BigInt)],
+        rng: &mut R,
+    ) -> Result<Vec<bool>, HopeError> {
+        if pairs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut _cs = Vec::with_capacity(pairs.len());
+        let mut _flips = Vec::with_capacity(pairs.len());
+        for (c1, c2) in pairs {
+            let (_v, _flip) = blinded_difference(ek, c1, c2, rng)?;
+            _cs.push(_v);
+            _flips.push(_flip);
+        }
+        match self.request(ProtocolOp::CompareBatch { cs: _cs })? {
+            ProtocolResult::Bits(_bs) if _bs.len() == _flips.len() => {
+                Ok(_bs.iter().zip(_flips.iter()).map(|(_b, _flip)| _b != _flip).collect())
+            }
+            ProtocolResult::Bits(_bs) => Err(HopeError::MalformedResponse(format!(
+                "{} bits for {} comparisons",
+                _bs.len(),
+                _flips.len()
+            ))),
+            res => Err(HopeError::from(res)),
+        }
+    }
+
+    /// Order of the plaintexts of c1 and c2, from two calls of `greater_than`
+    pub fn compare(&self, ek: &PaillierEncryptionKey, c1: &BigInt, c2: &BigInt) -> Result<CmpOrdering, HopeError> {
+        if self.greater_than(ek, c1, c2)? {
+            Ok(CmpOrdering::Greater)
+        } else if self.greater_than(ek, c2, c1)? {
+            Ok(CmpOrdering::Less)
+        } else {
+            Ok(CmpOrdering::Equal)
+        }
+    }
+
+    /// Whether the plaintext of c1 is greater than the plaintext of c2, for
+    /// EC-ElGamal ciphertexts
+    pub fn greater_than_elgamal(
+        &self,
+        ek: &ElGamalEncryptionKey,
+        c1: &ElGamalCiphertext,
+        c2: &ElGamalCiphertext,
+    ) -> Result<bool, HopeError> {
+        self.greater_than_elgamal_with_rng(ek, c1, c2, &mut OsRng)
+    }
+
+    /// `greater_than_elgamal` with randomness drawn from rng
+    ///
+    /// The blinding of `greater_than_with_rng` with masks r of
+    /// ELGAMAL_MASK_BITS bits. The client has to decrypt r*(x - y - 1) + s,
+    /// so its table must cover 2^ELGAMAL_MASK_BITS times the range of the
+    /// differences, and it learns the difference up to a factor of r.
+    pub fn greater_than_elgamal_with_rng<R: RngCore + CryptoRng>(
+        &self,
+        ek: &ElGamalEncryptionKey,
+        c1: &ElGamalCiphertext,
+        c2: &ElGamalCiphertext,
+        rng: &mut R,
+    ) -> Result<bool, HopeError> {
+        let _r = random_below_with_rng(&(BigInt::one() << ELGAMAL_MASK_BITS), rng) + 1;
+        let _s = random_below_with_rng(&_r, rng);
+        let _flip = rng.next_u32() & 1 == 1;
+        let _d = ElGamal::add_const(&ElGamal::sub(c1, c2), &-BigInt::one());
+        let mut _v = ElGamal::add_const(&ElGamal::mul_const(&_d, &_r), &_s);
+        if _flip {
+            _v = ElGamal::add_const(&ElGamal::neg(&_v), &-BigInt::one());
+        }
+        let _v = ElGamal::rerandomize_with_rng(ek, &_v, rng);
+        match self.request(ProtocolOp::CompareG1 { c1: _v.c1, c2: _v.c2 })? {
+            ProtocolResult::Bit(_b) => Ok(_b != _flip),
+            res => Err(HopeError::from(res)),
+        }
+    }
+}
+
+/// Encryption of r*(x - y - 1) + s, or of its negation minus one if the
+/// returned flip is set, see `HopeWebSocket::greater_than_with_rng`
+fn blinded_difference<R: RngCore + CryptoRng>(
+    ek: &PaillierEncryptionKey,
+    c1: &BigInt,
+    c2: &BigInt,
+    rng: &mut R,
+) -> Result<(BigInt, bool), HopeError> {
+    let _required = PLAINTEXT_BITS + STATISTICAL_BITS + 4;
+    if ek.n.bits() < _required {
+        return Err(HopeError::KeyTooSmall {
+            bits: ek.n.bits(),
+            required: _required,
+        });
+    }
+    let _r = random_below_with_rng(&(BigInt::one() << STATISTICAL_BITS), rng) + 1;
+    let _s = random_below_with_rng(&_r, rng);
+    let _flip = rng.next_u32() & 1 == 1;
+    let _d = Paillier::add_const(ek, &Paillier::sub(ek, c1, c2)?, &-BigInt::one());
+    let mut _v = Paillier::add_const(ek, &Paillier::mul_const(ek, &_d, &_r), &_s);
+    if _flip {
+        _v = Paillier::add_const(ek, &Paillier::neg(ek, &_v)?, &-BigInt::one());
+    }
+    Ok((Paillier::rerandomize_with_rng(ek, &_v, rng), _flip))
+}
+
+/// Schemes whose plaintexts the client compares over a `HopeWebSocket`
+pub trait ClientComparable: AdditiveHomomorphic {
+    /// Whether the plaintext of c1 is greater than the plaintext of c2
+    fn greater_than(
+        ws: &HopeWebSocket,
+        ek: &Self::EncryptionKey,
+        c1: &Self::Ciphertext,
+        c2: &Self::Ciphertext,
+    ) -> Result<bool, HopeError>;
+
+    /// `greater_than` for every pair (c1, c2) of pairs, one request per
+    /// pair unless the scheme has a batch request
+    fn greater_than_batch(
+        ws: &HopeWebSocket,
+        ek: &Self::EncryptionKey,
+        pairs: &[(&Self::Ciphertext, &Self::Ciphertext)],
+    ) -> Result<Vec<bool>, HopeError> {
+        pairs.iter().map(|(c1, c2)| Self::greater_than(ws, ek, c1, c2)).collect()
+    }
+}
+
+impl ClientComparable for Paillier {
+    fn greater_than(ws: &HopeWebSocket, ek: &PaillierEncryptionKey, c1: &BigInt, c2: &BigInt) -> Result<bool, HopeError> {
+        ws.greater_than(ek, c1, c2)
+    }
+
+    fn greater_than_batch(ws: &HopeWebSocket, ek: &PaillierEncryptionKey, pairs: &[(&BigInt, &BigInt)]) -> Result<Vec<bool>, HopeError> {
+        ws.greater_than_batch(ek, pairs)
+    }
+}
+
+impl ClientComparable for ElGamal {
+    fn greater_than(
+        ws: &HopeWebSocket,
+        ek: &ElGamalEncryptionKey,
+        c1: &ElGamalCiphertext,
+        c2: &ElGamalCiphertext,
+    ) -> Result<bool, HopeError> {
+        ws.greater_than_elgamal(ek, c1, c2)
+    }
+}
+
+impl HopeClientEnd {
+    /// Answers every request with handler until the server side is dropped
+    pub fn serve<F: FnMut(&ProtocolReq) -> ProtocolRes>(self, mut handler: F) {
+        for text in self.outgoing.iter() {
+            if let Ok(req) = serde_json::from_str::<ProtocolReq>(&text) {
+                if let Ok(res) = serde_json::to_string(&handler(&req)) {
+                    if self.incoming.send(res).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Client role of the client-assisted protocols
+pub struct HopeClient {
+    /// ehOPE System Parameters
+    pub _sp: hopeSP,
+    /// encryption key
+    pub _ek: PaillierEncryptionKey,
+    /// decryption key
+    pub _dk: PaillierDecryptionKey,
+    /// decryptor of EC-ElGamal columns
+    pub _eg: Option<ElGamalDecryptor>,
+}
+
+impl HopeClient {
+    pub fn new(_sp: hopeSP, _ek: PaillierEncryptionKey, _dk: PaillierDecryptionKey) -> HopeClient {
+        HopeClient {
+            _sp,
+            _ek,
+            _dk,
+            _eg: None,
+        }
+    }
+
+    /// Also answers comparisons of EC-ElGamal ciphertexts with _eg
+    pub fn with_elgamal(mut self, _eg: ElGamalDecryptor) -> HopeClient {
+        self._eg = Some(_eg);
+        self
+    }
+
+    /// Computes the response to _req
+    pub fn handle(&self, _req: &ProtocolReq) -> ProtocolRes {
+        self.handle_with_rng(_req, &mut OsRng)
+    }
+
+    /// `handle` with randomness drawn from rng
+    pub fn handle_with_rng<R: RngCore + CryptoRng>(&self, _req: &ProtocolReq, rng: &mut R) -> ProtocolRes {
+        let result = match &_req.op {
+            ProtocolOp::Mul { a, b } => {
+                let _x = Paillier::decrypt(&self._dk, &self._ek, a);
+                let _y = Paillier::decrypt(&self._dk, &self._ek, b);
+                self.value(&(_x * _y), rng)
+            }
+            ProtocolOp::DivConst { c, d } => {
+                let _z = Paillier::decrypt(&self._dk, &self._ek, c);
+                if !d.is_positive() || _z.is_negative() {
+                    ProtocolResult::Error(String::from("invalid blinded division"))
+                } else {
+                    match self.value(&(&_z / d), rng) {
+                        ProtocolResult::Value { c, g } => ProtocolResult::DivMod {
+                            q: c,
+                            g,
+                            r: Paillier::encrypt_with_rng(&self._ek, &(&_z % d), rng),
+                        },
+                        res => res,
+                    }
+                }
+            }
+            ProtocolOp::Compare { c } => {
+                ProtocolResult::Bit(!Paillier::decrypt(&self._dk, &self._ek, c).is_negative())
+            }
+            ProtocolOp::CompareBatch { cs } => ProtocolResult::Bits(
+                cs.iter()
+                    .map(|c| !Paillier::decrypt(&self._dk, &self._ek, c).is_negative())
+                    .collect(),
+            ),
+            ProtocolOp::Reencrypt { ek, cs } => {
+                // x + a with |x| < 2^PLAINTEXT_BITS and 0 <= a < 2^(PLAINTEXT_BITS + STATISTICAL_BITS)
+                let _low = -(BigInt::one() << PLAINTEXT_BITS);
+                let _high = (BigInt::one() << (PLAINTEXT_BITS + STATISTICAL_BITS)) - &_low;
+                let _ms: Vec<BigInt> = cs.iter().map(|c| Paillier::decrypt(&self._dk, &self._ek, c)).collect();
+                if _ms.iter().any(|_m| *_m <= _low || *_m >= _high) {
+                    ProtocolResult::Error(String::from("plaintext exceeds PLAINTEXT_BITS"))
+                } else {
+                    ProtocolResult::Ciphertexts(_ms.iter().map(|_m| Paillier::encrypt_with_rng(ek, _m, rng)).collect())
+                }
+            }
+            ProtocolOp::CompareG1 { c1, c2 } => {
+                let _c = ElGamalCiphertext { c1: *c1, c2: *c2 };
+                match self._eg.as_ref().and_then(|_eg| _eg.decrypt(&_c)) {
+                    Some(_v) => ProtocolResult::Bit(!_v.is_negative()),
+                    None => ProtocolResult::Error(String::from("cannot decrypt")),
+                }
+            }
+        };
+        ProtocolRes {
+            id: _req.id,
+            result,
+        }
+    }
+
+    fn value<R: RngCore + CryptoRng>(&self, _m: &BigInt, rng: &mut R) -> ProtocolResult {
+        match hope::to_fr(_m) {
+            Ok(_fr) => ProtocolResult::Value {
+                c: Paillier::encrypt_with_rng(&self._ek, _m, rng),
+                g: self._sp._p * _fr,
+            },
+            Err(e) => ProtocolResult::Error(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::paillier::test_keys;
+    use std::thread;
+
+    fn test_client() -> HopeClient {
+        let (ek, dk) = test_keys();
+        HopeClient::new(hopeSP::new(String::from("test")), ek, dk)
+    }
+
+    #[test]
+    fn websocket_roundtrip() {
+        let client = test_client();
+        let ek = client._ek.clone();
+        let dk = client._dk.clone();
+        let (ws, end) = HopeWebSocket::connect();
+        let server = thread::spawn(move || end.serve(|req| client.handle(req)));
+        let a = Paillier::encrypt(&ek, &BigInt::from(-6));
+        let b = Paillier::encrypt(&ek, &BigInt::from(7));
+        match ws.request(ProtocolOp::Mul { a, b }) {
+            Ok(ProtocolResult::Value { c, .. }) => assert_eq!(Paillier::decrypt(&dk, &ek, &c), BigInt::from(-42)),
+            _ => panic!("no product"),
+        }
+        drop(ws);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn websocket_compare() {
+        let p = (BigInt::from(1) << 521) - 1;
+        let q = (BigInt::from(1) << 607) - 1;
+        let (ek, dk) = Paillier::keygen_from_primes(&p, &q);
+        let client = HopeClient::new(hopeSP::new(String::from("test")), ek.clone(), dk);
+        let (ws, end) = HopeWebSocket::connect();
+        let server = thread::spawn(move || end.serve(|req| client.handle(req)));
+        let big: BigInt = (BigInt::one() << PLAINTEXT_BITS) - 1;
+        let ms = [-big.clone(), BigInt::from(-5), BigInt::from(-1), BigInt::from(0), BigInt::from(1), BigInt::from(2), big];
+        let cs: Vec<BigInt> = ms.iter().map(|m| Paillier::encrypt(&ek, m)).collect();
+        for i in 0..ms.len() {
+            for j in 0..ms.len() {
+                assert_eq!(ws.greater_than(&ek, &cs[i], &cs[j]), Ok(ms[i] > ms[j]));
+            }
+        }
+        assert_eq!(ws.compare(&ek, &cs[1], &Paillier::encrypt(&ek, &BigInt::from(-5))), Ok(CmpOrdering::Equal));
+        assert_eq!(ws.compare(&ek, &cs[1], &cs[4]), Ok(CmpOrdering::Less));
+        drop(ws);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reencrypt_checks_plaintext_bound() {
+        let p = (BigInt::from(1) << 521) - 1;
+        let q = (BigInt::from(1) << 607) - 1;
+        let (ek, dk) = Paillier::keygen_from_primes(&p, &q);
+        let client = HopeClient::new(hopeSP::new(String::from("test")), ek.clone(), dk.clone());
+        let reencrypt = |m: BigInt| {
+            let op = ProtocolOp::Reencrypt { ek: ek.clone(), cs: vec![Paillier::encrypt(&ek, &m)] };
+            client.handle(&ProtocolReq { id: 0, op }).result
+        };
+        let _bound = BigInt::one() << (PLAINTEXT_BITS + STATISTICAL_BITS);
+        match reencrypt(&_bound - 1) {
+            ProtocolResult::Ciphertexts(cs) => assert_eq!(Paillier::decrypt(&dk, &ek, &cs[0]), &_bound - 1),
+            _ => panic!("no ciphertexts"),
+        }
+        match reencrypt(&_bound << 1usize) {
+            ProtocolResult::Error(_) => {}
+            _ => panic!("unbounded plaintext re-encrypted"),
+        }
+    }
+
+    #[test]
+    fn websocket_unconnected() {
+        let ws = HopeWebSocket::new();
+        assert!(!ws.is_connected());
+        let op = || ProtocolOp::Mul { a: BigInt::from(1), b: BigInt::from(1) };
+        assert_eq!(ws.request(op()).err(), Some(HopeError::ClientUnavailable));
+        let (ws, end) = HopeWebSocket::connect();
+        let ws = ws.with_timeout(Duration::from_millis(10));
+        assert_eq!(ws.request(op()).err(), Some(HopeError::ClientTimeout));
+        end.incoming.send(String::from("garbage")).unwrap();
+        match ws.request(op()) {
+            Err(HopeError::MalformedResponse(_)) => {}
+            _ => panic!("malformed response accepted"),
+        }
+        drop(end);
+        assert_eq!(ws.request(op()).err(), Some(HopeError::ClientUnavailable));
+    }
+}
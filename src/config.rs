@@ -15,6 +15,12 @@ pub struct Config {
     pub log: LogConfig,
     /// The database configuration
     pub mongodb: MongoConfig,
+    /// The randomness pool configuration
+    #[serde(default)]
+    pub pool: PoolConfig,
+    /// The key generation configuration
+    #[serde(default)]
+    pub keygen: KeygenConfig,
 }
 
 impl Config {
@@ -71,3 +77,59 @@ pub struct MongoConfig {
     pub database: String,
 }
 
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// The configuration of the pool of precomputed Paillier randomness
+pub struct PoolConfig {
+    /// The number of precomputed values kept per key
+    pub size: usize,
+    /// The number of background threads refilling the pool
+    pub threads: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            size: 1024,
+            threads: 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// The kind of primes used for Paillier moduli
+pub enum PrimeType {
+    /// Uniformly random primes
+    Random,
+    /// Primes p where p-1 has a large prime factor
+    Strong,
+    /// Primes p where (p-1)/2 is prime
+    Safe,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// The key generation configuration
+pub struct KeygenConfig {
+    /// The size of generated moduli in bits
+    pub key_size: usize,
+    /// The smallest modulus size that is accepted
+    pub min_key_size: usize,
+    /// The number of Miller-Rabin rounds per prime
+    pub rounds: usize,
+    /// The kind of primes
+    pub prime_type: PrimeType,
+}
+
+impl Default for KeygenConfig {
+    fn default() -> Self {
+        KeygenConfig {
+            key_size: 2048,
+            min_key_size: 2048,
+            rounds: 40,
+            prime_type: PrimeType::Strong,
+        }
+    }
+}
@@ -1,37 +1,40 @@
 use serde::{Deserialize, Serialize};
 use bn::*;
-use mongodb::{oid::ObjectId, coll::Collection};
-use bson::*;
-use std::ops::Shl;
+use bson::oid::ObjectId;
+#[cfg(feature = "mongodb")]
+use mongodb::coll::Collection;
+#[cfg(feature = "mongodb")]
+use bson::doc;
 use std::cmp::Ordering;
 use std::marker::PhantomData;
 use num_bigint::*;
+use rand_core::{CryptoRng, OsRng, RngCore};
 
 
 /// ehOPE Node of T
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct hopeNode {
+pub struct hopeNode<C = BigInt> {
     /// node id
     pub _id: ObjectId,
-    /// degree of the node
+    /// degree of the node, the largest number of keys it holds
     pub _degree: usize,
     /// current filling of the node
     pub _num_cts: usize,
     /// type of this node: leaf or inner
     pub _is_leaf: bool,
     /// vector of child nodes
-    pub _children: Vec<hopeNode>,
+    pub _children: Vec<hopeNode<C>>,
     /// vector of child leafs
-    pub _cts: Vec<hopeLeaf>,
+    pub _cts: Vec<hopeLeaf<C>>,
 }
 
 /// ehOPE Leaf of T
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct hopeLeaf {
+pub struct hopeLeaf<C = BigInt> {
     /// node id
     pub _id: ObjectId,
-    /// paillier ciphertext
-    pub _c: BigInt,
+    /// homomorphic (by default paillier) ciphertext
+    pub _c: C,
     /// B^+ code
     pub _o: u64,
 }
@@ -51,16 +54,16 @@ pub struct hopeSP {
 
 /// ehOPE Ciphertext (CT)
 #[derive(Serialize, Deserialize, Clone)]
-pub struct hopeCT {
+pub struct hopeCT<C = BigInt> {
     /// id of the CT
     pub _id: ObjectId,
-    /// paillier ciphertext
-    pub _c: BigInt,
+    /// homomorphic (by default paillier) ciphertext
+    pub _c: C,
     /// G element
     pub _g: G1,
     /// H element
     pub _h: Gt,
-    /// B^+ code
+    /// B^+ code when the CT was returned, inserts may renumber the codes
     pub _o: u64,
 }
 
@@ -69,6 +72,28 @@ pub struct hopeCT {
 pub struct PaillierDecryptionKey {
     pub lambda: BigInt,
     pub mu: BigInt,
+    /// factors of n and CRT constants, missing in keys from older releases
+    #[serde(default)]
+    pub crt: Option<PaillierCrtKey>,
+}
+
+/// Factors of a PAILLIER modulus n = p*q and the constants derived from them
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PaillierCrtKey {
+    /// first prime factor of n
+    pub p: BigInt,
+    /// second prime factor of n
+    pub q: BigInt,
+    /// p^2
+    pub p2: BigInt,
+    /// q^2
+    pub q2: BigInt,
+    /// L_p(g^(p-1) mod p^2)^-1 mod p
+    pub hp: BigInt,
+    /// L_q(g^(q-1) mod q^2)^-1 mod q
+    pub hq: BigInt,
+    /// q^-1 mod p
+    pub qinv: BigInt,
 }
 
 /// A ehOPE PAILLIER KEY PAIR (EK/DK)
@@ -79,20 +104,47 @@ pub struct PaillierEncryptionKey {
     pub g: BigInt,
 }
 
+/// A DAMGARD-JURIK DECRYPTION KEY (DK)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DamgardJurikDecryptionKey {
+    /// lcm(p-1, q-1) or a multiple of it
+    pub lambda: BigInt,
+    /// lambda^-1 mod n^s
+    pub mu: BigInt,
+}
+
+/// A DAMGARD-JURIK ENCRYPTION KEY (EK)
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DamgardJurikEncryptionKey {
+    /// exponent s, plaintexts live in Z_(n^s)
+    pub s: usize,
+    /// RSA modulus n = p*q
+    pub n: BigInt,
+    /// plaintext modulus n^s
+    pub ns: BigInt,
+    /// ciphertext modulus n^(s+1)
+    pub ns1: BigInt,
+}
+
 /// A ehOPE PAILLIER KEY PAIR (EK/DK)
 #[derive(Serialize, Deserialize, Clone)]
-pub struct hopeK {
+pub struct hopeK<E = PaillierEncryptionKey, D = PaillierDecryptionKey> {
     /// the decryption key
-    pub _dk: Option<PaillierDecryptionKey>,
+    pub _dk: Option<D>,
     /// the encryption key
-    pub _ek: PaillierEncryptionKey,
+    pub _ek: E,
 }
 
 impl hopeSP {
     pub fn new(_name: String) -> hopeSP {
+        hopeSP::new_with_rng(_name, &mut OsRng)
+    }
+
+    /// `new` with generators drawn from rng
+    pub fn new_with_rng<R: RngCore + CryptoRng>(_name: String, rng: &mut R) -> hopeSP {
         // return SP
-        let g1: G1 = G1::random();
-        let g2: G2 = G2::random();
+        let g1: G1 = G1::random_with_rng(rng);
+        let g2: G2 = G2::random_with_rng(rng);
         hopeSP {
             _id: ObjectId::new().unwrap(),
             _name: _name,
@@ -102,8 +154,8 @@ impl hopeSP {
     }
 }
 
-impl hopeK {
-    pub fn new(ek: PaillierEncryptionKey, dk: PaillierDecryptionKey) -> hopeK {
+impl<E, D> hopeK<E, D> {
+    pub fn new(ek: E, dk: D) -> hopeK<E, D> {
         // return hopeKey
         hopeK {
             _dk: Some(dk),
@@ -112,8 +164,8 @@ impl hopeK {
     }
 }
 
-impl hopeCT {
-    pub fn clone(_id: ObjectId, _c: BigInt, _g: G1, _h: Gt, _o: u64) -> hopeCT {
+impl<C> hopeCT<C> {
+    pub fn clone(_id: ObjectId, _c: C, _g: G1, _h: Gt, _o: u64) -> hopeCT<C> {
         hopeCT {
             _id: _id,
             _c: _c,
@@ -123,7 +175,7 @@ impl hopeCT {
         }
     }
 
-    pub fn from_id(_id: ObjectId, _c: BigInt, _g: G1, _h: Gt, _o: u64) -> hopeCT {
+    pub fn from_id(_id: ObjectId, _c: C, _g: G1, _h: Gt, _o: u64) -> hopeCT<C> {
         hopeCT {
             _id: _id,
             _c: _c,
@@ -133,7 +185,7 @@ impl hopeCT {
         }
     }
 
-    pub fn new(_c: BigInt, _g: G1, _h: Gt, _o: u64) -> hopeCT {
+    pub fn new(_c: C, _g: G1, _h: Gt, _o: u64) -> hopeCT<C> {
         hopeCT {
             _id: ObjectId::new().unwrap(),
             _c: _c,
@@ -144,28 +196,30 @@ impl hopeCT {
     }
 }
 
-impl Ord for hopeCT {
+/// Orders by the code cached in `_o`, which inserts may have renumbered
+/// since. `hope::compare` orders by the current codes.
+impl<C> Ord for hopeCT<C> {
     fn cmp(&self, other: &Self) -> Ordering {
         self._o.cmp(&other._o)
     }
 }
 
-impl PartialOrd for hopeCT {
+impl<C> PartialOrd for hopeCT<C> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl PartialEq for hopeCT {
+impl<C> PartialEq for hopeCT<C> {
     fn eq(&self, other: &Self) -> bool {
         self._o == other._o
     }
 }
 
-impl Eq for hopeCT {}
+impl<C> Eq for hopeCT<C> {}
 
-impl hopeNode {
-    pub fn new(_degree: usize, _is_leaf: bool) -> hopeNode {
+impl<C: Clone> hopeNode<C> {
+    pub fn new(_degree: usize, _is_leaf: bool) -> hopeNode<C> {
         if _is_leaf {
             hopeNode {
                 _id: ObjectId::new().unwrap(),
@@ -178,10 +232,10 @@ impl hopeNode {
         } else {
             hopeNode {
                 _id: ObjectId::new().unwrap(),
-                _degree: _degree - 1,
+                _degree: _degree,
                 _num_cts: 0,
                 _is_leaf: false,
-                _children: Vec::with_capacity(_degree - 1),
+                _children: Vec::with_capacity(_degree + 1),
                 _cts: Vec::with_capacity(_degree),
             }
         }
@@ -191,7 +245,9 @@ impl hopeNode {
         self._degree
     }
 
-    pub fn insert_key(&mut self, _key: hopeLeaf) -> usize {
+    /// Appends _key after the keys of this node, without comparisons
+    #[deprecated(note = "does not keep the plaintext order, use `insert_key_by`")]
+    pub fn insert_key(&mut self, _key: hopeLeaf<C>) -> usize {
         let mut i = 0;
         while i < self._num_cts {
             //&& super::super::ask_client(key > self._cts[i]) {
@@ -202,7 +258,33 @@ impl hopeNode {
         i
     }
 
-    pub fn remove_key(&mut self, _key: hopeLeaf) -> hopeLeaf {
+    /// Position of the first key that _key is not greater than, using the
+    /// comparison _gt(a, b) = a > b. Stops at the first failed comparison.
+    pub fn position_by<F, E>(&self, _key: &hopeLeaf<C>, _gt: &mut F) -> Result<usize, E>
+    where
+        F: FnMut(&hopeLeaf<C>, &hopeLeaf<C>) -> Result<bool, E>,
+    {
+        let mut i = 0;
+        while i < self._num_cts && _gt(_key, &self._cts[i])? {
+            i += 1;
+        }
+        Ok(i)
+    }
+
+    /// Inserts _key in order, see `position_by`
+    pub fn insert_key_by<F, E>(&mut self, _key: hopeLeaf<C>, _gt: &mut F) -> Result<usize, E>
+    where
+        F: FnMut(&hopeLeaf<C>, &hopeLeaf<C>) -> Result<bool, E>,
+    {
+        let i = self.position_by(&_key, _gt)?;
+        self._num_cts += 1;
+        self._cts.insert(i, _key);
+        Ok(i)
+    }
+
+    /// Removes _key from this node
+    #[deprecated(note = "does not touch the children, remove keys through the tree")]
+    pub fn remove_key(&mut self, _key: hopeLeaf<C>) -> hopeLeaf<C> {
         let mut i = 0;
         while i < self._num_cts && self._cts[i] != _key {
             i += 1;
@@ -216,100 +298,115 @@ impl hopeNode {
     }
 
     pub fn is_full(&self) -> bool {
-        if self.is_leaf() {
-            self._cts.len() == self._degree
-        } else {
-            self._children.len() == self._degree
-        }
+        self._cts.len() >= self._degree
     }
 
-    pub fn split(self) -> (hopeLeaf, hopeNode, hopeNode) {
-        let _key: hopeLeaf = self._cts[self._degree - 1].clone();
-        let mut left = hopeNode::new(self._degree, self._is_leaf);
+    /// Splits a full node at its median key. Returns the median, the node
+    /// with the keys and children left of it and a new node with those
+    /// right of it.
+    pub fn split(mut self) -> (hopeLeaf<C>, hopeNode<C>, hopeNode<C>) {
+        let m = self._cts.len() / 2;
         let mut right = hopeNode::new(self._degree, self._is_leaf);
-
-        for (index, k) in self._cts.iter().enumerate() {
-            if index < self._degree - 1 {
-                left.insert_key(k.clone());
-            } else if index > self._degree - 1 {
-                right.insert_key(k.clone());
-            }
+        right._cts = self._cts.split_off(m + 1);
+        right._num_cts = right._cts.len();
+        if !self._is_leaf {
+            right._children = self._children.split_off(m + 1);
         }
-        let mut index = 0;
-        for child in self._children {
-            if index < self._degree {
-                left._children.push(child);
-            } else {
-                right._children.push(child);
-            }
-            index += 1;
+        let _key = self._cts.remove(m);
+        self._num_cts = self._cts.len();
+        (_key, self, right)
+    }
+
+    /// Node of this subtree holding the key with the id of _key, found
+    /// without comparisons by visiting every node
+    pub fn search(&self, _key: hopeLeaf<C>) -> Option<&hopeNode<C>> {
+        if self._cts.iter().any(|l| l._id == _key._id) {
+            return Some(self);
         }
-        return (_key, left, right);
+        self._children.iter().find_map(|c| c.search(_key.clone()))
     }
 
-    pub fn search(&self, _key: hopeLeaf) -> Option<&hopeNode> {
-        // Find the first key greater than or equal to k
-        let mut i = 0;
-        while i < self._num_cts {
-            //&& super::super::ask_client(key > self._cts[i]) {
-            // If key is on this node, return the node
-            if self._cts[i] == _key {
-                return Some(self);
-            }
-            i += 1;
+    /// Node containing _key, descending by the comparison _gt(a, b) = a > b
+    /// Ok(None) if it is not in the tree.
+    pub fn search_by<F, E>(&self, _key: &hopeLeaf<C>, _gt: &mut F) -> Result<Option<&hopeNode<C>>, E>
+    where
+        F: FnMut(&hopeLeaf<C>, &hopeLeaf<C>) -> Result<bool, E>,
+    {
+        let i = self.position_by(_key, _gt)?;
+        if i < self._num_cts && self._cts[i]._id == _key._id {
+            return Ok(Some(self));
+        }
+        match self._children.get(i) {
+            Some(_child) if !self._is_leaf => _child.search_by(_key, _gt),
+            _ => Ok(None),
         }
-        if self._is_leaf == true {
+    }
+
+    /// mOPE code of the key with id _key in this subtree. The code has one
+    /// digit of _bits bits per level, 2i for the child i and 2i + 1 for the
+    /// key i on the path from the root, padded with zero digits to _levels
+    /// digits, so the codes of a subtree sort like its in-order traversal.
+    /// _code holds the digits above this node. None if the key is not in
+    /// the subtree or the path is longer than _levels.
+    pub fn code(&self, _code: u64, _bits: u32, _levels: u32, _key: &ObjectId) -> Option<u64> {
+        if _levels == 0 {
             return None;
         }
-        return self._children[i].search(_key);
+        if let Some(i) = self._cts.iter().position(|l| l._id == *_key) {
+            return Some(((_code << _bits) + 2 * i as u64 + 1) << (_bits * (_levels - 1)));
+        }
+        self._children
+            .iter()
+            .enumerate()
+            .find_map(|(i, c)| c.code((_code << _bits) + 2 * i as u64, _bits, _levels - 1, _key))
     }
 
-    pub fn code(&self, _code: u64, _key: ObjectId) -> Option<u64> {
-        // Find the first key greater than or equal to k
-        let mut i = 0;
-        while i < self._num_cts {
-            //&& super::super::ask_client(key > self._cts[i]) {
+    /// Sets `_o` of every key of this subtree to its code, see `code`.
+    /// false if the subtree is deeper than _levels.
+    pub fn update_codes(&mut self, _code: u64, _bits: u32, _levels: u32) -> bool {
+        self.update_codes_from(_code, _bits, _levels, 0, &mut |_| {})
+    }
 
-            // If key is on this node, return the node
-            if self._cts[i]._id == _key {
-                let _code = (_code + u64::from(i as u32)).shl(self.capacity());
-                Some(_code);
-            }
-            i += 1;
+    /// Sets `_o` of the keys from index _from on and of the subtrees of the
+    /// children from index _from on, see `update_codes`. _set is called with
+    /// every renumbered key.
+    pub fn update_codes_from<F>(&mut self, _code: u64, _bits: u32, _levels: u32, _from: usize, _set: &mut F) -> bool
+    where
+        F: FnMut(&hopeLeaf<C>),
+    {
+        if _levels == 0 {
+            return false;
         }
-        if self._is_leaf == true {
-            return None;
+        for (i, l) in self._cts.iter_mut().enumerate().skip(_from) {
+            l._o = ((_code << _bits) + 2 * i as u64 + 1) << (_bits * (_levels - 1));
+            _set(l);
         }
-        return self._children[i].code((_code + u64::from(i as u32)).shl(self.capacity()), _key);
+        self._children
+            .iter_mut()
+            .enumerate()
+            .skip(_from)
+            .all(|(i, c)| c.update_codes_from((_code << _bits) + 2 * i as u64, _bits, _levels - 1, 0, _set))
     }
 
-    pub fn update_apl(&self, _code: u64, _coll: &Collection) {
-        let mut i = 0;
-        if self._num_cts == 0 {
-            while i < self._children.len() {
-                let _current_code = (_code + u64::from(i as u32)).shl(self.capacity());
-                self._children[i].update_apl(_current_code, _coll);
-                i += 1;
-            }
-        } else {
-            i = 0;
-            while i < self._num_cts {
-                _coll
-	                .update_one(
-	                    doc!{"_id" => self._cts[i]._id.clone()},
-	                    
-	                    doc!{"$set" => {"_o" => (_code + u64::from(i as u32)).shl(self.capacity())}},
-	                        None,
-                    )
-                    .unwrap();
-	            i += 1;
-            }
+    /// Writes `_o` of every key of this subtree to its document in _coll,
+    /// the codes are the ones set by `update_codes`
+    #[cfg(feature = "mongodb")]
+    pub fn update_apl(&self, _coll: &Collection) -> Result<(), mongodb::Error> {
+        self.update_apl_from(_coll, 0)
+    }
+
+    /// Writes the codes renumbered by `update_codes_from` with _from to _coll
+    #[cfg(feature = "mongodb")]
+    pub fn update_apl_from(&self, _coll: &Collection, _from: usize) -> Result<(), mongodb::Error> {
+        for _l in self._cts.iter().skip(_from) {
+            _coll.update_one(doc!{"_id" => _l._id.clone()}, doc!{"$set" => {"_o" => _l._o}}, None)?;
         }
+        self._children.iter().skip(_from).try_for_each(|c| c.update_apl(_coll))
     }
 }
 
-impl hopeLeaf {
-    pub fn new(_id: ObjectId, _c: BigInt, _o: u64) -> hopeLeaf {
+impl<C> hopeLeaf<C> {
+    pub fn new(_id: ObjectId, _c: C, _o: u64) -> hopeLeaf<C> {
         hopeLeaf {
             _id: _id,
             _c: _c,
@@ -318,28 +415,28 @@ impl hopeLeaf {
     }
 }
 
-impl Ord for hopeLeaf {
+impl<C> Ord for hopeLeaf<C> {
     fn cmp(&self, other: &Self) -> Ordering {
         self._o.cmp(&other._o)
     }
 }
 
-impl PartialOrd for hopeLeaf {
+impl<C> PartialOrd for hopeLeaf<C> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl PartialEq for hopeLeaf {
+impl<C> PartialEq for hopeLeaf<C> {
     fn eq(&self, other: &Self) -> bool {
         self._o == other._o
     }
 }
 
-impl Eq for hopeLeaf {}
+impl<C> Eq for hopeLeaf<C> {}
 
 
-impl std::fmt::Display for hopeLeaf {
+impl<C: std::fmt::Debug> std::fmt::Display for hopeLeaf<C> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
@@ -351,7 +448,7 @@ impl std::fmt::Display for hopeLeaf {
     }
 }
 
-impl std::fmt::Display for hopeNode {
+impl<C: std::fmt::Debug> std::fmt::Display for hopeNode<C> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
@@ -1,6 +1,9 @@
 //! Request messages
 use serde::{Deserialize, Serialize};
 use serde_json::Result;
+use num_bigint::BigInt;
+use bn::G1;
+use super::model::PaillierEncryptionKey;
 
 #[derive(Serialize, Deserialize)]
 enum mt {
@@ -31,3 +34,32 @@ struct b {
     d: String, // data
     s: String, // data
 }
+
+/// A request of the server to the client holding the decryption key
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProtocolReq {
+    /// request id, repeated in the response
+    pub id: u64,
+    /// requested operation
+    pub op: ProtocolOp,
+}
+
+/// Operations the server delegates to the client. All operands are
+/// blinded by the server before they are sent.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum ProtocolOp {
+    /// product of the plaintexts of two additively blinded ciphertexts
+    Mul { a: BigInt, b: BigInt },
+    /// quotient and remainder of the non-negative plaintext of an
+    /// additively blinded ciphertext by the public divisor d > 0
+    DivConst { c: BigInt, d: BigInt },
+    /// sign of the plaintext of a blinded difference
+    Compare { c: BigInt },
+    /// re-encryption of additively blinded ciphertexts under the key ek
+    Reencrypt { ek: PaillierEncryptionKey, cs: Vec<BigInt> },
+    /// sign of the plaintext of a blinded difference, as EC-ElGamal
+    /// ciphertext (c1, c2)
+    CompareG1 { c1: G1, c2: G1 },
+    /// signs of the plaintexts of several blinded differences
+    CompareBatch { cs: Vec<BigInt> },
+}
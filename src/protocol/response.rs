@@ -0,0 +1,32 @@
+//! Response messages
+use serde::{Deserialize, Serialize};
+use num_bigint::BigInt;
+use bn::G1;
+
+/// A response of the client to a `ProtocolReq`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProtocolRes {
+    /// id of the answered request
+    pub id: u64,
+    /// outcome of the operation
+    pub result: ProtocolResult,
+}
+
+/// Outcome of a delegated operation
+#[derive(Serialize, Deserialize, Clone)]
+pub enum ProtocolResult {
+    /// fresh paillier encryption c of a value and its G1 token g = p * value
+    Value { c: BigInt, g: G1 },
+    /// fresh paillier encryptions q and r of the quotient and remainder of
+    /// a `DivConst` request, and the G1 token g of the quotient
+    DivMod { q: BigInt, g: G1, r: BigInt },
+    /// whether the plaintext of a `Compare` request is non-negative
+    Bit(bool),
+    /// whether the plaintexts of a `CompareBatch` request are non-negative,
+    /// in the same order
+    Bits(Vec<bool>),
+    /// ciphertexts of a `Reencrypt` request, in the same order
+    Ciphertexts(Vec<BigInt>),
+    /// the client refused or failed to compute the operation
+    Error(String),
+}